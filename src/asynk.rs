@@ -89,6 +89,8 @@
 //! ```
 
 use std::{
+    borrow::Cow,
+    collections::HashMap,
     fmt, io,
     net::IpAddr,
     path::Path,
@@ -103,6 +105,7 @@ use blocking::unblock;
 use crossbeam_channel::{Receiver, Sender};
 
 use crate::client::{self, Client};
+use crate::message::message_accessors;
 use crate::Headers;
 
 /// Connect to a NATS server at the given url.
@@ -183,6 +186,41 @@ impl Connection {
         Ok(msg.into())
     }
 
+    /// Sends a request and waits for a response like `request_timeout`,
+    /// failing with `Interrupted` once `cancel` is cancelled.
+    pub async fn request_with_cancel(
+        &self,
+        subject: &str,
+        msg: impl AsRef<[u8]>,
+        timeout: Duration,
+        cancel: crate::CancelToken,
+    ) -> io::Result<Message> {
+        let subject = subject.to_string();
+        let msg = msg.as_ref().to_vec();
+        let inner = self.inner.clone();
+        let msg =
+            unblock(move || inner.request_with_cancel(&subject, msg, timeout, &cancel)).await?;
+        Ok(msg.into())
+    }
+
+    /// Publishes a message with headers and waits for the response or until
+    /// the timeout duration is reached
+    pub async fn request_with_headers(
+        &self,
+        subject: &str,
+        headers: &Headers,
+        msg: impl AsRef<[u8]>,
+        timeout: Duration,
+    ) -> io::Result<Message> {
+        let subject = subject.to_string();
+        let headers = headers.clone();
+        let msg = msg.as_ref().to_vec();
+        let inner = self.inner.clone();
+        let msg =
+            unblock(move || inner.request_with_headers(&subject, &headers, msg, timeout)).await?;
+        Ok(msg.into())
+    }
+
     /// Publishes a message and returns a subscription for awaiting the
     /// response.
     pub async fn request_multi(
@@ -215,6 +253,54 @@ impl Connection {
         })
     }
 
+    /// Creates a new unique inbox and subscribes to it, returning both, for
+    /// receiving the responses to many requests through one subscription.
+    pub async fn responder_inbox(&self) -> io::Result<(String, Subscription)> {
+        let inner = self.inner.clone();
+        let (inbox, inner) = unblock(move || inner.responder_inbox()).await?;
+        let (_closer_tx, closer_rx) = crossbeam_channel::bounded(0);
+        Ok((
+            inbox,
+            Subscription {
+                inner,
+                _closer_tx,
+                closer_rx,
+            },
+        ))
+    }
+
+    /// Creates a subscription, optionally as part of a queue group, and
+    /// waits until the server has registered it.
+    ///
+    /// Fails with `ErrorKind::PermissionDenied` if the subscription is not
+    /// allowed for the credentials in use.
+    pub async fn subscribe_sync(
+        &self,
+        subject: &str,
+        queue: Option<&str>,
+    ) -> io::Result<Subscription> {
+        let subject = subject.to_string();
+        let queue = queue.map(str::to_owned);
+        let inner = self.inner.clone();
+        let inner = unblock(move || inner.subscribe_sync(&subject, queue.as_deref())).await?;
+        let (_closer_tx, closer_rx) = crossbeam_channel::bounded(0);
+        Ok(Subscription {
+            inner,
+            _closer_tx,
+            closer_rx,
+        })
+    }
+
+    /// Creates a subscription, optionally as part of a queue group, and waits
+    /// for the server to accept it. This is the same as `subscribe_sync`.
+    pub async fn subscribe_with_permissions_check(
+        &self,
+        subject: &str,
+        queue: Option<&str>,
+    ) -> io::Result<Subscription> {
+        self.subscribe_sync(subject, queue).await
+    }
+
     /// Creates a queue subscription.
     pub async fn queue_subscribe(&self, subject: &str, queue: &str) -> io::Result<Subscription> {
         let subject = subject.to_string();
@@ -242,6 +328,97 @@ impl Connection {
         unblock(move || inner.flush_timeout(timeout)).await
     }
 
+    /// Flushes by performing a round trip to the server, unless nothing is
+    /// waiting to be written.
+    pub async fn flush_if_pending(&self) -> io::Result<()> {
+        let inner = self.inner.clone();
+        unblock(move || inner.flush_if_pending()).await
+    }
+
+    /// Flushes by performing a round trip to the server and returns the
+    /// observed round trip time.
+    pub async fn flush_timed(&self, timeout: Duration) -> io::Result<Duration> {
+        let inner = self.inner.clone();
+        unblock(move || inner.flush_timed(timeout)).await
+    }
+
+    /// Returns how many outgoing bytes are waiting in the reconnect buffer
+    /// and in the write buffer of the current connection.
+    pub fn buffered_bytes(&self) -> crate::BufferedBytes {
+        self.inner.buffered_bytes()
+    }
+
+    /// Returns the limits on pending data the server enforces before
+    /// disconnecting this client as a slow consumer.
+    pub fn pending_limits(&self) -> crate::PendingLimits {
+        self.inner.pending_limits()
+    }
+
+    /// Estimates how close this client is to being disconnected as a slow
+    /// consumer, from 0.0 to 1.0.
+    pub fn slow_consumer_risk(&self) -> f32 {
+        self.inner.slow_consumer_risk()
+    }
+
+    /// Returns how many lines the server sent that were not a known
+    /// protocol operation.
+    pub fn unknown_op_count(&self) -> usize {
+        self.inner.unknown_op_count()
+    }
+
+    /// Returns how many complete messages are waiting in the reconnect
+    /// buffer.
+    pub fn buffered_publish_count(&self) -> usize {
+        self.inner.buffered_publish_count()
+    }
+
+    /// Replaces the callback invoked when an async error from the server has
+    /// been received.
+    pub fn set_error_callback<F>(&self, cb: F)
+    where
+        F: Fn(io::Error) + Send + Sync + 'static,
+    {
+        self.inner.set_error_callback(cb);
+    }
+
+    /// Nudges the background flusher to write buffered messages to the
+    /// server soon, without waiting.
+    pub fn kick_flush(&self) {
+        self.inner.kick_flush();
+    }
+
+    /// Checks that the connection is alive by sending a `PING` and waiting
+    /// for the responding `PONG`.
+    pub async fn ping(&self, timeout: Duration) -> io::Result<()> {
+        let inner = self.inner.clone();
+        unblock(move || inner.ping(timeout)).await
+    }
+
+    /// Returns `true` if the current connection is secured by TLS.
+    pub fn is_tls(&self) -> bool {
+        self.inner.is_tls()
+    }
+
+    /// Flushes buffered messages into the socket and waits until the kernel
+    /// has sent them, where the platform supports checking this.
+    pub async fn flush_and_drain_writer(&self, timeout: Duration) -> io::Result<()> {
+        let inner = self.inner.clone();
+        unblock(move || inner.flush_and_drain_writer(timeout)).await
+    }
+
+    /// Waits until the connection to a server is established or the timeout
+    /// elapses.
+    pub async fn wait_connected(&self, timeout: Duration) -> io::Result<()> {
+        let inner = self.inner.clone();
+        unblock(move || inner.wait_connected(timeout)).await
+    }
+
+    /// Returns the number of flushes that are waiting for a `PONG` from the
+    /// server.
+    pub fn pending_flushes(&self) -> usize {
+        self.inner.pending_flushes()
+    }
+
     /// Calculates the round trip time between this client and the server.
     pub async fn rtt(&self) -> io::Result<Duration> {
         let inner = self.inner.clone();
@@ -268,6 +445,22 @@ impl Connection {
         unblock(move || inner.drain()).await
     }
 
+    /// Flushes and closes the connection, returning a handle that can be used
+    /// to wait until its background threads have exited. The connection is
+    /// closed even if the flush fails.
+    pub async fn drain_and_close(&self) -> io::Result<crate::ShutdownHandle> {
+        let inner = self.inner.clone();
+        unblock(move || inner.drain_and_close()).await
+    }
+
+    /// Stops fetching `JetStream` batches, waits up to `timeout` for in-flight
+    /// acks to complete, then unsubscribes all subscriptions and closes the
+    /// connection.
+    pub async fn drain_jetstream(&self, timeout: Duration) -> io::Result<()> {
+        let inner = self.inner.clone();
+        unblock(move || inner.drain_jetstream(timeout)).await
+    }
+
     /// Closes the connection.
     pub async fn close(&self) -> io::Result<()> {
         let inner = self.inner.clone();
@@ -275,6 +468,152 @@ impl Connection {
         Ok(())
     }
 
+    /// Publish a message, returning the number of bytes it takes on the
+    /// wire, including the subject, headers and protocol overhead.
+    pub async fn publish_sized(
+        &self,
+        subject: &str,
+        reply: Option<&str>,
+        headers: Option<&Headers>,
+        msg: impl AsRef<[u8]>,
+    ) -> io::Result<usize> {
+        let subject = subject.to_string();
+        let reply = reply.map(str::to_owned);
+        let headers = headers.cloned();
+        let msg = msg.as_ref().to_vec();
+        let inner = self.inner.clone();
+        unblock(move || inner.publish_sized(&subject, reply.as_deref(), headers.as_ref(), msg))
+            .await
+    }
+
+    /// Publish a message and flush the connection, returning once the server
+    /// has received the message.
+    pub async fn publish_and_flush(
+        &self,
+        subject: &str,
+        reply: Option<&str>,
+        headers: Option<&Headers>,
+        msg: impl AsRef<[u8]>,
+        timeout: Duration,
+    ) -> io::Result<()> {
+        let subject = subject.to_string();
+        let reply = reply.map(str::to_owned);
+        let headers = headers.cloned();
+        let msg = msg.as_ref().to_vec();
+        let inner = self.inner.clone();
+        unblock(move || {
+            inner.publish_and_flush(&subject, reply.as_deref(), headers.as_ref(), msg, timeout)
+        })
+        .await
+    }
+
+    /// Publish a message and wait until the server has received it, retrying
+    /// once after a reconnect if the connection is lost meanwhile.
+    pub async fn publish_reliable(
+        &self,
+        subject: &str,
+        reply: Option<&str>,
+        headers: Option<&Headers>,
+        msg: impl AsRef<[u8]>,
+        timeout: Duration,
+    ) -> io::Result<()> {
+        let subject = subject.to_string();
+        let reply = reply.map(str::to_owned);
+        let headers = headers.cloned();
+        let msg = msg.as_ref().to_vec();
+        let inner = self.inner.clone();
+        unblock(move || {
+            inner.publish_reliable(&subject, reply.as_deref(), headers.as_ref(), msg, timeout)
+        })
+        .await
+    }
+
+    /// Publish a message, failing with `TimedOut` if the socket stalls for
+    /// longer than the timeout while the message is written.
+    pub async fn publish_with_timeout(
+        &self,
+        subject: &str,
+        reply: Option<&str>,
+        headers: Option<&Headers>,
+        msg: impl AsRef<[u8]>,
+        timeout: Duration,
+    ) -> io::Result<()> {
+        let subject = subject.to_string();
+        let reply = reply.map(str::to_owned);
+        let headers = headers.cloned();
+        let msg = msg.as_ref().to_vec();
+        let inner = self.inner.clone();
+        unblock(move || {
+            inner.publish_with_timeout(&subject, reply.as_deref(), headers.as_ref(), msg, timeout)
+        })
+        .await
+    }
+
+    /// Publishes a value serialized as JSON.
+    ///
+    /// Requires the `json` feature.
+    #[cfg(feature = "json")]
+    pub async fn publish_json<T: serde::Serialize + ?Sized>(
+        &self,
+        subject: &str,
+        value: &T,
+    ) -> io::Result<()> {
+        self.publish(subject, serde_json::to_vec(value)?).await
+    }
+
+    /// Compresses the data with the given algorithm and publishes it with a
+    /// `Content-Encoding` header announcing the algorithm.
+    ///
+    /// Requires the `compression` feature.
+    #[cfg(feature = "compression")]
+    pub async fn publish_compressed(
+        &self,
+        subject: &str,
+        data: impl AsRef<[u8]>,
+        algorithm: crate::Compression,
+    ) -> io::Result<()> {
+        let subject = subject.to_string();
+        let data = data.as_ref().to_vec();
+        let inner = self.inner.clone();
+        unblock(move || inner.publish_compressed(&subject, data, algorithm)).await
+    }
+
+    /// Publishes data that may exceed the server's maximum payload size as a
+    /// sequence of chunks, returning the number of published chunks.
+    pub async fn publish_chunked(
+        &self,
+        subject: &str,
+        data: impl AsRef<[u8]>,
+        chunk_size: usize,
+    ) -> io::Result<usize> {
+        let subject = subject.to_string();
+        let data = data.as_ref().to_vec();
+        let inner = self.inner.clone();
+        unblock(move || inner.publish_chunked(&subject, data, chunk_size)).await
+    }
+
+    /// Publish a message only if the client is currently connected.
+    ///
+    /// Returns `Ok(false)` without buffering the message if the client is
+    /// reconnecting.
+    pub async fn publish_if_connected(
+        &self,
+        subject: &str,
+        reply: Option<&str>,
+        headers: Option<&Headers>,
+        msg: impl AsRef<[u8]>,
+    ) -> io::Result<bool> {
+        let subject = subject.to_string();
+        let reply = reply.map(str::to_owned);
+        let headers = headers.cloned();
+        let msg = msg.as_ref().to_vec();
+        let inner = self.inner.clone();
+        unblock(move || {
+            inner.publish_if_connected(&subject, reply.as_deref(), headers.as_ref(), msg)
+        })
+        .await
+    }
+
     /// Publish a message which may have a reply subject or headers set.
     pub async fn publish_with_reply_or_headers(
         &self,
@@ -383,6 +722,11 @@ pub struct Message {
     /// Optional headers associated with this `Message`.
     pub headers: Option<Headers>,
 
+    /// The subject of the subscription this message was delivered to, which
+    /// tells which wildcard subscription matched `subject`. `None` if the
+    /// message was not received through a subscription.
+    pub subscription_subject: Option<String>,
+
     /// Client for publishing on the reply subject.
     #[doc(hidden)]
     pub client: Client,
@@ -391,6 +735,10 @@ pub struct Message {
     /// using `JetStream`.
     #[doc(hidden)]
     pub double_acked: Arc<AtomicBool>,
+
+    /// The place of this message in the ack window of its subscription,
+    /// kept for when it is converted back into a `client::Message`.
+    pub(crate) ack_slot: Option<Arc<client::AckSlot>>,
 }
 
 impl From<client::Message> for Message {
@@ -400,8 +748,10 @@ impl From<client::Message> for Message {
             reply: sync.reply,
             data: sync.data,
             headers: sync.headers,
+            subscription_subject: sync.subscription_subject,
             client: sync.client,
             double_acked: sync.double_acked,
+            ack_slot: sync.ack_slot,
         }
     }
 }
@@ -428,14 +778,28 @@ impl Message {
             reply: message.reply,
             data: message.data,
             headers: message.headers,
+            subscription_subject: message.subscription_subject,
             client,
             double_acked: Arc::new(AtomicBool::new(false)),
+            ack_slot: None,
         }
     }
 
+    message_accessors!();
+
+    /// Returns the largest payload `decompressed` may produce.
+    #[cfg(feature = "compression")]
+    fn max_decompressed_size(&self) -> usize {
+        self.client.max_decompressed_size()
+    }
+
     /// Respond to a request message.
+    ///
+    /// If the message has no reply subject, the `default_reply_subject` from
+    /// the connection options is used, if set.
     pub async fn respond(&self, msg: impl AsRef<[u8]>) -> io::Result<()> {
-        match self.reply.as_ref() {
+        let default_reply = self.client.options.default_reply_subject.as_ref();
+        match self.reply.as_ref().or(default_reply) {
             None => Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
                 "no reply subject available",
@@ -459,6 +823,7 @@ impl fmt::Debug for Message {
             .field("subject", &self.subject)
             .field("headers", &self.headers)
             .field("reply", &self.reply)
+            .field("subscription_subject", &self.subscription_subject)
             .field("length", &self.data.len())
             .field("double_ack", &self.double_acked)
             .finish()
@@ -614,6 +979,30 @@ impl Options {
         }
     }
 
+    /// Authenticate with a user JWT and a function that signs the server
+    /// nonce.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # smol::block_on(async {
+    /// # let seed = "SUANQDPB2RUOE4ETUA26CNX7FUKE5ZZKFCQIIW63OX225F2CO7UEXTM7ZY";
+    /// # let kp = nkeys::KeyPair::from_seed(seed).unwrap();
+    /// # let jwt = String::from("eyJ0eXAiOiJqd3QiLCJhbGciOiJlZDI1NTE5...");
+    /// let nc = nats::asynk::Options::new()
+    ///     .jwt(jwt, move |nonce| kp.sign(nonce).unwrap())
+    ///     .connect("connect.ngs.global")
+    ///     .await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn jwt<S>(self, jwt: String, sig_cb: S) -> Options
+    where
+        S: Fn(&[u8]) -> Vec<u8> + Send + Sync + 'static,
+    {
+        Options {
+            inner: self.inner.jwt(jwt, sig_cb),
+        }
+    }
+
     /// Set client certificate and private key files.
     ///
     /// # Example
@@ -631,6 +1020,26 @@ impl Options {
         }
     }
 
+    /// Set the client certificate and private key from memory, as a PEM
+    /// certificate chain and a PEM-encoded PKCS#8 private key.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # smol::block_on(async {
+    /// # let cert = std::fs::read("client-cert.pem")?;
+    /// # let key = std::fs::read("client-key.pem")?;
+    /// let nc = nats::asynk::Options::new()
+    ///     .client_cert_pem(cert, key)
+    ///     .connect("nats://localhost:4443")
+    ///     .await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn client_cert_pem(self, cert: Vec<u8>, key: Vec<u8>) -> Options {
+        Options {
+            inner: self.inner.client_cert_pem(cert, key),
+        }
+    }
+
     /// Add a name option to this configuration.
     ///
     /// # Example
@@ -648,6 +1057,28 @@ impl Options {
         }
     }
 
+    /// Attach key-value metadata to the connection, which is shown in server
+    /// monitoring.
+    ///
+    /// # Example
+    /// ```
+    /// # use std::collections::HashMap;
+    /// # smol::block_on(async {
+    /// let mut metadata = HashMap::new();
+    /// metadata.insert("region".to_string(), "eu-west-1".to_string());
+    ///
+    /// let nc = nats::asynk::Options::new()
+    ///     .connection_metadata(metadata)
+    ///     .connect("demo.nats.io")
+    ///     .await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn connection_metadata(self, metadata: HashMap<String, String>) -> Options {
+        Options {
+            inner: self.inner.connection_metadata(metadata),
+        }
+    }
+
     /// Select option to not deliver messages that we have published.
     ///
     /// # Example
@@ -708,33 +1139,358 @@ impl Options {
         }
     }
 
-    /// Establish a `Connection` with a NATS server.
-    ///
-    /// Multiple servers may be specified by separating
-    /// them with commas.
+    /// Mirror messages buffered while disconnected to an append-only file at
+    /// the given path, so that they survive a crash of the process or the
+    /// machine. Each message is synced to disk before the publish that
+    /// buffered it returns.
     ///
     /// # Example
-    ///
     /// ```
     /// # smol::block_on(async {
-    /// let options = nats::asynk::Options::new();
-    /// let nc = options.connect("demo.nats.io").await?;
+    /// # let path = std::env::temp_dir().join("nats-reconnect-buffer-async");
+    /// let nc = nats::asynk::Options::new()
+    ///     .reconnect_buffer_persist(&path)
+    ///     .connect("demo.nats.io")
+    ///     .await?;
+    /// # std::fs::remove_file(path)?;
     /// # std::io::Result::Ok(()) });
     /// ```
+    pub fn reconnect_buffer_persist<P: AsRef<Path>>(self, path: P) -> Options {
+        Options {
+            inner: self.inner.reconnect_buffer_persist(path),
+        }
+    }
+
+    /// Set what happens to messages published while disconnected once the
+    /// reconnect buffer is full.
     ///
-    /// In the below case, the second server is configured
-    /// to use TLS but the first one is not. Using the
-    /// `tls_required` method can ensure that all
-    /// servers are connected to with TLS, if that is
-    /// your intention.
-    ///
-    ///
+    /// # Example
     /// ```
     /// # smol::block_on(async {
-    /// let options = nats::asynk::Options::new();
-    /// let nc = options
-    ///     .connect("nats://demo.nats.io:4222,tls://demo.nats.io:4443")
-    ///     .await?;
+    /// let nc = nats::asynk::Options::new()
+    ///     .reconnect_buffer_policy(nats::BufferPolicy::DropOldest)
+    ///     .connect("demo.nats.io")
+    ///     .await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn reconnect_buffer_policy(self, policy: crate::BufferPolicy) -> Options {
+        Options {
+            inner: self.inner.reconnect_buffer_policy(policy),
+        }
+    }
+
+    /// Set the maximum size in bytes of incoming messages, including their
+    /// headers.
+    ///
+    /// Larger messages are skipped without being buffered in memory and the
+    /// `error_callback` is called with an error of kind
+    /// `ErrorKind::InvalidData`.
+    ///
+    /// # Example
+    /// ```
+    /// # smol::block_on(async {
+    /// let nc = nats::asynk::Options::new()
+    ///     .max_incoming_message_size(64 * 1024)
+    ///     .connect("demo.nats.io")
+    ///     .await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn max_incoming_message_size(self, max_incoming_message_size: usize) -> Options {
+        Options {
+            inner: self
+                .inner
+                .max_incoming_message_size(max_incoming_message_size),
+        }
+    }
+
+    /// Set how many replies can be queued on the inbox subscription of a
+    /// request before further replies are dropped.
+    ///
+    /// The default value is 64.
+    ///
+    /// # Example
+    /// ```
+    /// # smol::block_on(async {
+    /// let nc = nats::asynk::Options::new()
+    ///     .request_inbox_capacity(8)
+    ///     .connect("demo.nats.io")
+    ///     .await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn request_inbox_capacity(self, request_inbox_capacity: usize) -> Options {
+        Options {
+            inner: self.inner.request_inbox_capacity(request_inbox_capacity),
+        }
+    }
+
+    /// Set the estimated limits on pending bytes and messages that the
+    /// server enforces before disconnecting this client as a slow consumer.
+    ///
+    /// # Example
+    /// ```
+    /// # smol::block_on(async {
+    /// let nc = nats::asynk::Options::new()
+    ///     .pending_limits(32 * 1024 * 1024, 100_000)
+    ///     .connect("demo.nats.io")
+    ///     .await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn pending_limits(self, bytes: usize, messages: usize) -> Options {
+        Options {
+            inner: self.inner.pending_limits(bytes, messages),
+        }
+    }
+
+    /// Set a subject that `Message::respond` publishes to when the message
+    /// being responded to has no reply subject.
+    ///
+    /// # Example
+    /// ```
+    /// # smol::block_on(async {
+    /// let nc = nats::asynk::Options::new()
+    ///     .default_reply_subject("gateway.replies".to_string())
+    ///     .connect("demo.nats.io")
+    ///     .await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn default_reply_subject<T: Into<Option<String>>>(self, subject: T) -> Options {
+        Options {
+            inner: self.inner.default_reply_subject(subject),
+        }
+    }
+
+    /// Set whether the `.creds` file given to `Options::with_credentials` is
+    /// read again on every connection attempt.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # smol::block_on(async {
+    /// let nc = nats::asynk::Options::with_credentials("path/to/my.creds")
+    ///     .reload_credentials_on_reconnect(false)
+    ///     .connect("connect.ngs.global")
+    ///     .await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn reload_credentials_on_reconnect(self, reload: bool) -> Options {
+        Options {
+            inner: self.inner.reload_credentials_on_reconnect(reload),
+        }
+    }
+
+    /// Set whether to ask the server for no responders replies to requests
+    /// sent to subjects without subscribers.
+    ///
+    /// # Example
+    /// ```
+    /// # smol::block_on(async {
+    /// let nc = nats::asynk::Options::new()
+    ///     .no_responders(false)
+    ///     .connect("demo.nats.io")
+    ///     .await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn no_responders(self, no_responders: bool) -> Options {
+        Options {
+            inner: self.inner.no_responders(no_responders),
+        }
+    }
+
+    /// Return an error with `ErrorKind::NotConnected` from publishing methods
+    /// while the client is disconnected, instead of writing the messages into
+    /// the reconnect buffer.
+    ///
+    /// # Example
+    /// ```
+    /// # smol::block_on(async {
+    /// let nc = nats::asynk::Options::new()
+    ///     .fail_when_disconnected()
+    ///     .connect("demo.nats.io")
+    ///     .await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn fail_when_disconnected(self) -> Options {
+        Options {
+            inner: self.inner.fail_when_disconnected(),
+        }
+    }
+
+    /// Set whether `TCP_NODELAY` is enabled on the socket,
+    /// disabling Nagle's algorithm.
+    ///
+    /// The default value is `true`.
+    ///
+    /// # Example
+    /// ```
+    /// # smol::block_on(async {
+    /// let nc = nats::asynk::Options::new()
+    ///     .tcp_nodelay(false)
+    ///     .connect("demo.nats.io")
+    ///     .await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn tcp_nodelay(self, tcp_nodelay: bool) -> Options {
+        Options {
+            inner: self.inner.tcp_nodelay(tcp_nodelay),
+        }
+    }
+
+    /// Enable TCP keepalive on the socket, probing the connection after
+    /// it has been idle for the given duration.
+    ///
+    /// # Example
+    /// ```
+    /// # use std::time::Duration;
+    /// # smol::block_on(async {
+    /// let nc = nats::asynk::Options::new()
+    ///     .tcp_keepalive(Duration::from_secs(60))
+    ///     .connect("demo.nats.io")
+    ///     .await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn tcp_keepalive<T: Into<Option<Duration>>>(self, tcp_keepalive: T) -> Options {
+        Options {
+            inner: self.inner.tcp_keepalive(tcp_keepalive),
+        }
+    }
+
+    /// Stop the client from sending `PING`s while the connection is idle,
+    /// leaving liveness checks to the application.
+    ///
+    /// # Example
+    /// ```
+    /// # smol::block_on(async {
+    /// let nc = nats::asynk::Options::new()
+    ///     .disable_keepalive()
+    ///     .connect("demo.nats.io")
+    ///     .await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn disable_keepalive(self) -> Options {
+        Options {
+            inner: self.inner.disable_keepalive(),
+        }
+    }
+
+    /// Set how long establishing the initial connection may take before
+    /// `connect` fails with `ErrorKind::TimedOut`.
+    ///
+    /// # Example
+    /// ```
+    /// # use std::time::Duration;
+    /// # smol::block_on(async {
+    /// let nc = nats::asynk::Options::new()
+    ///     .connect_timeout(Duration::from_secs(5))
+    ///     .connect("demo.nats.io")
+    ///     .await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn connect_timeout<T: Into<Option<Duration>>>(self, connect_timeout: T) -> Options {
+        Options {
+            inner: self.inner.connect_timeout(connect_timeout),
+        }
+    }
+
+    /// Set the capacity in bytes of the buffer used for reading from the
+    /// server.
+    ///
+    /// # Example
+    /// ```
+    /// # smol::block_on(async {
+    /// let nc = nats::asynk::Options::new()
+    ///     .read_buffer_capacity(256 * 1024)
+    ///     .connect("demo.nats.io")
+    ///     .await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn read_buffer_capacity(self, capacity: usize) -> Options {
+        Options {
+            inner: self.inner.read_buffer_capacity(capacity),
+        }
+    }
+
+    /// Set the capacity in bytes of the buffer used for writing to the
+    /// server.
+    ///
+    /// # Example
+    /// ```
+    /// # smol::block_on(async {
+    /// let nc = nats::asynk::Options::new()
+    ///     .write_buffer_capacity(4 * 1024)
+    ///     .connect("demo.nats.io")
+    ///     .await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn write_buffer_capacity(self, capacity: usize) -> Options {
+        Options {
+            inner: self.inner.write_buffer_capacity(capacity),
+        }
+    }
+
+    /// Set how long the final flush of buffered messages may block when the
+    /// client shuts down.
+    ///
+    /// # Example
+    /// ```
+    /// # use std::time::Duration;
+    /// # smol::block_on(async {
+    /// let nc = nats::asynk::Options::new()
+    ///     .shutdown_flush_timeout(Duration::from_secs(1))
+    ///     .connect("demo.nats.io")
+    ///     .await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn shutdown_flush_timeout(self, shutdown_flush_timeout: Duration) -> Options {
+        Options {
+            inner: self.inner.shutdown_flush_timeout(shutdown_flush_timeout),
+        }
+    }
+
+    /// Close the client once it has gone unused, without subscriptions or
+    /// publishes, for the given duration.
+    ///
+    /// # Example
+    /// ```
+    /// # smol::block_on(async {
+    /// # use std::time::Duration;
+    /// let nc = nats::asynk::Options::new()
+    ///     .auto_close_idle(Duration::from_secs(5 * 60))
+    ///     .connect("demo.nats.io")
+    ///     .await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn auto_close_idle(self, idle: Duration) -> Options {
+        Options {
+            inner: self.inner.auto_close_idle(idle),
+        }
+    }
+
+    /// Establish a `Connection` with a NATS server.
+    ///
+    /// Multiple servers may be specified by separating
+    /// them with commas.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # smol::block_on(async {
+    /// let options = nats::asynk::Options::new();
+    /// let nc = options.connect("demo.nats.io").await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    ///
+    /// In the below case, the second server is configured
+    /// to use TLS but the first one is not. Using the
+    /// `tls_required` method can ensure that all
+    /// servers are connected to with TLS, if that is
+    /// your intention.
+    ///
+    ///
+    /// ```
+    /// # smol::block_on(async {
+    /// let options = nats::asynk::Options::new();
+    /// let nc = options
+    ///     .connect("nats://demo.nats.io:4222,tls://demo.nats.io:4443")
+    ///     .await?;
     /// # std::io::Result::Ok(()) });
     /// ```
     pub async fn connect(self, nats_url: &str) -> io::Result<Connection> {
@@ -743,6 +1499,21 @@ impl Options {
         Ok(Connection::new(conn))
     }
 
+    /// Establishes a `Connection` in the background without waiting for the
+    /// handshake with the server to complete.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # smol::block_on(async {
+    /// let nc = nats::asynk::Options::new().connect_lazy("demo.nats.io")?;
+    /// nc.publish("foo", "sent once connected").await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn connect_lazy(self, nats_url: &str) -> io::Result<Connection> {
+        self.inner.connect_lazy(nats_url).map(Connection::new)
+    }
+
     /// Set a callback to be executed when connectivity to
     /// a server has been lost.
     ///
@@ -765,6 +1536,31 @@ impl Options {
         }
     }
 
+    /// Set a callback to be executed once, when the first connection to a
+    /// server has been established.
+    ///
+    /// The callback runs on a thread of its own while the connection is
+    /// already reading from the server, so it can use the connection.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # smol::block_on(async {
+    /// let nc = nats::asynk::Options::new()
+    ///     .connect_callback(|| println!("connection has been established"))
+    ///     .connect("demo.nats.io")
+    ///     .await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn connect_callback<F>(self, cb: F) -> Self
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        Options {
+            inner: self.inner.connect_callback(cb),
+        }
+    }
+
     /// Set a callback to be executed when connectivity to a
     /// server has been reestablished.
     ///
@@ -787,6 +1583,28 @@ impl Options {
         }
     }
 
+    /// Set a callback to be executed each time an attempt to connect to a
+    /// server fails, with the URL of the server and the error.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # smol::block_on(async {
+    /// let nc = nats::asynk::Options::new()
+    ///     .reconnect_attempt_callback(|url, err| println!("failed to connect to {}: {}", url, err))
+    ///     .connect("demo.nats.io")
+    ///     .await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn reconnect_attempt_callback<F>(self, cb: F) -> Self
+    where
+        F: Fn(&str, &io::Error) + Send + Sync + 'static,
+    {
+        Options {
+            inner: self.inner.reconnect_attempt_callback(cb),
+        }
+    }
+
     /// Set a callback to be executed when the client has been
     /// closed due to exhausting reconnect retries to known servers
     /// or by completing a drain request.
@@ -810,6 +1628,52 @@ impl Options {
         }
     }
 
+    /// Set a callback to be executed with the number of messages from the
+    /// reconnect buffer that were sent once a connection has been
+    /// established.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # smol::block_on(async {
+    /// let nc = nats::asynk::Options::new()
+    ///     .replayed_callback(|count| println!("sent {} buffered messages", count))
+    ///     .connect("demo.nats.io")
+    ///     .await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn replayed_callback<F>(self, cb: F) -> Self
+    where
+        F: Fn(usize) + Send + Sync + 'static,
+    {
+        Options {
+            inner: self.inner.replayed_callback(cb),
+        }
+    }
+
+    /// Set a callback to be executed with the number of messages that were
+    /// evicted from the reconnect buffer while disconnected, once a
+    /// connection has been established.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # smol::block_on(async {
+    /// let nc = nats::asynk::Options::new()
+    ///     .dropped_callback(|count| println!("lost {} buffered messages", count))
+    ///     .connect("demo.nats.io")
+    ///     .await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn dropped_callback<F>(self, cb: F) -> Self
+    where
+        F: Fn(usize) + Send + Sync + 'static,
+    {
+        Options {
+            inner: self.inner.dropped_callback(cb),
+        }
+    }
+
     /// Set a callback to be executed for calculating the backoff duration
     /// to wait before a server reconnection attempt.
     ///
@@ -840,6 +1704,101 @@ impl Options {
         }
     }
 
+    /// Set a function that rewrites the subject of every published message,
+    /// including responses and acknowledgements.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use std::borrow::Cow;
+    /// # smol::block_on(async {
+    /// let nc = nats::asynk::Options::new()
+    ///     .subject_transform(|subject| match subject.strip_prefix("orders.v1.") {
+    ///         Some(rest) => Cow::Owned(format!("orders.v2.{}", rest)),
+    ///         None => Cow::Borrowed(subject),
+    ///     })
+    ///     .connect("demo.nats.io")
+    ///     .await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn subject_transform<F>(self, transform: F) -> Self
+    where
+        F: Fn(&str) -> Cow<'_, str> + Send + Sync + 'static,
+    {
+        Options {
+            inner: self.inner.subject_transform(transform),
+        }
+    }
+
+    /// Set a callback to be executed for every protocol operation the client
+    /// writes to the server, such as `PUB`, `SUB`, `UNSUB`, `PING` and
+    /// `PONG`, including the `CONNECT` and `PING` of each handshake.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # smol::block_on(async {
+    /// let nc = nats::asynk::Options::new()
+    ///     .outgoing_op_callback(|op| println!("sending {:?}", op))
+    ///     .connect("demo.nats.io")
+    ///     .await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn outgoing_op_callback<F>(self, cb: F) -> Self
+    where
+        F: Fn(&crate::ClientOp<'_>) + Send + Sync + 'static,
+    {
+        Options {
+            inner: self.inner.outgoing_op_callback(cb),
+        }
+    }
+
+    /// Set a callback to be executed with each message that is dropped
+    /// because the channel of its subscription is full.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # smol::block_on(async {
+    /// let nc = nats::asynk::Options::new()
+    ///     .dead_letter_callback(|msg| println!("dropped message on {}", msg.subject))
+    ///     .connect("demo.nats.io")
+    ///     .await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn dead_letter_callback<F>(self, cb: F) -> Self
+    where
+        F: Fn(&Message) + Send + Sync + 'static,
+    {
+        Options {
+            inner: self
+                .inner
+                .dead_letter_callback(move |msg| cb(&msg.clone().into())),
+        }
+    }
+
+    /// Set a callback to be executed with each line received from the
+    /// server that is not a known protocol operation.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # smol::block_on(async {
+    /// let nc = nats::asynk::Options::new()
+    ///     .unknown_op_callback(|line| eprintln!("unknown op from server: {}", line))
+    ///     .connect("demo.nats.io")
+    ///     .await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn unknown_op_callback<F>(self, cb: F) -> Self
+    where
+        F: Fn(&str) + Send + Sync + 'static,
+    {
+        Options {
+            inner: self.inner.unknown_op_callback(cb),
+        }
+    }
+
     /// Setting this requires that TLS be set for all server connections.
     ///
     /// If you only want to use TLS for some server connections, you may
@@ -0,0 +1,270 @@
+// Copyright 2020-2021 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::io::{self, Error, ErrorKind};
+use std::mem;
+use std::time::{Duration, Instant};
+
+use crate::client::{self, Client};
+use crate::headers::{NATS_CHUNK_ID, NATS_CHUNK_INDEX, NATS_CHUNK_TOTAL};
+use crate::{Headers, Subscription};
+
+/// Returns the headers tagging a chunk of a chunked transfer.
+fn chunk_headers(id: &str, index: usize, total: usize) -> Headers {
+    vec![
+        (NATS_CHUNK_ID.to_string(), id.to_string()),
+        (NATS_CHUNK_INDEX.to_string(), index.to_string()),
+        (NATS_CHUNK_TOTAL.to_string(), total.to_string()),
+    ]
+    .into_iter()
+    .collect()
+}
+
+/// Publishes the data as a sequence of chunks of at most `chunk_size` bytes,
+/// capped so that every chunk including its headers fits into the server's
+/// `max_payload`. Returns the number of published chunks.
+pub(crate) fn publish(
+    client: &Client,
+    subject: &str,
+    data: &[u8],
+    chunk_size: usize,
+) -> io::Result<usize> {
+    if chunk_size == 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "chunk size must be greater than zero",
+        ));
+    }
+
+    // Budget for the largest possible headers, which belong to the last
+    // chunk of a transfer with the smallest possible chunks.
+    let id = nuid::next();
+    let max_payload = client.server_info().max_payload;
+    let overhead = chunk_headers(&id, data.len(), data.len()).to_bytes().len();
+    let chunk_size = if max_payload > 0 {
+        chunk_size.min(max_payload.saturating_sub(overhead))
+    } else {
+        chunk_size
+    };
+    if chunk_size == 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "chunk headers do not fit into the server's max_payload",
+        ));
+    }
+
+    // Even empty data is sent as a single chunk.
+    let mut chunks = data.chunks(chunk_size);
+    let total = chunks.len().max(1);
+    for index in 0..total {
+        let headers = chunk_headers(&id, index, total);
+        let chunk = chunks.next().unwrap_or_default();
+        client.publish(subject, None, Some(&headers), chunk)?;
+    }
+    Ok(total)
+}
+
+/// The number of incomplete transfers a `Reassembler` holds by default.
+const DEFAULT_MAX_PENDING: usize = 64;
+
+/// Chunks received so far for a single transfer.
+#[derive(Debug)]
+struct Transfer {
+    total: usize,
+    chunks: HashMap<usize, Vec<u8>>,
+
+    /// The number of transfers started before this one.
+    seq: u64,
+}
+
+/// Reassembles transfers published with `Connection::publish_chunked` from
+/// the messages of a subscription.
+///
+/// Chunks of concurrent transfers may be interleaved and may arrive in any
+/// order. Messages that are not part of a chunked transfer are passed
+/// through unchanged.
+///
+/// At most 64 incomplete transfers are held by default, see `max_pending`.
+/// Once the limit is reached, the transfer that started first is discarded
+/// to make room for a new one, so that transfers whose remaining chunks
+/// never arrive cannot use up memory indefinitely.
+///
+/// # Example
+/// ```
+/// # fn main() -> std::io::Result<()> {
+/// # let nc = nats::connect("demo.nats.io")?;
+/// let mut blobs = nc.subscribe("blobs")?.reassemble();
+/// nc.publish_chunked("blobs", vec![0; 4 * 1024 * 1024], 512 * 1024)?;
+/// let blob = blobs.next().unwrap()?;
+/// assert_eq!(blob.data.len(), 4 * 1024 * 1024);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Reassembler {
+    sub: Subscription,
+    transfers: HashMap<String, Transfer>,
+    max_pending: usize,
+    started: u64,
+}
+
+impl Reassembler {
+    pub(crate) fn new(sub: Subscription) -> Reassembler {
+        Reassembler {
+            sub,
+            transfers: HashMap::new(),
+            max_pending: DEFAULT_MAX_PENDING,
+            started: 0,
+        }
+    }
+
+    /// Sets the maximum number of incomplete transfers to hold. When a chunk
+    /// of a new transfer arrives at the limit, the transfer that started
+    /// first is discarded. A limit of zero is treated as one.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// let blobs = nc.subscribe("blobs")?.reassemble().max_pending(8);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn max_pending(mut self, max_pending: usize) -> Reassembler {
+        self.max_pending = max_pending.max(1);
+        self
+    }
+
+    /// Returns the number of transfers that have not been completely
+    /// received yet.
+    pub fn pending(&self) -> usize {
+        self.transfers.len()
+    }
+
+    /// Waits up to `timeout` for the next complete message, failing with
+    /// `TimedOut` if none has been reassembled in time.
+    pub fn next_timeout(&mut self, timeout: Duration) -> io::Result<client::Message> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let msg = self.sub.next_timeout(remaining)?;
+            if let Some(msg) = self.add(msg)? {
+                return Ok(msg);
+            }
+        }
+    }
+
+    /// Adds a received message, returning it once its transfer is complete.
+    fn add(&mut self, mut msg: client::Message) -> io::Result<Option<client::Message>> {
+        let (id, index, total) = match msg.headers.as_ref().and_then(chunk_info) {
+            Some(info) => info?,
+            None => return Ok(Some(msg)),
+        };
+
+        if !self.transfers.contains_key(&id) {
+            if self.transfers.len() >= self.max_pending {
+                self.discard_oldest();
+            }
+            self.transfers.insert(
+                id.clone(),
+                Transfer {
+                    total,
+                    chunks: HashMap::new(),
+                    seq: self.started,
+                },
+            );
+            self.started += 1;
+        }
+        let transfer = self.transfers.get_mut(&id).unwrap();
+        if transfer.total != total || index >= total {
+            self.transfers.remove(&id);
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "inconsistent chunk {} of {} in transfer {}",
+                    index, total, id
+                ),
+            ));
+        }
+        transfer.chunks.insert(index, mem::take(&mut msg.data));
+        if transfer.chunks.len() < total {
+            return Ok(None);
+        }
+
+        let mut chunks = self.transfers.remove(&id).unwrap().chunks;
+        for i in 0..total {
+            msg.data.extend(chunks.remove(&i).unwrap());
+        }
+        if let Some(headers) = msg.headers.as_mut() {
+            headers.inner.remove(NATS_CHUNK_ID);
+            headers.inner.remove(NATS_CHUNK_INDEX);
+            headers.inner.remove(NATS_CHUNK_TOTAL);
+            if headers.is_empty() {
+                msg.headers = None;
+            }
+        }
+        Ok(Some(msg))
+    }
+
+    /// Discards the incomplete transfer that started first.
+    fn discard_oldest(&mut self) {
+        let oldest = self
+            .transfers
+            .iter()
+            .min_by_key(|(_, transfer)| transfer.seq)
+            .map(|(id, _)| id.clone());
+        if let Some(id) = oldest {
+            log::warn!("discarding incomplete chunked transfer {}", id);
+            self.transfers.remove(&id);
+        }
+    }
+}
+
+impl Iterator for Reassembler {
+    type Item = io::Result<client::Message>;
+
+    /// Blocks until the next complete message is available. Yields an
+    /// `InvalidData` error for chunks with inconsistent headers, discarding
+    /// their transfer, and ends when the subscription is closed.
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let msg = self.sub.next()?;
+            match self.add(msg) {
+                Ok(Some(msg)) => return Some(Ok(msg)),
+                Ok(None) => {}
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+/// Parses the transfer id, chunk index and chunk count from the headers of a
+/// chunk, or returns `None` if the message is not a chunk.
+fn chunk_info(headers: &Headers) -> Option<io::Result<(String, usize, usize)>> {
+    let value = |name: &str| headers.get(name).and_then(|values| values.iter().next());
+    let id = value(NATS_CHUNK_ID)?;
+    let parse = |name: &str| {
+        value(name)
+            .and_then(|value| value.parse::<usize>().ok())
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("missing or invalid {} header in transfer {}", name, id),
+                )
+            })
+    };
+    Some(
+        parse(NATS_CHUNK_INDEX).and_then(|index| Ok((id.clone(), index, parse(NATS_CHUNK_TOTAL)?))),
+    )
+}
@@ -14,11 +14,13 @@
 use std::{
     collections::{HashMap, VecDeque},
     fmt,
+    fs::{File, OpenOptions},
     io::{self, prelude::*, BufReader, BufWriter, Error, ErrorKind},
     mem,
+    path::Path,
     sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Weak,
     },
     thread,
     time::{Duration, Instant},
@@ -26,13 +28,26 @@ use std::{
 
 use crossbeam_channel as channel;
 use crossbeam_channel::RecvTimeoutError;
-use parking_lot::Mutex;
+use parking_lot::{Condvar, Mutex};
 
 use crate::connector::{Connector, NatsStream};
+use crate::message::message_accessors;
+use crate::options::ErrorCallback;
 use crate::proto::{self, ClientOp, ServerOp};
-use crate::{inject_delay, inject_io_failure, Headers, Options, ServerInfo};
+use crate::streaming::{self, StreamingMessage};
+use crate::{
+    inject_delay, inject_io_failure, inject_write_failure, BufferPolicy, Headers, Options,
+    PendingLimits, ServerFeature, ServerInfo,
+};
+
+/// Sent by the server before closing a connection that missed too many PONGs.
+const STALE_CONNECTION: &str = "Stale Connection";
 
-const BUF_CAPACITY: usize = 32 * 1024;
+/// Prefix of the error sent by the server when a subscription is not allowed.
+const SUBSCRIPTION_VIOLATION: &str = "Permissions Violation for Subscription to \"";
+
+/// Prefix of the reply subjects of messages delivered by `JetStream`.
+const JETSTREAM_ACK_PREFIX: &str = "$JS.ACK.";
 
 /// Client state.
 ///
@@ -43,6 +58,11 @@ const BUF_CAPACITY: usize = 32 * 1024;
 struct State {
     write: Mutex<WriteState>,
     read: Mutex<ReadState>,
+
+    /// Notified when a connection is established or the client is closed.
+    ///
+    /// Used together with `write`.
+    connected: Condvar,
 }
 
 struct WriteState {
@@ -62,28 +82,437 @@ struct WriteState {
     buffer: Buffer,
 
     /// Next subscription ID.
+    ///
+    /// This is never reset, including across reconnects, so that a sid is
+    /// never reused during the lifetime of the client.
     next_sid: u64,
+
+    /// Number of connections established so far.
+    ///
+    /// Pending PONGs are completed when reconnecting, so this tells whether
+    /// a round trip was answered by the connection it started on.
+    connections: u64,
+
+    /// When the client was last used to publish, subscribe or unsubscribe,
+    /// for closing it once idle.
+    last_used: Instant,
 }
 
 struct ReadState {
     /// Current subscriptions.
     subscriptions: HashMap<u64, Subscription>,
 
-    /// Expected pongs and their notification channels.
-    pongs: VecDeque<channel::Sender<()>>,
+    /// Expected pongs and their notifiers.
+    pongs: VecDeque<Pong>,
 
     /// Tracks the last activity from the server.
     last_active: Instant,
 
     /// Used for client side monitoring of connection health.
     pings_out: u8,
+
+    /// Subjects with a pending permissions check, and the flags set when the
+    /// server denies a subscription to them.
+    permission_checks: Vec<(String, Arc<AtomicBool>)>,
 }
 
+/// A predicate deciding which messages are delivered to a subscription.
+type Filter = Box<dyn Fn(&Message) -> bool + Send + Sync>;
+
 /// A registered subscription.
 struct Subscription {
     subject: String,
     queue_group: Option<String>,
     messages: channel::Sender<Message>,
+
+    /// Payload sizes of the messages sent to `messages`, newest last, of
+    /// which the last `messages.len()` are still queued.
+    queued_sizes: VecDeque<usize>,
+
+    /// Messages not matching this predicate are dropped before delivery.
+    filter: Option<Filter>,
+
+    /// Limits the number of delivered messages that have not been acked.
+    ack_window: Option<AckWindow>,
+
+    /// Receives the messages of a streaming subscription instead of
+    /// `messages`.
+    streaming: Option<channel::Sender<StreamingMessage>>,
+}
+
+impl Subscription {
+    /// Returns `true` if the message should be delivered to this
+    /// subscription.
+    fn accepts(&self, msg: &Message) -> bool {
+        match &self.filter {
+            Some(filter) => filter(msg),
+            None => true,
+        }
+    }
+
+    /// Delivers a message to the subscription, or holds it back while the
+    /// ack window is full. Drops the message if the channel is
+    /// disconnected, and returns it if the channel is full.
+    fn deliver(&mut self, sid: u64, mut msg: Message) -> Option<Message> {
+        if let Some(window) = self.ack_window.as_mut() {
+            msg.ack_slot = Some(Arc::new(AckSlot {
+                sid,
+                released: AtomicBool::new(false),
+            }));
+            if window.in_flight >= window.max_pending {
+                window.held.push_back(msg);
+                return None;
+            }
+            window.in_flight += 1;
+        }
+        let mut overflow = self.send(msg);
+        if let Some(undelivered) = overflow.as_mut() {
+            // A message that is not delivered is never acked, so it gives its
+            // place in the ack window back right away.
+            if undelivered.ack_slot.take().is_some() {
+                if let Some(window) = self.ack_window.as_mut() {
+                    window.in_flight -= 1;
+                }
+            }
+        }
+        overflow
+    }
+
+    /// Queues a message in the channel, returning it if the channel is full.
+    fn send(&mut self, msg: Message) -> Option<Message> {
+        let size = msg.data.len();
+        match self.messages.try_send(msg) {
+            Err(channel::TrySendError::Full(msg)) => Some(msg),
+            Err(channel::TrySendError::Disconnected(_)) => None,
+            Ok(()) => {
+                self.queued_sizes.push_back(size);
+                // Forget the sizes of the messages received since.
+                while self.queued_sizes.len() > self.messages.len() {
+                    self.queued_sizes.pop_front();
+                }
+                None
+            }
+        }
+    }
+
+    /// Returns the number of messages and payload bytes that are queued in
+    /// the channel or held back by the ack window.
+    fn backlog(&self) -> (usize, usize) {
+        let queued = self.messages.len();
+        let mut bytes: usize = self.queued_sizes.iter().rev().take(queued).sum();
+        let mut messages = queued;
+        if let Some(window) = self.ack_window.as_ref() {
+            messages += window.held.len();
+            bytes += window.held.iter().map(|msg| msg.data.len()).sum::<usize>();
+        }
+        (messages, bytes)
+    }
+}
+
+/// Returns `true` if the error means that the connection was lost.
+fn is_disconnect(err: &Error) -> bool {
+    matches!(
+        err.kind(),
+        ErrorKind::NotConnected
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::BrokenPipe
+            | ErrorKind::UnexpectedEof
+    )
+}
+
+/// Client-side flow control for a subscription created with
+/// `subscribe_with_ack_window`.
+struct AckWindow {
+    /// Maximum number of delivered messages that have not been acked.
+    max_pending: usize,
+
+    /// Number of delivered messages that have not been acked.
+    in_flight: usize,
+
+    /// Messages held back until acks catch up, oldest first.
+    held: VecDeque<Message>,
+}
+
+/// Releases a message's place in the ack window of its subscription once
+/// the message has been acked.
+#[derive(Debug)]
+pub(crate) struct AckSlot {
+    sid: u64,
+    released: AtomicBool,
+}
+
+/// Tracks `JetStream` acks that are still in flight so that draining can
+/// wait for them before tearing down subscriptions.
+#[derive(Default)]
+struct AckTracker {
+    /// Set once `drain_jetstream` has been called.
+    draining: AtomicBool,
+
+    /// Number of acks that have not completed yet.
+    in_flight: Mutex<usize>,
+
+    /// Notified when `in_flight` drops to zero.
+    idle: Condvar,
+}
+
+/// Marks an ack as in flight until dropped.
+pub(crate) struct AckGuard<'a>(&'a AckTracker);
+
+impl Drop for AckGuard<'_> {
+    fn drop(&mut self) {
+        let mut in_flight = self.0.in_flight.lock();
+        *in_flight -= 1;
+        if *in_flight == 0 {
+            self.0.idle.notify_all();
+        }
+    }
+}
+
+/// Tracks the background threads of a client so that shutdown can wait for
+/// them to exit.
+#[derive(Default)]
+struct ThreadTracker {
+    /// Number of background threads that are still running.
+    running: Mutex<usize>,
+
+    /// Notified when `running` drops to zero.
+    exited: Condvar,
+}
+
+/// Marks a background thread as running until dropped.
+struct ThreadGuard(Arc<ThreadTracker>);
+
+impl ThreadGuard {
+    fn new(threads: &Arc<ThreadTracker>) -> ThreadGuard {
+        *threads.running.lock() += 1;
+        ThreadGuard(threads.clone())
+    }
+}
+
+impl Drop for ThreadGuard {
+    fn drop(&mut self) {
+        let mut running = self.0.running.lock();
+        *running -= 1;
+        if *running == 0 {
+            self.0.exited.notify_all();
+        }
+    }
+}
+
+/// A handle for waiting until a connection closed with
+/// `Connection::drain_and_close` has fully shut down.
+pub struct ShutdownHandle {
+    threads: Arc<ThreadTracker>,
+}
+
+impl fmt::Debug for ShutdownHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        f.debug_struct("ShutdownHandle")
+            .field("running", &*self.threads.running.lock())
+            .finish()
+    }
+}
+
+impl ShutdownHandle {
+    /// Blocks until the background threads of the connection have exited,
+    /// which includes the final flush of buffered messages and the close
+    /// callback. Fails with `TimedOut` if they are still running after the
+    /// timeout.
+    pub fn join(&self, timeout: Duration) -> io::Result<()> {
+        let deadline = Instant::now() + timeout;
+        let mut running = self.threads.running.lock();
+        while *running > 0 {
+            if self
+                .threads
+                .exited
+                .wait_until(&mut running, deadline)
+                .timed_out()
+            {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    "timed out waiting for the connection to shut down",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The state of an expected PONG.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PongState {
+    /// The PONG has not arrived yet.
+    Pending,
+
+    /// The PONG has arrived.
+    Received,
+
+    /// The expected PONG was discarded because the connection was lost.
+    Dropped,
+}
+
+/// A notification slot for a PONG. Slots are kept in a freelist and reused
+/// across flushes so that a round trip does not allocate a new channel.
+struct PongSlot {
+    /// The generation of the current waiter and the state of its PONG.
+    state: Mutex<(u64, PongState)>,
+
+    /// Notified when the PONG of the current generation is finished.
+    finished: Condvar,
+}
+
+impl PongSlot {
+    /// Finishes the PONG of the given generation, unless the slot has been
+    /// reused for a newer one since.
+    fn finish(&self, generation: u64, outcome: PongState) {
+        let mut state = self.state.lock();
+        if *state == (generation, PongState::Pending) {
+            state.1 = outcome;
+            self.finished.notify_one();
+        }
+    }
+
+    /// Waits until the PONG of the current generation is finished or the
+    /// timeout elapses, returning its state.
+    fn wait(&self, timeout: Option<Duration>) -> PongState {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut state = self.state.lock();
+        while state.1 == PongState::Pending {
+            match deadline {
+                Some(deadline) => {
+                    if self.finished.wait_until(&mut state, deadline).timed_out() {
+                        break;
+                    }
+                }
+                None => self.finished.wait(&mut state),
+            }
+        }
+        state.1
+    }
+}
+
+/// An expected PONG, completed when the server answers a PING.
+enum Pong {
+    /// Wakes up a flush waiting on a pooled slot. Dropping it without
+    /// completing it marks the PONG as dropped.
+    Slot(Arc<PongSlot>, u64),
+
+    /// Sends a message on a channel.
+    Channel(channel::Sender<()>),
+}
+
+impl Pong {
+    /// Notifies the waiter that the PONG has arrived.
+    fn complete(&self) {
+        match self {
+            Pong::Slot(slot, generation) => slot.finish(*generation, PongState::Received),
+            Pong::Channel(sender) => {
+                sender.try_send(()).ok();
+            }
+        }
+    }
+}
+
+impl Drop for Pong {
+    fn drop(&mut self) {
+        if let Pong::Slot(slot, generation) = self {
+            slot.finish(*generation, PongState::Dropped);
+        }
+    }
+}
+
+/// Numbers of outgoing bytes that have not been sent to the server yet, as
+/// returned by `Connection::buffered_bytes`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BufferedBytes {
+    /// Bytes of messages published while disconnected, waiting in the
+    /// reconnect buffer for the connection to be reestablished.
+    pub reconnect: usize,
+
+    /// Bytes in the write buffer of the current connection, waiting to be
+    /// flushed into the socket.
+    pub writer: usize,
+}
+
+/// The number of server info updates held for a listener that has not
+/// received them yet.
+const INFO_CHANGES_CAPACITY: usize = 16;
+
+/// A listener for server info updates, as returned by
+/// `Connection::server_info_changes`.
+struct InfoListener {
+    sender: channel::Sender<ServerInfo>,
+
+    /// The receiving end, used to discard the oldest update when the channel
+    /// is full. It is gone once the listener has been dropped.
+    receiver: Weak<channel::Receiver<ServerInfo>>,
+}
+
+impl InfoListener {
+    /// Sends an update, discarding the oldest one if the channel is full.
+    /// Returns `false` if the listener has been dropped.
+    fn notify(&self, server_info: &ServerInfo) -> bool {
+        let receiver = match self.receiver.upgrade() {
+            Some(receiver) => receiver,
+            None => return false,
+        };
+        let mut update = server_info.clone();
+        while let Err(channel::TrySendError::Full(rejected)) = self.sender.try_send(update) {
+            receiver.try_recv().ok();
+            update = rejected;
+        }
+        true
+    }
+}
+
+/// Server info updates, received each time the server sends an `INFO` or the
+/// client reconnects, as returned by `Connection::server_info_changes`.
+///
+/// Only the most recent updates are held until they are received; older
+/// ones are discarded.
+pub struct ServerInfoChanges {
+    receiver: Arc<channel::Receiver<ServerInfo>>,
+}
+
+impl fmt::Debug for ServerInfoChanges {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        f.debug_struct("ServerInfoChanges")
+            .field("pending", &self.receiver.len())
+            .finish()
+    }
+}
+
+impl ServerInfoChanges {
+    /// Returns the next update if one is available.
+    pub fn try_next(&self) -> Option<ServerInfo> {
+        self.receiver.try_recv().ok()
+    }
+
+    /// Waits for the next update, failing with `TimedOut` if none arrives
+    /// before the timeout elapses.
+    pub fn next_timeout(&self, timeout: Duration) -> io::Result<ServerInfo> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(server_info) => Ok(server_info),
+            Err(RecvTimeoutError::Timeout) => {
+                Err(Error::new(ErrorKind::TimedOut, "next_timeout: timed out"))
+            }
+            Err(RecvTimeoutError::Disconnected) => Err(Error::new(
+                ErrorKind::Other,
+                "next_timeout: the connection was closed",
+            )),
+        }
+    }
+}
+
+impl Iterator for ServerInfoChanges {
+    type Item = ServerInfo;
+
+    /// Blocks until the next update, returning `None` once the connection
+    /// has been dropped.
+    fn next(&mut self) -> Option<ServerInfo> {
+        self.receiver.recv().ok()
+    }
 }
 
 /// A NATS client.
@@ -95,9 +524,36 @@ pub struct Client {
     /// Server info provided by the last INFO message.
     pub(crate) server_info: Arc<Mutex<ServerInfo>>,
 
+    /// Receive server info updated by INFO messages.
+    info_listeners: Arc<Mutex<Vec<InfoListener>>>,
+
     /// Set to `true` if shutdown has been requested.
     shutdown: Arc<Mutex<bool>>,
 
+    /// In-flight `JetStream` acks.
+    acks: Arc<AckTracker>,
+
+    /// Whether the most recently established connection uses TLS.
+    tls: Arc<AtomicBool>,
+
+    /// Unused PONG slots, reused by flushes.
+    pong_slots: Arc<Mutex<Vec<Arc<PongSlot>>>>,
+
+    /// The error callback set at runtime, replacing the one in the options.
+    error_callback: Arc<Mutex<Option<Arc<ErrorCallback>>>>,
+
+    /// Number of protocol lines received from the server that could not be
+    /// parsed as a known operation.
+    unknown_ops: Arc<AtomicUsize>,
+
+    /// Number of subscriptions that stream their payloads, so that looking
+    /// up the subscription of every message can be skipped while there are
+    /// none.
+    streaming_subscriptions: Arc<AtomicUsize>,
+
+    /// The background threads of this client.
+    threads: Arc<ThreadTracker>,
+
     /// The options that this `Client` was created using.
     pub(crate) options: Arc<Options>,
 }
@@ -105,6 +561,19 @@ pub struct Client {
 impl Client {
     /// Creates a new client that will begin connecting in the background.
     pub(crate) fn connect(url: &str, options: Options) -> io::Result<Client> {
+        Client::start(url, options, false)
+    }
+
+    /// Creates a new client like `connect`, but returns without waiting for
+    /// the handshake. If the client gives up connecting, the error is
+    /// reported to the error callback and the client is closed.
+    pub(crate) fn connect_lazy(url: &str, options: Options) -> io::Result<Client> {
+        Client::start(url, options, true)
+    }
+
+    /// Spawns the client thread and, unless `lazy`, waits for the handshake
+    /// with the first server.
+    fn start(url: &str, options: Options, lazy: bool) -> io::Result<Client> {
         // A channel for coordinating flushes.
         let (flush_kicker, flush_wanted) = channel::bounded(1);
 
@@ -118,18 +587,34 @@ impl Client {
                 write: Mutex::new(WriteState {
                     writer: None,
                     flush_kicker,
-                    buffer: Buffer::new(options.reconnect_buffer_size),
+                    buffer: Buffer::new(
+                        options.reconnect_buffer_size,
+                        options.reconnect_buffer_policy,
+                        options.reconnect_buffer_persist.as_deref(),
+                    )?,
                     next_sid: 1,
+                    connections: 0,
+                    last_used: Instant::now(),
                 }),
                 read: Mutex::new(ReadState {
                     subscriptions: HashMap::new(),
-                    pongs: VecDeque::from(vec![pong_sender]),
+                    pongs: VecDeque::from(vec![Pong::Channel(pong_sender)]),
                     last_active: Instant::now(),
                     pings_out: 0,
+                    permission_checks: Vec::new(),
                 }),
+                connected: Condvar::new(),
             }),
             server_info: Arc::new(Mutex::new(ServerInfo::default())),
+            info_listeners: Arc::new(Mutex::new(Vec::new())),
             shutdown: Arc::new(Mutex::new(false)),
+            acks: Arc::new(AckTracker::default()),
+            tls: Arc::new(AtomicBool::new(false)),
+            pong_slots: Arc::new(Mutex::new(Vec::new())),
+            error_callback: Arc::new(Mutex::new(None)),
+            unknown_ops: Arc::new(AtomicUsize::new(0)),
+            streaming_subscriptions: Arc::new(AtomicUsize::new(0)),
+            threads: Arc::new(ThreadTracker::default()),
             options: Arc::new(options),
         };
 
@@ -146,111 +631,199 @@ impl Client {
         // - Forwarding MSG operations to subscribers.
         thread::spawn({
             let client = client.clone();
+            let guard = ThreadGuard::new(&client.threads);
             move || {
+                let _guard = guard;
                 let res = client.run(connector);
-                run_sender.send(res).ok();
+                if lazy {
+                    if let Err(err) = res {
+                        client.report_error(err);
+                        client.close();
+                    }
+                } else {
+                    run_sender.send(res).ok();
+                }
 
                 // One final flush before shutting down.
                 // This way we make sure buffered published messages reach the
                 // server.
-                {
-                    let mut write = client.state.write.lock();
-                    if let Some(writer) = write.writer.as_mut() {
-                        writer.flush().ok();
-                    }
-                }
+                client.shutdown_flush(&mut client.state.write.lock());
 
                 options.close_callback.call();
             }
         });
 
+        if lazy {
+            client.spawn_flusher(flush_wanted);
+            return Ok(client);
+        }
+
+        let timeout = client
+            .options
+            .connect_timeout
+            .map_or_else(channel::never, channel::after);
         channel::select! {
             recv(run_receiver) -> res => {
                 res.expect("client thread has panicked")?;
                 unreachable!()
             }
             recv(pong_receiver) -> _ => {}
+            recv(timeout) -> _ => {
+                // Stop the client thread once its connection attempt ends.
+                client.close();
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    "timed out waiting for the server to complete the handshake",
+                ));
+            }
         }
 
         // Spawn a thread that periodically flushes buffered messages.
-        thread::spawn({
-            let client = client.clone();
-            move || {
-                // Track last flush/write time.
-                const MIN_FLUSH_BETWEEN: Duration = Duration::from_millis(5);
-
-                // Handle recv timeouts and check if we should send a PING.
-                // TODO(dlc) - Make configurable.
-                const PING_INTERVAL: Duration = Duration::from_secs(2 * 60);
-                const MAX_PINGS_OUT: u8 = 2;
-
-                let mut last = Instant::now() - MIN_FLUSH_BETWEEN;
-
-                // Wait until at least one message is buffered.
-                loop {
-                    match flush_wanted.recv_timeout(PING_INTERVAL) {
-                        Ok(_) => {
-                            let since = last.elapsed();
-                            if since < MIN_FLUSH_BETWEEN {
-                                thread::sleep(MIN_FLUSH_BETWEEN - since);
+        client.spawn_flusher(flush_wanted);
+
+        Ok(client)
+    }
+
+    /// Spawns the thread that flushes buffered messages when kicked through
+    /// `flush_kicker`, and sends PINGs while the connection is idle unless
+    /// keepalive is disabled.
+    fn spawn_flusher(&self, flush_wanted: channel::Receiver<()>) {
+        let client = self.clone();
+        let guard = ThreadGuard::new(&self.threads);
+        thread::spawn(move || {
+            // Track last flush/write time.
+            const MIN_FLUSH_BETWEEN: Duration = Duration::from_millis(5);
+
+            // Handle recv timeouts and check if we should send a PING.
+            // TODO(dlc) - Make configurable.
+            const PING_INTERVAL: Duration = Duration::from_secs(2 * 60);
+            const MAX_PINGS_OUT: u8 = 2;
+
+            let _guard = guard;
+            let mut last = Instant::now() - MIN_FLUSH_BETWEEN;
+
+            // Wakeups for closing idle clients may come more often than
+            // PINGs are due.
+            let mut last_ping = Instant::now();
+
+            // Wait until at least one message is buffered.
+            loop {
+                let mut timeout = if client.options.keepalive_pings {
+                    Some(PING_INTERVAL)
+                } else {
+                    None
+                };
+                if let Some(idle) = client.options.auto_close_idle {
+                    let remaining = client.idle_remaining(idle).max(MIN_FLUSH_BETWEEN);
+                    timeout = Some(timeout.map_or(remaining, |timeout| timeout.min(remaining)));
+                }
+                let kicked = match timeout {
+                    Some(timeout) => flush_wanted.recv_timeout(timeout),
+                    None => flush_wanted
+                        .recv()
+                        .map_err(|_| RecvTimeoutError::Disconnected),
+                };
+
+                // Exit once the client has been closed, which kicks the
+                // flusher after flushing the writer itself.
+                if *client.shutdown.lock() {
+                    break;
+                }
+
+                match kicked {
+                    Ok(_) => {
+                        let since = last.elapsed();
+                        if since < MIN_FLUSH_BETWEEN {
+                            thread::sleep(MIN_FLUSH_BETWEEN - since);
+                        }
+
+                        // Flush the writer.
+                        let mut write = client.state.write.lock();
+                        if let Some(writer) = write.writer.as_mut() {
+                            let res = writer.flush();
+                            last = Instant::now();
+                            // If flushing fails, disconnect.
+                            if res.is_err() {
+                                // NB see locking protocol for state.write and state.read
+                                writer.get_ref().shutdown();
+                                write.writer = None;
+                                let mut read = client.state.read.lock();
+                                read.pongs.clear();
+                            }
+                        }
+                        drop(write);
+                    }
+                    Err(RecvTimeoutError::Timeout) => {
+                        // Close the client once it has been unused for long
+                        // enough.
+                        if let Some(idle) = client.options.auto_close_idle {
+                            if client.idle_remaining(idle) == Duration::from_secs(0) {
+                                log::debug!("closing the client after being idle for {:?}", idle);
+                                client.close_and_disconnect();
+                                break;
                             }
+                        }
+                        if !client.options.keepalive_pings || last_ping.elapsed() < PING_INTERVAL {
+                            continue;
+                        }
 
-                            // Flush the writer.
-                            let mut write = client.state.write.lock();
+                        let mut write = client.state.write.lock();
+                        let mut read = client.state.read.lock();
+
+                        if read.pings_out >= MAX_PINGS_OUT {
                             if let Some(writer) = write.writer.as_mut() {
+                                writer.get_ref().shutdown();
+                            }
+                            write.writer = None;
+                            read.pongs.clear();
+                        } else if read.last_active.elapsed() > PING_INTERVAL {
+                            last_ping = Instant::now();
+                            read.pings_out += 1;
+                            read.pongs
+                                .push_back(Pong::Channel(write.flush_kicker.clone()));
+                            // Send out a PING here.
+                            if let Some(mut writer) = write.writer.as_mut() {
+                                // Ok to ignore errors here.
+                                client.encode(&mut writer, ClientOp::Ping).ok();
                                 let res = writer.flush();
-                                last = Instant::now();
-                                // If flushing fails, disconnect.
                                 if res.is_err() {
                                     // NB see locking protocol for state.write and state.read
                                     writer.get_ref().shutdown();
                                     write.writer = None;
-                                    let mut read = client.state.read.lock();
                                     read.pongs.clear();
                                 }
                             }
-                            drop(write);
                         }
-                        Err(RecvTimeoutError::Timeout) => {
-                            let mut write = client.state.write.lock();
-                            let mut read = client.state.read.lock();
-
-                            if read.pings_out >= MAX_PINGS_OUT {
-                                if let Some(writer) = write.writer.as_mut() {
-                                    writer.get_ref().shutdown();
-                                }
-                                write.writer = None;
-                                read.pongs.clear();
-                            } else if read.last_active.elapsed() > PING_INTERVAL {
-                                read.pings_out += 1;
-                                read.pongs.push_back(write.flush_kicker.clone());
-                                // Send out a PING here.
-                                if let Some(mut writer) = write.writer.as_mut() {
-                                    // Ok to ignore errors here.
-                                    proto::encode(&mut writer, ClientOp::Ping).ok();
-                                    let res = writer.flush();
-                                    if res.is_err() {
-                                        // NB see locking protocol for state.write and state.read
-                                        writer.get_ref().shutdown();
-                                        write.writer = None;
-                                        read.pongs.clear();
-                                    }
-                                }
-                            }
 
-                            drop(read);
-                            drop(write);
-                        }
-                        _ => {
-                            // Any other err break and exit.
-                            break;
-                        }
+                        drop(read);
+                        drop(write);
+                    }
+                    _ => {
+                        // Any other err break and exit.
+                        break;
                     }
                 }
             }
         });
+    }
 
-        Ok(client)
+    /// Returns how much longer the client has to go without publishes and
+    /// subscriptions to have been idle for `idle`, or zero if it has been.
+    fn idle_remaining(&self, idle: Duration) -> Duration {
+        let write = self.state.write.lock();
+        let read = self.state.read.lock();
+        let remaining = if read.subscriptions.is_empty() {
+            idle.checked_sub(write.last_used.elapsed())
+                .unwrap_or_default()
+        } else {
+            idle
+        };
+
+        // NB see locking protocol for state.write and state.read
+        drop(read);
+        drop(write);
+
+        remaining
     }
 
     /// Retrieves server info as received by the most recent connection.
@@ -258,9 +831,68 @@ impl Client {
         self.server_info.lock().clone()
     }
 
+    /// Returns `true` if no responders replies were requested from the
+    /// current server, so that an empty reply with a 503 status means that
+    /// the request had no responders.
+    pub(crate) fn no_responders_enabled(&self) -> bool {
+        self.options.no_responders
+            && self
+                .server_info
+                .lock()
+                .supports(ServerFeature::NoResponders)
+    }
+
+    /// Returns the server info each time it is updated by an INFO message
+    /// from the server or by a reconnect.
+    pub(crate) fn server_info_changes(&self) -> ServerInfoChanges {
+        let (sender, receiver) = channel::bounded(INFO_CHANGES_CAPACITY);
+        let receiver = Arc::new(receiver);
+        self.info_listeners.lock().push(InfoListener {
+            sender,
+            receiver: Arc::downgrade(&receiver),
+        });
+        ServerInfoChanges { receiver }
+    }
+
+    /// Notifies listeners of updated server info, forgetting those that were
+    /// dropped.
+    fn notify_info_listeners(&self, server_info: &ServerInfo) {
+        self.info_listeners
+            .lock()
+            .retain(|listener| listener.notify(server_info));
+    }
+
+    /// Returns `true` if the most recently established connection uses TLS.
+    pub(crate) fn is_tls(&self) -> bool {
+        self.tls.load(Ordering::Acquire)
+    }
+
     /// Makes a round trip to the server to ensure buffered messages reach it.
     pub(crate) fn flush(&self, timeout: Duration) -> io::Result<()> {
-        let pong = {
+        self.flush_timed(timeout).map(drop)
+    }
+
+    /// Flushes like `flush`, but returns right away without a round trip if
+    /// neither the writer nor the reconnect buffer holds unwritten bytes.
+    pub(crate) fn flush_if_pending(&self, timeout: Duration) -> io::Result<()> {
+        {
+            let write = self.state.write.lock();
+
+            // Check if the client is closed.
+            self.check_shutdown()?;
+
+            let unwritten = write.writer.as_ref().map_or(0, |w| w.buffer().len());
+            if unwritten == 0 && write.buffer.written == 0 {
+                return Ok(());
+            }
+        }
+        self.flush(timeout)
+    }
+
+    /// Makes a round trip to the server like `flush` and returns the time
+    /// between sending the `PING` and receiving the `PONG`.
+    pub(crate) fn flush_timed(&self, timeout: Duration) -> io::Result<Duration> {
+        let (start, pong) = {
             // Inject random delays when testing.
             inject_delay();
 
@@ -269,7 +901,8 @@ impl Client {
             // Check if the client is closed.
             self.check_shutdown()?;
 
-            let (sender, receiver) = channel::bounded(1);
+            let (slot, pong) = self.pong_slot();
+            let start = Instant::now();
 
             // If connected, send a PING.
             match write.writer.as_mut() {
@@ -279,7 +912,7 @@ impl Client {
                     // rather than the timeout because right now the timeout
                     // applies to each write syscall individually.
                     writer.get_ref().set_write_timeout(Some(timeout))?;
-                    proto::encode(&mut writer, ClientOp::Ping)?;
+                    self.encode(&mut writer, ClientOp::Ping)?;
                     writer.flush()?;
                     writer.get_ref().set_write_timeout(None)?;
                 }
@@ -287,58 +920,437 @@ impl Client {
 
             // Enqueue an expected PONG.
             let mut read = self.state.read.lock();
-            read.pongs.push_back(sender);
+            read.pongs.push_back(pong);
 
             // NB see locking protocol for state.write and state.read
             drop(read);
             drop(write);
 
-            receiver
+            (start, slot)
         };
 
         // Wait until the PONG operation is received.
-        match pong.recv() {
-            Ok(()) => Ok(()),
-            Err(_) => Err(Error::new(ErrorKind::ConnectionReset, "flush failed")),
+        let state = pong.wait(None);
+        self.pong_slots.lock().push(pong);
+        match state {
+            PongState::Received => Ok(start.elapsed()),
+            _ => Err(Error::new(ErrorKind::ConnectionReset, "flush failed")),
         }
     }
 
-    /// Closes the client.
-    pub(crate) fn close(&self) {
-        // Inject random delays when testing.
-        inject_delay();
-
-        let mut write = self.state.write.lock();
-        let mut read = self.state.read.lock();
-
-        // Initiate shutdown process.
-        if self.shutdown() {
-            // Clear all subscriptions.
-            let old_subscriptions = mem::take(&mut read.subscriptions);
-            for (sid, _) in old_subscriptions {
-                // Send an UNSUB message and ignore errors.
-                if let Some(writer) = write.writer.as_mut() {
-                    let max_msgs = None;
-                    proto::encode(writer, ClientOp::Unsub { sid, max_msgs }).ok();
-                    write.flush_kicker.try_send(()).ok();
-                }
-            }
-            read.subscriptions.clear();
+    /// Replaces the callback invoked with asynchronous errors, such as `-ERR`
+    /// messages sent by the server.
+    pub(crate) fn set_error_callback<F>(&self, cb: F)
+    where
+        F: Fn(Error) + Send + Sync + 'static,
+    {
+        *self.error_callback.lock() = Some(Arc::new(ErrorCallback::new(cb)));
+    }
 
-            // Flush the writer in case there are buffered messages.
-            if let Some(writer) = write.writer.as_mut() {
-                writer.flush().ok();
-            }
+    /// Passes an asynchronous error to the current error callback.
+    pub(crate) fn report_error(&self, err: Error) {
+        // Release the lock before calling the callback so that it can replace
+        // itself.
+        let callback = self.error_callback.lock().clone();
+        match callback {
+            Some(callback) => callback.call(self, err),
+            None => self.options.error_callback.call(self, err),
+        }
+    }
 
-            // Wake up all pending flushes.
-            read.pongs.clear();
+    /// Takes a slot from the freelist, or allocates one, and prepares it for
+    /// a new expected PONG. The slot should be returned to the freelist once
+    /// the PONG is finished.
+    fn pong_slot(&self) -> (Arc<PongSlot>, Pong) {
+        let slot = self.pong_slots.lock().pop().unwrap_or_else(|| {
+            Arc::new(PongSlot {
+                state: Mutex::new((0, PongState::Dropped)),
+                finished: Condvar::new(),
+            })
+        });
+        let generation = {
+            let mut state = slot.state.lock();
+            *state = (state.0 + 1, PongState::Pending);
+            state.0
+        };
+        let pong = Pong::Slot(slot.clone(), generation);
+        (slot, pong)
+    }
 
-            // NB see locking protocol for state.write and state.read
-            drop(read);
-            drop(write);
+    /// Returns the numbers of bytes waiting in the reconnect buffer and in
+    /// the write buffer of the current connection.
+    pub(crate) fn buffered_bytes(&self) -> BufferedBytes {
+        let write = self.state.write.lock();
+        BufferedBytes {
+            reconnect: write.buffer.flushed,
+            writer: write
+                .writer
+                .as_ref()
+                .map_or(0, |writer| writer.buffer().len()),
         }
     }
 
+    /// Returns the largest payload `Message::decompressed` may produce.
+    #[cfg(feature = "compression")]
+    pub(crate) fn max_decompressed_size(&self) -> usize {
+        self.options.max_incoming_message_size.unwrap_or_else(|| {
+            match self.server_info.lock().max_payload {
+                0 => crate::compression::DEFAULT_MAX_DECOMPRESSED,
+                max_payload => max_payload,
+            }
+        })
+    }
+
+    /// Returns the pending limits advertised by the server, falling back to
+    /// the configured estimates for those it does not advertise.
+    pub(crate) fn pending_limits(&self) -> PendingLimits {
+        let info = self.server_info.lock();
+        let estimates = self.options.pending_limits;
+        PendingLimits {
+            bytes: info.max_pending.unwrap_or(estimates.bytes),
+            messages: info.max_pending_msgs.unwrap_or(estimates.messages),
+        }
+    }
+
+    /// Returns the larger of the fractions of the pending limits taken by
+    /// the messages queued in subscriptions or held back by their ack
+    /// windows, and by their payloads, capped at 1.0.
+    #[allow(clippy::cast_precision_loss, clippy::float_arithmetic)]
+    pub(crate) fn slow_consumer_risk(&self) -> f32 {
+        let limits = self.pending_limits();
+        let (messages, bytes) = self
+            .state
+            .read
+            .lock()
+            .subscriptions
+            .values()
+            .map(Subscription::backlog)
+            .fold((0, 0), |(messages, bytes), backlog| {
+                (messages + backlog.0, bytes + backlog.1)
+            });
+
+        let fraction = |pending: usize, limit: usize| {
+            if limit == 0 {
+                0.0
+            } else {
+                pending as f32 / limit as f32
+            }
+        };
+        fraction(bytes, limits.bytes)
+            .max(fraction(messages, limits.messages))
+            .min(1.0)
+    }
+
+    /// Returns how many unknown protocol operations have been received from
+    /// the server.
+    pub(crate) fn unknown_op_count(&self) -> usize {
+        self.unknown_ops.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of complete PUB messages waiting in the reconnect
+    /// buffer.
+    pub(crate) fn buffered_publish_count(&self) -> usize {
+        self.state.write.lock().buffer.len()
+    }
+
+    /// Signals the flusher thread to flush the writer soon, without waiting.
+    pub(crate) fn kick_flush(&self) {
+        let mut write = self.state.write.lock();
+        self.kick_flusher(&mut write);
+    }
+
+    /// Signals the flusher thread that the writer needs a flush.
+    ///
+    /// A full channel means a flush is already pending. If the flusher
+    /// thread has died, a new one is spawned so that buffered messages still
+    /// reach the server.
+    fn kick_flusher(&self, write: &mut WriteState) {
+        if let Err(channel::TrySendError::Disconnected(())) = write.flush_kicker.try_send(()) {
+            // The flusher exits by itself after shutdown.
+            if *self.shutdown.lock() {
+                return;
+            }
+
+            log::error!("the flusher thread has died, spawning a new one");
+            let (flush_kicker, flush_wanted) = channel::bounded(1);
+            flush_kicker.try_send(()).ok();
+            write.flush_kicker = flush_kicker;
+            self.spawn_flusher(flush_wanted);
+        }
+    }
+
+    /// Sends a PING and waits for the PONG, failing with `NotConnected` if
+    /// the client is disconnected instead of waiting for a reconnect.
+    ///
+    /// Unlike `flush`, the round trip is not used to deliver the reconnect
+    /// buffer, and the wait for the PONG is bounded by the timeout.
+    pub(crate) fn ping(&self, timeout: Duration) -> io::Result<()> {
+        let pong = {
+            // Inject random delays when testing.
+            inject_delay();
+
+            let mut write = self.state.write.lock();
+
+            // Check if the client is closed.
+            self.check_shutdown()?;
+
+            let writer = write.writer.as_mut().ok_or_else(|| {
+                Error::new(ErrorKind::NotConnected, "the client is not connected")
+            })?;
+
+            // The protocol is ordered, so the PING still goes out after any
+            // operations already in the write buffer.
+            writer.get_ref().set_write_timeout(Some(timeout))?;
+            self.encode(&mut *writer, ClientOp::Ping)?;
+            writer.flush()?;
+            writer.get_ref().set_write_timeout(None)?;
+
+            // Enqueue an expected PONG.
+            let (slot, pong) = self.pong_slot();
+            let mut read = self.state.read.lock();
+            read.pongs.push_back(pong);
+
+            // NB see locking protocol for state.write and state.read
+            drop(read);
+            drop(write);
+
+            slot
+        };
+
+        // Wait until the PONG operation is received. The slot can be reused
+        // even if the PONG is still expected, as it belongs to an older
+        // generation by then.
+        let state = pong.wait(Some(timeout));
+        self.pong_slots.lock().push(pong);
+        match state {
+            PongState::Received => Ok(()),
+            PongState::Pending => Err(Error::new(ErrorKind::TimedOut, "no PONG received")),
+            PongState::Dropped => Err(Error::new(ErrorKind::ConnectionReset, "ping failed")),
+        }
+    }
+
+    /// Flushes buffered messages into the socket, including the records a TLS
+    /// session holds back, and waits until the kernel has sent them, where
+    /// the platform supports checking this.
+    pub(crate) fn flush_and_drain_writer(&self, timeout: Duration) -> io::Result<()> {
+        let deadline = Instant::now() + timeout;
+
+        let stream = {
+            let mut write = self.state.write.lock();
+
+            // Check if the client is closed.
+            self.check_shutdown()?;
+
+            let writer = write.writer.as_mut().ok_or_else(|| {
+                Error::new(ErrorKind::NotConnected, "the client is not connected")
+            })?;
+
+            writer.get_ref().set_write_timeout(Some(timeout))?;
+            let res = writer.flush();
+            writer.get_ref().set_write_timeout(None)?;
+            res?;
+
+            writer.get_ref().clone()
+        };
+
+        // Wait for the kernel to empty the send buffer.
+        while let Some(unsent) = stream.unsent_bytes()? {
+            if unsent == 0 {
+                break;
+            }
+            if Instant::now() >= deadline {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    "timed out waiting for the send buffer to drain",
+                ));
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        Ok(())
+    }
+
+    /// Blocks until the client is connected to a server, failing with
+    /// `TimedOut` if that does not happen before the timeout elapses.
+    pub(crate) fn wait_connected(&self, timeout: Duration) -> io::Result<()> {
+        let deadline = Instant::now() + timeout;
+        let mut write = self.state.write.lock();
+
+        while write.writer.is_none() {
+            // Check if the client is closed.
+            self.check_shutdown()?;
+
+            if self
+                .state
+                .connected
+                .wait_until(&mut write, deadline)
+                .timed_out()
+                && write.writer.is_none()
+            {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    "timed out waiting for a connection",
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of flushes that are still waiting for a PONG.
+    pub(crate) fn pending_flushes(&self) -> usize {
+        // Only the read lock is needed here, which is allowed by the locking
+        // protocol since the write lock is not held.
+        self.state.read.lock().pongs.len()
+    }
+
+    /// Closes the client.
+    pub(crate) fn close(&self) {
+        // Inject random delays when testing.
+        inject_delay();
+
+        let mut write = self.state.write.lock();
+        let mut read = self.state.read.lock();
+
+        // Initiate shutdown process.
+        if self.shutdown() {
+            // Clear all subscriptions.
+            let old_subscriptions = mem::take(&mut read.subscriptions);
+            for (sid, _) in old_subscriptions {
+                // Send an UNSUB message and ignore errors.
+                if let Some(writer) = write.writer.as_mut() {
+                    let max_msgs = None;
+                    self.encode(writer, ClientOp::Unsub { sid, max_msgs }).ok();
+                    self.kick_flusher(&mut write);
+                }
+            }
+            read.subscriptions.clear();
+            self.streaming_subscriptions.store(0, Ordering::Release);
+
+            // Flush the writer in case there are buffered messages.
+            self.shutdown_flush(&mut write);
+
+            // Wake up all pending flushes.
+            read.pongs.clear();
+
+            // Wake up anyone waiting for a connection, and the flusher so
+            // that it exits.
+            self.state.connected.notify_all();
+            write.flush_kicker.try_send(()).ok();
+
+            // NB see locking protocol for state.write and state.read
+            drop(read);
+            drop(write);
+        }
+    }
+
+    /// Flushes the writer while shutting down. The write timeout keeps an
+    /// unresponsive server from blocking the shutdown, and a writer that
+    /// could not be flushed in time is dropped.
+    fn shutdown_flush(&self, write: &mut WriteState) {
+        let timeout = self.options.shutdown_flush_timeout;
+        if timeout == Duration::from_secs(0) {
+            return;
+        }
+        if let Some(writer) = write.writer.as_mut() {
+            let flushed = writer
+                .get_ref()
+                .set_write_timeout(Some(timeout))
+                .and_then(|_| writer.flush())
+                .and_then(|_| writer.get_ref().set_write_timeout(None));
+            if flushed.is_err() {
+                write.writer = None;
+            }
+        }
+    }
+
+    /// Flushes and closes the client, then disconnects from the server so
+    /// that the client thread stops reading and exits without waiting for
+    /// the next message. Returns a handle for waiting until the background
+    /// threads have exited.
+    ///
+    /// The client is closed even if the flush fails, in which case the error
+    /// is returned instead of the handle.
+    pub(crate) fn drain_and_close(&self, timeout: Duration) -> io::Result<ShutdownHandle> {
+        let flushed = self.flush(timeout);
+        self.close_and_disconnect();
+        flushed?;
+
+        Ok(ShutdownHandle {
+            threads: self.threads.clone(),
+        })
+    }
+
+    /// Closes the client and disconnects from the server, so that the client
+    /// thread exits without waiting for the next message.
+    fn close_and_disconnect(&self) {
+        self.close();
+
+        let mut write = self.state.write.lock();
+        if let Some(writer) = write.writer.take() {
+            writer.get_ref().shutdown();
+        }
+    }
+
+    /// Stops fetching new `JetStream` batches, waits for in-flight acks to
+    /// complete and their effects to reach the server, then closes the
+    /// client. Fails with `TimedOut` if the acks did not complete in time, in
+    /// which case the client is closed regardless.
+    pub(crate) fn drain_jetstream(&self, timeout: Duration) -> io::Result<()> {
+        let deadline = Instant::now() + timeout;
+        self.acks.draining.store(true, Ordering::Release);
+
+        let mut timed_out = false;
+        {
+            let mut in_flight = self.acks.in_flight.lock();
+            while *in_flight > 0 {
+                if self
+                    .acks
+                    .idle
+                    .wait_until(&mut in_flight, deadline)
+                    .timed_out()
+                {
+                    timed_out = true;
+                    break;
+                }
+            }
+        }
+
+        if !timed_out {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            self.flush(remaining.max(Duration::from_millis(1))).ok();
+        }
+        self.close();
+
+        if timed_out {
+            Err(Error::new(
+                ErrorKind::TimedOut,
+                "timed out waiting for in-flight acks",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns an error if `drain_jetstream` has been called, in which case
+    /// no new `JetStream` batches should be fetched.
+    pub(crate) fn check_jetstream_draining(&self) -> io::Result<()> {
+        if self.acks.draining.load(Ordering::Acquire) {
+            Err(Error::new(
+                ErrorKind::NotConnected,
+                "the client is draining",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Marks an ack as in flight until the returned guard is dropped.
+    pub(crate) fn track_ack(&self) -> AckGuard<'_> {
+        *self.acks.in_flight.lock() += 1;
+        AckGuard(&self.acks)
+    }
+
     /// Kicks off the shutdown process, but doesn't wait for its completion.
     /// Returns true if this is the first attempt to shut down the system.
     pub(crate) fn shutdown(&self) -> bool {
@@ -348,6 +1360,12 @@ impl Client {
         !old
     }
 
+    /// Returns `true` if publishes should fail with `NotConnected` instead of
+    /// being buffered while the client is disconnected.
+    fn rejects_while_disconnected(&self) -> bool {
+        self.options.fail_when_disconnected || self.options.reconnect_buffer_size == 0
+    }
+
     fn check_shutdown(&self) -> io::Result<()> {
         if *self.shutdown.lock() {
             Err(Error::new(ErrorKind::NotConnected, "the client is closed"))
@@ -361,6 +1379,190 @@ impl Client {
         &self,
         subject: &str,
         queue_group: Option<&str>,
+    ) -> io::Result<(u64, channel::Receiver<Message>)> {
+        self.subscribe_with_filter(subject, queue_group, None, None, None, None)
+    }
+
+    /// Subscribes to a subject and makes a round trip to the server, so that
+    /// the subscription is registered before returning. Fails with
+    /// `PermissionDenied` if the server reported a permissions violation for
+    /// it. Unsubscribes again if the round trip or the check fails.
+    pub(crate) fn subscribe_sync(
+        &self,
+        subject: &str,
+        queue_group: Option<&str>,
+        timeout: Duration,
+    ) -> io::Result<(u64, channel::Receiver<Message>)> {
+        // Register the check before subscribing so that an error received
+        // right after the SUB operation is not missed.
+        let denied = Arc::new(AtomicBool::new(false));
+        self.state
+            .read
+            .lock()
+            .permission_checks
+            .push((subject.to_string(), denied.clone()));
+
+        let res = self
+            .subscribe(subject, queue_group)
+            .and_then(|(sid, receiver)| {
+                // The server handles operations in order, so any error for the
+                // subscription arrives before the PONG.
+                let checked = self.flush(timeout).and_then(|_| {
+                    if denied.load(Ordering::Acquire) {
+                        Err(Error::new(
+                            ErrorKind::PermissionDenied,
+                            format!("subscription to {:?} is not allowed", subject),
+                        ))
+                    } else {
+                        Ok(())
+                    }
+                });
+
+                match checked {
+                    Ok(()) => Ok((sid, receiver)),
+                    Err(err) => {
+                        self.unsubscribe(sid).ok();
+                        Err(err)
+                    }
+                }
+            });
+
+        self.state
+            .read
+            .lock()
+            .permission_checks
+            .retain(|(_, flag)| !Arc::ptr_eq(flag, &denied));
+
+        res
+    }
+
+    /// Subscribes to a subject, only delivering messages that match the
+    /// predicate. Non-matching messages are dropped by the client thread.
+    pub(crate) fn subscribe_filtered<F>(
+        &self,
+        subject: &str,
+        queue_group: Option<&str>,
+        predicate: F,
+    ) -> io::Result<(u64, channel::Receiver<Message>)>
+    where
+        F: Fn(&Message) -> bool + Send + Sync + 'static,
+    {
+        self.subscribe_with_filter(
+            subject,
+            queue_group,
+            Some(Box::new(predicate)),
+            None,
+            None,
+            None,
+        )
+    }
+
+    /// Subscribes to a subject, holding back new messages while
+    /// `max_pending` delivered messages have not been acked.
+    pub(crate) fn subscribe_with_ack_window(
+        &self,
+        subject: &str,
+        max_pending: usize,
+    ) -> io::Result<(u64, channel::Receiver<Message>)> {
+        if max_pending == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "the ack window must allow at least one pending message",
+            ));
+        }
+        self.subscribe_with_filter(subject, None, None, None, Some(max_pending), None)
+    }
+
+    /// Subscribes to a subject, streaming the payloads of its messages from
+    /// the connection as they are read.
+    pub(crate) fn subscribe_streaming(
+        &self,
+        subject: &str,
+    ) -> io::Result<(u64, channel::Receiver<StreamingMessage>)> {
+        let (sender, receiver) = channel::unbounded();
+        let (sid, _) = self.subscribe_with_filter(subject, None, None, None, None, Some(sender))?;
+        Ok((sid, receiver))
+    }
+
+    /// Returns `true` if the subscription streams its payloads.
+    fn is_streamed(&self, sid: u64) -> bool {
+        if self.streaming_subscriptions.load(Ordering::Acquire) == 0 {
+            return false;
+        }
+        matches!(
+            self.state.read.lock().subscriptions.get(&sid),
+            Some(subscription) if subscription.streaming.is_some()
+        )
+    }
+
+    /// Frees the place of an acked message in the ack window of its
+    /// subscription and delivers the oldest held back message, if any.
+    fn release_ack_slot(&self, slot: &AckSlot) {
+        if slot.released.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let mut read = self.state.read.lock();
+        if let Some(subscription) = read.subscriptions.get_mut(&slot.sid) {
+            let next = subscription.ack_window.as_mut().and_then(|window| {
+                window.in_flight -= 1;
+                let msg = window.held.pop_front()?;
+                window.in_flight += 1;
+                Some(msg)
+            });
+            if let Some(overflow) = next.and_then(|msg| subscription.send(msg)) {
+                // The channel is full, so keep holding the message back until
+                // the next ack.
+                if let Some(window) = subscription.ack_window.as_mut() {
+                    window.in_flight -= 1;
+                    window.held.push_front(overflow);
+                }
+            }
+        }
+    }
+
+    /// Subscribes to a subject, dropping messages whose subject has more than
+    /// `max_token_depth` tokens before they are queued.
+    pub(crate) fn subscribe_bounded_wildcard(
+        &self,
+        subject: &str,
+        max_token_depth: usize,
+    ) -> io::Result<(u64, channel::Receiver<Message>)> {
+        let depth = subject.split('.').count();
+        if max_token_depth < depth {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "a maximum token depth of {} can never match {:?}",
+                    max_token_depth, subject
+                ),
+            ));
+        }
+
+        self.subscribe_filtered(subject, None, move |msg| {
+            let accepted = msg.subject_tokens().nth(max_token_depth).is_none();
+            if !accepted {
+                log::trace!(
+                    "dropped message on {:?} exceeding the token depth",
+                    msg.subject
+                );
+            }
+            accepted
+        })
+    }
+
+    /// Subscribes to a subject, delivering messages through a channel
+    /// bounded by `capacity`, or an unbounded one if `None`. If
+    /// `max_pending` is set, at most that many delivered messages may be
+    /// waiting for an ack. If `streaming` is set, messages are sent to it
+    /// as `StreamingMessage`s instead.
+    fn subscribe_with_filter(
+        &self,
+        subject: &str,
+        queue_group: Option<&str>,
+        filter: Option<Filter>,
+        capacity: Option<usize>,
+        max_pending: Option<usize>,
+        streaming: Option<channel::Sender<StreamingMessage>>,
     ) -> io::Result<(u64, channel::Receiver<Message>)> {
         // Inject random delays when testing.
         inject_delay();
@@ -374,6 +1576,12 @@ impl Client {
         // Generate a subject ID.
         let sid = write.next_sid;
         write.next_sid += 1;
+        write.last_used = Instant::now();
+
+        // Count streaming subscriptions before their messages can arrive.
+        if streaming.is_some() {
+            self.streaming_subscriptions.fetch_add(1, Ordering::AcqRel);
+        }
 
         // If connected, send a SUB operation.
         if let Some(writer) = write.writer.as_mut() {
@@ -382,18 +1590,34 @@ impl Client {
                 queue_group,
                 sid,
             };
-            proto::encode(writer, op).ok();
-            write.flush_kicker.try_send(()).ok();
+            self.encode(writer, op).ok();
+            self.kick_flusher(&mut write);
         }
 
         // Register the subscription in the hash map.
-        let (sender, receiver) = channel::unbounded();
+        debug_assert!(
+            !read.subscriptions.contains_key(&sid),
+            "sid {} is already in use",
+            sid
+        );
+        let (sender, receiver) = match capacity {
+            Some(capacity) => channel::bounded(capacity),
+            None => channel::unbounded(),
+        };
         read.subscriptions.insert(
             sid,
             Subscription {
                 subject: subject.to_string(),
                 queue_group: queue_group.map(ToString::to_string),
                 messages: sender,
+                queued_sizes: VecDeque::new(),
+                filter,
+                ack_window: max_pending.map(|max_pending| AckWindow {
+                    max_pending,
+                    in_flight: 0,
+                    held: VecDeque::new(),
+                }),
+                streaming,
             },
         );
 
@@ -404,6 +1628,27 @@ impl Client {
         Ok((sid, receiver))
     }
 
+    /// Creates a new unique inbox and subscribes to it. At most
+    /// `request_inbox_capacity` replies are queued on the subscription.
+    pub(crate) fn new_respond_inbox(&self) -> io::Result<(String, crate::Subscription)> {
+        let inbox = format!("_INBOX.{}", nuid::next());
+        let capacity = Some(self.options.request_inbox_capacity);
+        let (sid, receiver) =
+            self.subscribe_with_filter(&inbox, None, None, capacity, None, None)?;
+        let sub = crate::Subscription::new(sid, inbox.clone(), receiver, self.clone());
+        Ok((inbox, sub))
+    }
+
+    /// Creates a new unique inbox and subscribes to it without limiting the
+    /// number of queued messages, for receiving the responses to many
+    /// requests.
+    pub(crate) fn responder_inbox(&self) -> io::Result<(String, crate::Subscription)> {
+        let inbox = format!("_INBOX.{}", nuid::next());
+        let (sid, receiver) = self.subscribe(&inbox, None)?;
+        let sub = crate::Subscription::new(sid, inbox.clone(), receiver, self.clone());
+        Ok((inbox, sub))
+    }
+
     /// Unsubscribes from a subject.
     pub(crate) fn unsubscribe(&self, sid: u64) -> io::Result<()> {
         // Inject random delays when testing.
@@ -413,21 +1658,28 @@ impl Client {
         let mut read = self.state.read.lock();
 
         // Remove the subscription from the map.
-        if read.subscriptions.remove(&sid).is_none() {
-            // already unsubscribed
+        match read.subscriptions.remove(&sid) {
+            Some(subscription) if subscription.streaming.is_some() => {
+                self.streaming_subscriptions.fetch_sub(1, Ordering::AcqRel);
+            }
+            Some(_) => {}
+            None => {
+                // already unsubscribed
 
-            // NB see locking protocol for state.write and state.read
-            drop(read);
-            drop(write);
+                // NB see locking protocol for state.write and state.read
+                drop(read);
+                drop(write);
 
-            return Ok(());
+                return Ok(());
+            }
         }
+        write.last_used = Instant::now();
 
         // Send an UNSUB message.
         if let Some(writer) = write.writer.as_mut() {
             let max_msgs = None;
-            proto::encode(writer, ClientOp::Unsub { sid, max_msgs })?;
-            write.flush_kicker.try_send(()).ok();
+            self.encode(writer, ClientOp::Unsub { sid, max_msgs })?;
+            self.kick_flusher(&mut write);
         }
 
         // NB see locking protocol for state.write and state.read
@@ -445,26 +1697,146 @@ impl Client {
         headers: Option<&Headers>,
         msg: &[u8],
     ) -> io::Result<()> {
-        // Inject random delays when testing.
-        inject_delay();
-
-        let server_info = self.server_info.lock();
-        if headers.is_some() && !server_info.headers {
-            return Err(Error::new(
-                ErrorKind::InvalidInput,
-                "the server does not support headers",
-            ));
-        }
-        drop(server_info);
+        self.publish_or_buffer(subject, reply_to, headers, msg, true, None)
+            .map(drop)
+    }
 
-        // Check if the client is closed.
-        self.check_shutdown()?;
+    /// Publishes a message like `publish`, returning the number of bytes of
+    /// the encoded operation, including protocol overhead.
+    pub(crate) fn publish_sized(
+        &self,
+        subject: &str,
+        reply_to: Option<&str>,
+        headers: Option<&Headers>,
+        msg: &[u8],
+    ) -> io::Result<usize> {
+        self.publish_or_buffer(subject, reply_to, headers, msg, true, None)
+            .map(Option::unwrap_or_default)
+    }
 
-        let op = if let Some(headers) = headers {
-            ClientOp::Hpub {
-                subject,
-                reply_to,
-                payload: msg,
+    /// Publishes a message, failing with `TimedOut` instead of blocking
+    /// indefinitely if writing to the socket stalls for longer than the
+    /// timeout. The connection is reestablished after such a failure.
+    pub(crate) fn publish_with_timeout(
+        &self,
+        subject: &str,
+        reply_to: Option<&str>,
+        headers: Option<&Headers>,
+        msg: &[u8],
+        timeout: Duration,
+    ) -> io::Result<()> {
+        self.publish_or_buffer(subject, reply_to, headers, msg, true, Some(timeout))
+            .map(drop)
+    }
+
+    /// Publishes a message and makes a round trip to the server to ensure it
+    /// was received. The flush is not attempted if publishing fails.
+    pub(crate) fn publish_and_flush(
+        &self,
+        subject: &str,
+        reply_to: Option<&str>,
+        headers: Option<&Headers>,
+        msg: &[u8],
+        timeout: Duration,
+    ) -> io::Result<()> {
+        self.publish(subject, reply_to, headers, msg)?;
+        self.flush(timeout)
+    }
+
+    /// Publishes a message and confirms it with a round trip to the server.
+    /// If the connection fails meanwhile, waits for a reconnect and tries
+    /// once more. Everything happens within the timeout.
+    pub(crate) fn publish_reliable(
+        &self,
+        subject: &str,
+        reply_to: Option<&str>,
+        headers: Option<&Headers>,
+        msg: &[u8],
+        timeout: Duration,
+    ) -> io::Result<()> {
+        let deadline = Instant::now() + timeout;
+        let attempt = || {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::from_secs(0) {
+                return Err(Error::new(ErrorKind::TimedOut, "publish timed out"));
+            }
+            let connection = self.state.write.lock().connections;
+            let written =
+                self.publish_or_buffer(subject, reply_to, headers, msg, false, Some(remaining))?;
+            if written.is_none() {
+                return Err(Error::new(
+                    ErrorKind::NotConnected,
+                    "the client is not connected",
+                ));
+            }
+            self.ping(remaining)?;
+            if self.state.write.lock().connections != connection {
+                return Err(Error::new(
+                    ErrorKind::ConnectionReset,
+                    "the connection was lost before the publish was confirmed",
+                ));
+            }
+            Ok(())
+        };
+
+        match attempt() {
+            Err(err) if is_disconnect(&err) => {
+                log::debug!("retrying publish after a reconnect: {}", err);
+                self.wait_connected(deadline.saturating_duration_since(Instant::now()))?;
+                attempt()
+            }
+            res => res,
+        }
+    }
+
+    /// Publishes a message only if the client is connected, returning
+    /// `Ok(false)` instead of buffering it while reconnecting.
+    pub(crate) fn publish_if_connected(
+        &self,
+        subject: &str,
+        reply_to: Option<&str>,
+        headers: Option<&Headers>,
+        msg: &[u8],
+    ) -> io::Result<bool> {
+        self.publish_or_buffer(subject, reply_to, headers, msg, false, None)
+            .map(|written| written.is_some())
+    }
+
+    /// Publishes a message, returning the number of encoded bytes, or
+    /// `Ok(None)` if the client is disconnected and `buffer` is false.
+    /// Writes to the socket are bounded by `write_timeout`, if set.
+    fn publish_or_buffer(
+        &self,
+        subject: &str,
+        reply_to: Option<&str>,
+        headers: Option<&Headers>,
+        msg: &[u8],
+        buffer: bool,
+        write_timeout: Option<Duration>,
+    ) -> io::Result<Option<usize>> {
+        // Inject random delays when testing.
+        inject_delay();
+
+        let subject = self.options.subject_transform.call(subject);
+        let subject = subject.as_ref();
+
+        let server_info = self.server_info.lock();
+        if headers.is_some() && !server_info.supports(ServerFeature::Headers) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "the server does not support headers",
+            ));
+        }
+        drop(server_info);
+
+        // Check if the client is closed.
+        self.check_shutdown()?;
+
+        let op = if let Some(headers) = headers {
+            ClientOp::Hpub {
+                subject,
+                reply_to,
+                payload: msg,
                 headers,
             }
         } else {
@@ -476,21 +1848,95 @@ impl Client {
         };
 
         let mut write = self.state.write.lock();
+        write.last_used = Instant::now();
 
         let written = write.buffer.written;
 
         match write.writer.as_mut() {
+            None if !buffer => Ok(None),
+            None if self.rejects_while_disconnected() => Err(Error::new(
+                ErrorKind::NotConnected,
+                "the client is not connected",
+            )),
             None => {
                 // If reconnecting, write into the buffer.
-                proto::encode(&mut write.buffer, op)?;
+                let len = self.encode(&mut write.buffer, op)?;
                 write.buffer.flush()?;
-                Ok(())
+                Ok(Some(len))
             }
             Some(mut writer) => {
                 assert_eq!(written, 0);
 
+                if let Some(timeout) = write_timeout {
+                    writer.get_ref().set_write_timeout(Some(timeout))?;
+                }
+
+                // If connected, write into the writer.
+                let mut res = inject_write_failure().and_then(|_| self.encode(&mut writer, op));
+
+                if write_timeout.is_some() {
+                    res = match res {
+                        Ok(len) => writer.get_ref().set_write_timeout(None).map(|_| len),
+                        // A timed out write is reported as `WouldBlock` on
+                        // some platforms.
+                        Err(err) if err.kind() == ErrorKind::WouldBlock => Err(Error::new(
+                            ErrorKind::TimedOut,
+                            "timed out writing to the socket",
+                        )),
+                        Err(err) => Err(err),
+                    };
+                }
+
+                // If writing fails, disconnect.
+                if res.is_err() {
+                    write.writer = None;
+
+                    // NB see locking protocol for state.write and state.read
+                    let mut read = self.state.read.lock();
+                    read.pongs.clear();
+                }
+
+                self.kick_flusher(&mut write);
+
+                res.map(Some)
+            }
+        }
+    }
+
+    /// Publishes a message by writing a pre-encoded protocol prefix followed
+    /// by the payload. The caller is responsible for the prefix being valid.
+    pub(crate) fn publish_raw(&self, prefix: &[u8], payload: &[u8]) -> io::Result<()> {
+        self.write_raw(&[prefix, payload, b"\r\n"])
+    }
+
+    /// Writes already encoded protocol bytes, bypassing `proto::encode`.
+    pub(crate) fn write_raw(&self, parts: &[&[u8]]) -> io::Result<()> {
+        // Inject random delays when testing.
+        inject_delay();
+
+        // Check if the client is closed.
+        self.check_shutdown()?;
+
+        let mut write = self.state.write.lock();
+        write.last_used = Instant::now();
+
+        match write.writer.as_mut() {
+            None if self.rejects_while_disconnected() => Err(Error::new(
+                ErrorKind::NotConnected,
+                "the client is not connected",
+            )),
+            None => {
+                // If reconnecting, write into the buffer.
+                for part in parts {
+                    write.buffer.write_all(part)?;
+                }
+                write.buffer.flush()?;
+                Ok(())
+            }
+            Some(writer) => {
                 // If connected, write into the writer.
-                let res = proto::encode(&mut writer, op);
+                let res = inject_write_failure()
+                    .and_then(|_| parts.iter().try_for_each(|part| writer.write_all(part)));
 
                 // If writing fails, disconnect.
                 if res.is_err() {
@@ -501,7 +1947,7 @@ impl Client {
                     read.pongs.clear();
                 }
 
-                write.flush_kicker.try_send(()).ok();
+                self.kick_flusher(&mut write);
 
                 res
             }
@@ -524,6 +1970,9 @@ impl Client {
             return Some(Err(e));
         }
 
+        let subject = self.options.subject_transform.call(subject);
+        let subject = subject.as_ref();
+
         // Estimate how many bytes the message will consume when written into
         // the stream. We must make a conservative guess: it's okay to
         // overestimate but not to underestimate.
@@ -551,24 +2000,31 @@ impl Client {
         };
 
         let mut write = self.state.write.try_lock()?;
+        write.last_used = Instant::now();
 
         match write.writer.as_mut() {
+            None if self.rejects_while_disconnected() => Some(Err(Error::new(
+                ErrorKind::NotConnected,
+                "the client is not connected",
+            ))),
             None => {
                 // If reconnecting, write into the buffer.
-                let res = proto::encode(&mut write.buffer, op).and_then(|_| write.buffer.flush());
+                let res = self
+                    .encode(&mut write.buffer, op)
+                    .and_then(|_| write.buffer.flush());
                 Some(res)
             }
             Some(mut writer) => {
                 // Check if there's enough space in the buffer to encode the
                 // whole message.
-                if BUF_CAPACITY - writer.buffer().len() < estimate {
+                if writer.capacity() - writer.buffer().len() < estimate {
                     return None;
                 }
 
                 // If connected, write into the writer. This is not going to
                 // block because there's enough space in the buffer.
-                let res = proto::encode(&mut writer, op);
-                write.flush_kicker.try_send(()).ok();
+                let res = inject_write_failure().and_then(|_| self.encode(&mut writer, op));
+                self.kick_flusher(&mut write);
 
                 // If writing fails, disconnect.
                 if res.is_err() {
@@ -578,7 +2034,7 @@ impl Client {
                     let mut read = self.state.read.lock();
                     read.pongs.clear();
                 }
-                Some(res)
+                Some(res.map(drop))
             }
         }
     }
@@ -593,16 +2049,24 @@ impl Client {
             // Make a connection to the server.
             let (server_info, stream) = connector.connect(use_backoff)?;
 
-            let reader = BufReader::with_capacity(BUF_CAPACITY, stream.clone());
-            let writer = BufWriter::with_capacity(BUF_CAPACITY, stream);
+            let reader =
+                BufReader::with_capacity(self.options.read_buffer_capacity, stream.clone());
+            let writer = BufWriter::with_capacity(self.options.write_buffer_capacity, stream);
 
             // Set up the new connection for this client.
             if self.reconnect(server_info, writer).is_ok() {
                 // Connected! Now dispatch MSG operations.
-                if !first_connect {
+                if first_connect {
+                    // Run the callback on a thread of its own, so it can use
+                    // the connection while this thread is dispatching.
+                    let options = connector.get_options();
+                    thread::spawn(move || options.connect_callback.call());
+                } else {
                     connector.get_options().reconnect_callback.call();
                 }
-                if self.dispatch(reader, &mut connector).is_ok() {
+                // A connection broken by `drain_and_close` is not reported as
+                // a disconnect.
+                if self.dispatch(reader, &mut connector).is_ok() || self.check_shutdown().is_err() {
                     // If the client stopped gracefully, return.
                     return Ok(());
                 } else {
@@ -651,7 +2115,7 @@ impl Client {
         // Restart subscriptions that existed before the last reconnect.
         for (sid, subscription) in &read.subscriptions {
             // Send a SUB operation to the server.
-            proto::encode(
+            self.encode(
                 &mut writer,
                 ClientOp::Sub {
                     subject: subscription.subject.as_str(),
@@ -661,32 +2125,63 @@ impl Client {
             )?;
         }
 
+        // Make sure the server has established all subscriptions before any
+        // buffered messages are published, so that e.g. `JetStream` deliver
+        // subjects are in place before redeliveries arrive. A failure here
+        // aborts the reconnect rather than continuing with a connection that
+        // is only partially set up.
+        writer.flush()?;
+
         // Take out expected PONGs.
         let pongs = mem::take(&mut read.pongs);
 
         // Take out buffered operations.
+        let replayed = write.buffer.len();
+        let dropped = write.buffer.dropped();
         let buffered = write.buffer.clear();
 
         // Write buffered PUB operations into the new writer.
         writer.write_all(buffered)?;
         writer.flush()?;
 
+        // The buffered operations have been handed to the new connection, so
+        // they no longer need to be replayed after a restart.
+        write.buffer.discard_persisted()?;
+
         // All good, continue with this connection.
+        self.notify_info_listeners(&server_info);
         *self.server_info.lock() = server_info;
+        self.tls.store(writer.get_ref().is_tls(), Ordering::Release);
         write.writer = Some(writer);
+        write.connections += 1;
+        self.state.connected.notify_all();
 
         // Complete PONGs because the connection is healthy.
         for p in pongs {
-            p.try_send(()).ok();
+            p.complete();
         }
 
         // NB see locking protocol for state.write and state.read
         drop(read);
         drop(write);
 
+        if replayed > 0 {
+            self.options.replayed_callback.call(replayed);
+        }
+        if dropped > 0 {
+            self.options.dropped_callback.call(dropped);
+        }
+
         Ok(())
     }
 
+    /// Encodes an operation into the writer, notifying the outgoing op
+    /// callback.
+    fn encode(&self, writer: impl Write, op: ClientOp<'_>) -> io::Result<usize> {
+        self.options.outgoing_op_callback.call(&op);
+        proto::encode(writer, op)
+    }
+
     /// Updates our last activity from the server.
     fn update_activity(&self) {
         let mut read = self.state.read.lock();
@@ -696,7 +2191,10 @@ impl Client {
     /// Reads messages from the server and dispatches them to subscribers.
     fn dispatch(&self, mut reader: impl BufRead, connector: &mut Connector) -> io::Result<()> {
         // Handle operations received from the server.
-        while let Some(op) = proto::decode(&mut reader)? {
+        let max_message_size = self.options.max_incoming_message_size;
+        while let Some(op) =
+            proto::decode_streaming(&mut reader, max_message_size, |sid| self.is_streamed(sid))?
+        {
             // Inject random delays when testing.
             inject_delay();
 
@@ -708,11 +2206,42 @@ impl Client {
             self.update_activity();
 
             match op {
-                ServerOp::Info(server_info) => {
+                ServerOp::Info(mut server_info) => {
                     for url in &server_info.connect_urls {
                         connector.add_url(url).ok();
                     }
-                    *self.server_info.lock() = server_info;
+                    let mut current = self.server_info.lock();
+
+                    // INFO updates sent during the connection may omit the
+                    // client fields, which only change across reconnects.
+                    if server_info.client_id == 0 {
+                        server_info.client_id = current.client_id;
+                    }
+                    if server_info.client_ip.is_none() {
+                        server_info.client_ip = current.client_ip.take();
+                    }
+
+                    let nonce_changed =
+                        !server_info.nonce.is_empty() && server_info.nonce != current.nonce;
+                    *current = server_info.clone();
+                    drop(current);
+
+                    // Answer a new auth challenge without reconnecting.
+                    if nonce_changed {
+                        match connector.resign(&server_info) {
+                            Ok(Some(connect_info)) => {
+                                let mut write = self.state.write.lock();
+                                if let Some(writer) = write.writer.as_mut() {
+                                    self.encode(writer, ClientOp::Connect(&connect_info))?;
+                                    self.kick_flusher(&mut write);
+                                }
+                            }
+                            Ok(None) => {}
+                            Err(err) => self.report_error(err),
+                        }
+                    }
+
+                    self.notify_info_listeners(&server_info);
                 }
 
                 ServerOp::Ping => {
@@ -720,14 +2249,27 @@ impl Client {
                     let mut write = self.state.write.lock();
                     let read = self.state.read.lock();
 
-                    if let Some(w) = write.writer.as_mut() {
-                        proto::encode(w, ClientOp::Pong)?;
-                        write.flush_kicker.try_send(()).ok();
-                    }
+                    let connected = if let Some(w) = write.writer.as_mut() {
+                        self.encode(w, ClientOp::Pong)?;
+                        self.kick_flusher(&mut write);
+                        true
+                    } else {
+                        // The writer was dropped after a failed write, so
+                        // reconnect right away instead of waiting for the
+                        // server to find this client stale.
+                        false
+                    };
 
                     // NB see locking protocol for state.write and state.read
                     drop(read);
                     drop(write);
+
+                    if !connected {
+                        return Err(Error::new(
+                            ErrorKind::NotConnected,
+                            "received a PING after the connection was lost",
+                        ));
+                    }
                 }
 
                 ServerOp::Pong => {
@@ -744,7 +2286,7 @@ impl Client {
                         // Take the next expected PONG and complete it by
                         // sending a message.
                         if let Some(pong) = read.pongs.pop_front() {
-                            pong.try_send(()).ok();
+                            pong.complete();
                         }
                     }
 
@@ -759,22 +2301,34 @@ impl Client {
                     reply_to,
                     payload,
                 } => {
-                    let read = self.state.read.lock();
+                    let mut read = self.state.read.lock();
+                    let mut overflow = None;
 
                     // Send the message to matching subscription.
-                    if let Some(subscription) = read.subscriptions.get(&sid) {
+                    if let Some(subscription) = read.subscriptions.get_mut(&sid) {
                         let msg = Message {
                             subject,
                             reply: reply_to,
                             data: payload,
                             headers: None,
+                            subscription_subject: Some(subscription.subject.clone()),
                             client: self.clone(),
                             double_acked: Default::default(),
+                            ack_slot: None,
                         };
 
                         // Send a message or drop it if the channel is
-                        // disconnected or full.
-                        subscription.messages.try_send(msg).ok();
+                        // disconnected, or the message is filtered out.
+                        if subscription.accepts(&msg) {
+                            overflow = subscription.deliver(sid, msg);
+                        }
+                    }
+
+                    // Divert a message that didn't fit into the channel,
+                    // without holding the lock.
+                    drop(read);
+                    if let Some(msg) = overflow {
+                        self.options.dead_letter_callback.call(&msg);
                     }
                 }
 
@@ -785,33 +2339,108 @@ impl Client {
                     reply_to,
                     payload,
                 } => {
-                    let read = self.state.read.lock();
+                    let mut read = self.state.read.lock();
+                    let mut overflow = None;
                     // Send the message to matching subscription.
-                    if let Some(subscription) = read.subscriptions.get(&sid) {
+                    if let Some(subscription) = read.subscriptions.get_mut(&sid) {
                         let msg = Message {
                             subject,
                             reply: reply_to,
                             data: payload,
                             headers: Some(headers),
+                            subscription_subject: Some(subscription.subject.clone()),
                             client: self.clone(),
                             double_acked: Default::default(),
+                            ack_slot: None,
                         };
 
                         // Send a message or drop it if the channel is
-                        // disconnected or full.
-                        subscription.messages.try_send(msg).ok();
+                        // disconnected, or the message is filtered out.
+                        if subscription.accepts(&msg) {
+                            overflow = subscription.deliver(sid, msg);
+                        }
+                    }
+
+                    // Divert a message that didn't fit into the channel,
+                    // without holding the lock.
+                    drop(read);
+                    if let Some(msg) = overflow {
+                        self.options.dead_letter_callback.call(&msg);
                     }
                 }
 
+                ServerOp::Streamed {
+                    subject,
+                    headers,
+                    sid,
+                    reply_to,
+                    num_bytes,
+                } => {
+                    // The subscription may be gone by now, in which case the
+                    // payload is skipped.
+                    let messages = self
+                        .state
+                        .read
+                        .lock()
+                        .subscriptions
+                        .get(&sid)
+                        .and_then(|subscription| subscription.streaming.clone());
+                    streaming::forward(
+                        &mut reader,
+                        messages,
+                        subject,
+                        reply_to,
+                        headers,
+                        num_bytes,
+                        streaming::CHUNK_TIMEOUT,
+                    )?;
+                }
+
+                ServerOp::Oversized {
+                    subject,
+                    sid,
+                    num_bytes,
+                } => {
+                    log::debug!("skipped oversized message for sid {}", sid);
+                    self.report_error(Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "dropped a message of {} bytes on {:?} exceeding the \
+                             maximum incoming message size",
+                            num_bytes, subject
+                        ),
+                    ));
+                }
+
+                ServerOp::Err(msg) if msg.eq_ignore_ascii_case(STALE_CONNECTION) => {
+                    // The server is about to close the connection. This is an
+                    // expected transient condition, so reconnect instead of
+                    // reporting it as an application error.
+                    log::debug!("server reported a stale connection, reconnecting");
+                    return Err(Error::new(ErrorKind::ConnectionReset, msg));
+                }
+
                 ServerOp::Err(msg) => {
-                    connector
-                        .get_options()
-                        .error_callback
-                        .call(self, Error::new(ErrorKind::Other, msg));
+                    // Fail pending permissions checks for the subject.
+                    if let Some(subject) = msg
+                        .strip_prefix(SUBSCRIPTION_VIOLATION)
+                        .and_then(|rest| rest.split('"').next())
+                    {
+                        let read = self.state.read.lock();
+                        for (checked, denied) in &read.permission_checks {
+                            if checked == subject {
+                                denied.store(true, Ordering::Release);
+                            }
+                        }
+                    }
+
+                    self.report_error(Error::new(ErrorKind::Other, msg));
                 }
 
                 ServerOp::Unknown(line) => {
                     log::warn!("unknown op: {}", line);
+                    self.unknown_ops.fetch_add(1, Ordering::Relaxed);
+                    self.options.unknown_op_callback.call(line.trim_end());
                 }
             }
         }
@@ -832,6 +2461,10 @@ impl fmt::Debug for Client {
 /// messages get stored in this buffer of limited size. As soon as the
 /// connection is then re-established, buffered messages will be sent to the
 /// server.
+///
+/// If a persistence file is configured, flushed PUB messages are also
+/// appended to it and synced to disk, and any contents left over from a
+/// previous process are loaded into the buffer on creation.
 struct Buffer {
     /// Bytes in the buffer.
     ///
@@ -847,16 +2480,81 @@ struct Buffer {
 
     /// Number of bytes marked as "flushed".
     flushed: usize,
+
+    /// End offsets of the PUB messages in `..flushed`, oldest first.
+    ends: VecDeque<usize>,
+
+    /// Number of messages evicted since the buffer was last cleared.
+    dropped: usize,
+
+    /// What to do when a message does not fit.
+    policy: BufferPolicy,
+
+    /// Append-only file mirroring the flushed bytes.
+    file: Option<File>,
 }
 
 impl Buffer {
-    /// Creates a new buffer with the given size.
-    fn new(size: usize) -> Buffer {
-        Buffer {
+    /// Creates a new buffer with the given size, optionally persisted to the
+    /// file at `persist`.
+    ///
+    /// If the file contains PUB messages that were never sent, they are
+    /// loaded into the buffer, growing it if they do not fit. Anything
+    /// after the last complete message, such as a message cut short by a
+    /// crash, is truncated from the file and dropped.
+    fn new(size: usize, policy: BufferPolicy, persist: Option<&Path>) -> io::Result<Buffer> {
+        let mut buffer = Buffer {
             bytes: vec![0_u8; size].into_boxed_slice(),
             written: 0,
             flushed: 0,
+            ends: VecDeque::new(),
+            dropped: 0,
+            policy,
+            file: None,
+        };
+
+        if let Some(path) = persist {
+            let mut file = OpenOptions::new()
+                .read(true)
+                .append(true)
+                .create(true)
+                .open(path)?;
+
+            let mut replay = Vec::new();
+            file.read_to_end(&mut replay)?;
+            let ends = op_ends(&replay);
+            let complete = ends.back().copied().unwrap_or(0);
+            if complete < replay.len() {
+                log::warn!(
+                    "dropping {} bytes of an incomplete message at the end of {}",
+                    replay.len() - complete,
+                    path.display()
+                );
+                replay.truncate(complete);
+                file.set_len(complete as u64)?;
+            }
+            if replay.len() > size {
+                buffer.bytes = vec![0_u8; replay.len()].into_boxed_slice();
+            }
+            buffer.bytes[..replay.len()].copy_from_slice(&replay);
+            buffer.written = replay.len();
+            buffer.flushed = replay.len();
+            buffer.ends = ends;
+            buffer.file = Some(file);
         }
+
+        Ok(buffer)
+    }
+
+    /// Returns the number of complete PUB messages in the buffer.
+    fn len(&self) -> usize {
+        self.ends.len()
+    }
+
+    /// Returns the number of messages evicted since the buffer was last
+    /// cleared.
+    fn dropped(&self) -> usize {
+        self.dropped
     }
 
     /// Clears the buffer and returns buffered bytes.
@@ -864,38 +2562,139 @@ impl Buffer {
         let buffered = &self.bytes[..self.flushed];
         self.written = 0;
         self.flushed = 0;
+        self.ends.clear();
+        self.dropped = 0;
         buffered
     }
+
+    /// Evicts the oldest messages until `n` more bytes fit into the buffer.
+    ///
+    /// Returns `false` without evicting anything if the bytes would not fit
+    /// even with all messages evicted.
+    fn evict_for(&mut self, n: usize) -> io::Result<bool> {
+        let partial = self.written - self.flushed;
+        if partial + n > self.bytes.len() {
+            return Ok(false);
+        }
+
+        let mut evicted = 0;
+        while self.bytes.len() - (self.written - evicted) < n {
+            // The partial message and `n` fit, so the loop ends at the
+            // latest by evicting every message up to `flushed`.
+            evicted = match self.ends.pop_front() {
+                Some(end) => {
+                    self.dropped += 1;
+                    end
+                }
+                None => self.flushed,
+            };
+        }
+
+        log::debug!("dropping {} bytes of the oldest buffered messages", evicted);
+        self.bytes.copy_within(evicted..self.written, 0);
+        self.written -= evicted;
+        self.flushed -= evicted;
+        for end in &mut self.ends {
+            *end -= evicted;
+        }
+
+        // Rewrite the file, which would otherwise still contain the evicted
+        // messages.
+        if let Some(file) = self.file.as_mut() {
+            file.set_len(0)?;
+            file.write_all(&self.bytes[..self.flushed])?;
+            file.sync_data()?;
+        }
+
+        Ok(true)
+    }
+
+    /// Truncates the persistence file once its contents have been sent.
+    fn discard_persisted(&mut self) -> io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.set_len(0),
+            None => Ok(()),
+        }
+    }
 }
 
 impl Write for Buffer {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         let n = buf.len();
 
-        // Check if `buf` will fit into this `Buffer`.
-        if self.bytes.len() - self.written < n {
-            // Fill the buffer to prevent subsequent smaller writes.
-            self.written = self.bytes.len();
+        // Check if `buf` will fit into this `Buffer`, evicting the oldest
+        // messages if the policy allows it.
+        let fits = self.bytes.len() - self.written >= n
+            || (self.policy == BufferPolicy::DropOldest && self.evict_for(n)?);
 
-            Err(Error::new(
-                ErrorKind::Other,
-                "the disconnect buffer is full",
-            ))
-        } else {
+        if fits {
             // Append `buf` into the buffer.
             let range = self.written..self.written + n;
             self.bytes[range].copy_from_slice(&buf[..n]);
             self.written += n;
             Ok(n)
+        } else {
+            if self.policy == BufferPolicy::DropOldest {
+                // Discard the partial message, which can never fit.
+                self.written = self.flushed;
+            } else {
+                // Fill the buffer to prevent subsequent smaller writes.
+                self.written = self.bytes.len();
+            }
+
+            Err(Error::new(
+                ErrorKind::Other,
+                "the disconnect buffer is full",
+            ))
         }
     }
 
     fn flush(&mut self) -> io::Result<()> {
+        if self.written > self.flushed {
+            // Sync each flushed message so that it is on disk by the time the
+            // publish returns.
+            if let Some(file) = self.file.as_mut() {
+                file.write_all(&self.bytes[self.flushed..self.written])?;
+                file.sync_data()?;
+            }
+
+            self.ends.push_back(self.written);
+        }
         self.flushed = self.written;
         Ok(())
     }
 }
 
+/// Returns the end offsets of the complete PUB and HPUB messages at the
+/// start of `bytes`, stopping at the first one that is cut short or
+/// malformed.
+fn op_ends(bytes: &[u8]) -> VecDeque<usize> {
+    let mut ends = VecDeque::new();
+    let mut start = 0;
+    while let Some(end) = op_end(&bytes[start..]) {
+        start += end;
+        ends.push_back(start);
+    }
+    ends
+}
+
+/// Returns the length of the PUB or HPUB message at the start of `bytes`,
+/// or `None` if there is no complete one.
+fn op_end(bytes: &[u8]) -> Option<usize> {
+    let line_len = bytes.windows(2).position(|w| w == b"\r\n")?;
+    let line = std::str::from_utf8(&bytes[..line_len]).ok()?;
+    let mut args = line.split_whitespace();
+    if !matches!(args.next(), Some("PUB") | Some("HPUB")) {
+        return None;
+    }
+    let payload_len: usize = args.last()?.parse().ok()?;
+    let end = line_len + 2 + payload_len + 2;
+    if end > bytes.len() || &bytes[end - 2..end] != b"\r\n" {
+        return None;
+    }
+    Some(end)
+}
+
 /// A message wrapped in a struct with access to Client and all relevant methods
 #[allow(clippy::module_name_repetitions)]
 #[derive(Clone)]
@@ -913,6 +2712,11 @@ pub struct Message {
     /// Optional headers associated with this `Message`.
     pub headers: Option<Headers>,
 
+    /// The subject of the subscription this message was delivered to, which
+    /// tells which wildcard subscription matched `subject`. `None` if the
+    /// message was not received through a subscription.
+    pub subscription_subject: Option<String>,
+
     /// Client for publishing on the reply subject.
     #[doc(hidden)]
     pub(crate) client: Client,
@@ -921,6 +2725,9 @@ pub struct Message {
     /// using `JetStream`.
     #[doc(hidden)]
     pub double_acked: Arc<AtomicBool>,
+
+    /// The place of this message in the ack window of its subscription.
+    pub(crate) ack_slot: Option<Arc<AckSlot>>,
 }
 
 /// Only Into implementation, as Client would be lost while doing the transformation other way around
@@ -943,8 +2750,10 @@ impl From<crate::asynk::Message> for Message {
             reply: asynk.reply,
             data: asynk.data,
             headers: asynk.headers,
+            subscription_subject: asynk.subscription_subject,
             client: asynk.client,
             double_acked: asynk.double_acked,
+            ack_slot: asynk.ack_slot,
         }
     }
 }
@@ -958,14 +2767,42 @@ impl Message {
             reply: message.reply,
             data: message.data,
             headers: message.headers,
+            subscription_subject: message.subscription_subject,
             client,
             double_acked: Arc::new(AtomicBool::new(false)),
+            ack_slot: None,
         }
     }
 
+    /// Converts this message into an `asynk::Message`, preserving the client
+    /// used for responding and whether it has already been double-acked.
+    pub fn into_async(self) -> crate::asynk::Message {
+        crate::asynk::Message::from(self)
+    }
+
+    message_accessors!();
+
+    /// Returns the largest payload `decompressed` may produce.
+    #[cfg(feature = "compression")]
+    fn max_decompressed_size(&self) -> usize {
+        self.client.max_decompressed_size()
+    }
+
+    /// Creates a new unique inbox subscribed on the client this message was
+    /// received on. The inbox can be used as the reply subject of follow-up
+    /// messages in a multi-step conversation, whose responses are then
+    /// received on the returned subscription.
+    pub fn new_respond_inbox(&self) -> io::Result<(String, crate::Subscription)> {
+        self.client.new_respond_inbox()
+    }
+
     /// Respond to a request message.
+    ///
+    /// If the message has no reply subject, the `default_reply_subject` from
+    /// the connection options is used, if set.
     pub fn respond(&self, msg: impl AsRef<[u8]>) -> io::Result<()> {
-        match self.reply.as_ref() {
+        let default_reply = self.client.options.default_reply_subject.as_ref();
+        match self.reply.as_ref().or(default_reply) {
             None => Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
                 "no reply subject available",
@@ -990,6 +2827,18 @@ impl Message {
         false
     }
 
+    /// Determine if the message is likely a no responders response, even if
+    /// the server or a proxy in between did not include the `Status` header.
+    ///
+    /// In addition to the checks done by `is_no_responders`, this treats any
+    /// message without a payload and without headers as a no responders
+    /// response. This is a heuristic: a responder that legitimately replies
+    /// with an empty message will be reported as missing, so only use this
+    /// when empty replies are not expected.
+    pub fn looks_like_no_responders(&self) -> bool {
+        self.is_no_responders() || (self.data.is_empty() && self.headers.is_none())
+    }
+
     /// Acknowledge a `JetStream` message with a default acknowledgement.
     /// See `AckKind` documentation for details of what other types of
     /// acks are available. If you need to send a non-default ack, use
@@ -1002,7 +2851,35 @@ impl Message {
         if self.double_acked.load(Ordering::Acquire) {
             return Ok(());
         }
-        self.respond(b"")
+        let _guard = self.client.track_ack();
+        self.respond(b"")?;
+        self.release_ack_slot();
+        Ok(())
+    }
+
+    /// Acknowledge a `JetStream` message with a default acknowledgement and
+    /// block until the ack has been flushed to the server.
+    ///
+    /// The ways of acknowledging a message give increasing guarantees:
+    ///
+    /// - `ack` buffers the ack and returns immediately, so the ack may
+    ///   still be lost if the connection fails before it is written.
+    /// - `ack_sync` flushes the ack and waits for a round trip with the
+    ///   server, so the server has received the ack once it returns. The
+    ///   ack itself is not confirmed and may still be rejected.
+    /// - `double_ack` waits until the server confirms that it has processed
+    ///   the ack, retrying until it does.
+    ///
+    /// Returns immediately if this message has already been
+    /// double-acked.
+    pub fn ack_sync(&self, timeout: Duration) -> io::Result<()> {
+        if self.double_acked.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        let _guard = self.client.track_ack();
+        self.respond(b"")?;
+        self.release_ack_slot();
+        self.client.flush(timeout)
     }
 
     /// Acknowledge a `JetStream` message. See `AckKind` documentation for
@@ -1011,7 +2888,40 @@ impl Message {
     ///
     /// Does not check whether this message has already been double-acked.
     pub fn ack_kind(&self, ack_kind: crate::jetstream::AckKind) -> io::Result<()> {
-        self.respond(ack_kind)
+        let _guard = self.client.track_ack();
+        self.respond(ack_kind)?;
+        if !matches!(ack_kind, crate::jetstream::AckKind::Progress) {
+            self.release_ack_slot();
+        }
+        Ok(())
+    }
+
+    /// Acknowledge a `JetStream` message like `ack_kind`, making sure that
+    /// the ack carries the given token back to the server.
+    ///
+    /// Servers following ADR-15 may end the reply subject with a token,
+    /// which is returned to the server as part of the ack subject. This
+    /// fails with `InvalidInput`, without acking, if the reply subject does
+    /// not end with the given token. `double_ack` acks on the same reply
+    /// subject, so it preserves the token as well.
+    pub fn ack_kind_with_token(
+        &self,
+        ack_kind: crate::jetstream::AckKind,
+        token: &str,
+    ) -> io::Result<()> {
+        let info = self.jetstream_message_info().ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                "No reply subject available (not a JetStream message)",
+            )
+        })?;
+        if info.token != Some(token) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "the reply subject does not carry the given ack token",
+            ));
+        }
+        self.ack_kind(ack_kind)
     }
 
     /// Acknowledge a `JetStream` message and wait for acknowledgement from the server
@@ -1020,11 +2930,27 @@ impl Message {
     ///
     /// Returns immediately if this message has already been double-acked.
     pub fn double_ack(&self, ack_kind: crate::jetstream::AckKind) -> io::Result<()> {
-        if self.double_acked.load(Ordering::Acquire) {
-            return Ok(());
-        }
-        let original_reply = match self.reply.as_ref() {
-            None => {
+        self.double_ack_with_options(ack_kind, crate::jetstream::AckOptions::default())
+    }
+
+    /// Acknowledge a `JetStream` message like `double_ack`, using the given
+    /// options to time the attempts and cap how many are made.
+    ///
+    /// Fails with `TimedOut` if the server has not confirmed the ack after
+    /// `max_attempts` attempts, and with `NotConnected` once the client is
+    /// closed.
+    ///
+    /// Returns immediately if this message has already been double-acked.
+    pub fn double_ack_with_options(
+        &self,
+        ack_kind: crate::jetstream::AckKind,
+        options: crate::jetstream::AckOptions,
+    ) -> io::Result<()> {
+        if self.double_acked.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        let original_reply = match self.reply.as_ref() {
+            None => {
                 return Err(io::Error::new(
                     io::ErrorKind::InvalidInput,
                     "No reply subject available (not a JetStream message)",
@@ -1032,39 +2958,61 @@ impl Message {
             }
             Some(original_reply) => original_reply,
         };
+        let _guard = self.client.track_ack();
         let mut retries = 0;
         loop {
+            self.client.check_shutdown()?;
+            if matches!(options.max_attempts, Some(max) if retries >= max) {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    format!("double_ack was not confirmed after {} attempts", retries),
+                ));
+            }
             retries += 1;
             if retries == 2 {
                 log::warn!("double_ack is retrying until the server connection is reestablished");
             }
-            let ack_reply = format!("_INBOX.{}", nuid::next());
-            let sub_ret = self.client.subscribe(&ack_reply, None);
-            if sub_ret.is_err() {
-                std::thread::sleep(std::time::Duration::from_millis(100));
+            let (ack_reply, sub) = if let Ok(inbox) = self.client.new_respond_inbox() {
+                inbox
+            } else {
+                std::thread::sleep(options.retry_delay);
                 continue;
-            }
-            let (sid, receiver) = sub_ret?;
-            let sub =
-                crate::Subscription::new(sid, ack_reply.to_string(), receiver, self.client.clone());
+            };
 
             let pub_ret =
                 self.client
                     .publish(original_reply, Some(&ack_reply), None, ack_kind.as_ref());
             if pub_ret.is_err() {
-                std::thread::sleep(std::time::Duration::from_millis(100));
+                std::thread::sleep(options.retry_delay);
                 continue;
             }
-            if sub
-                .next_timeout(std::time::Duration::from_millis(100))
-                .is_ok()
-            {
+            if sub.next_timeout(options.attempt_timeout).is_ok() {
                 self.double_acked.store(true, Ordering::Release);
+                if !matches!(ack_kind, crate::jetstream::AckKind::Progress) {
+                    self.release_ack_slot();
+                }
                 return Ok(());
             }
         }
     }
 
+    /// Frees the place of this message in the ack window of its
+    /// subscription, if it was delivered through one.
+    fn release_ack_slot(&self) {
+        if let Some(slot) = self.ack_slot.as_ref() {
+            self.client.release_ack_slot(slot);
+        }
+    }
+
+    /// Returns `true` if the reply subject is a `JetStream` ack subject, which
+    /// means that this message was delivered by `JetStream`.
+    ///
+    /// Unlike `jetstream_message_info`, this only checks the prefix of the
+    /// reply subject without parsing it.
+    pub fn is_jetstream(&self) -> bool {
+        matches!(&self.reply, Some(reply) if reply.starts_with(JETSTREAM_ACK_PREFIX))
+    }
+
     /// Returns the `JetStream` message ID
     /// if this is a `JetStream` message.
     /// Returns `None` if this is not
@@ -1072,12 +3020,11 @@ impl Message {
     /// set.
     #[allow(clippy::eval_order_dependence)]
     pub fn jetstream_message_info(&self) -> Option<crate::jetstream::JetStreamMessageInfo<'_>> {
-        const PREFIX: &str = "$JS.ACK.";
-        const SKIP: usize = PREFIX.len();
+        const SKIP: usize = JETSTREAM_ACK_PREFIX.len();
 
         let mut reply: &str = self.reply.as_ref()?;
 
-        if !reply.starts_with(PREFIX) {
+        if !reply.starts_with(JETSTREAM_ACK_PREFIX) {
             return None;
         }
 
@@ -1166,7 +3113,7 @@ impl Message {
                     std::time::UNIX_EPOCH + offset
                 },
                 pending: try_parse!(),
-                token: if n_tokens >= 9 {
+                token: if n_tokens >= 10 {
                     Some(try_parse!(str))
                 } else {
                     None
@@ -1231,8 +3178,1407 @@ impl fmt::Debug for Message {
             .field("subject", &self.subject)
             .field("headers", &self.headers)
             .field("reply", &self.reply)
+            .field("subscription_subject", &self.subscription_subject)
             .field("length", &self.data.len())
             .field("double_ack", &self.double_acked)
             .finish()
     }
 }
+
+#[cfg(test)]
+mod buffer {
+    use super::*;
+
+    #[test]
+    fn persisted_messages_are_replayed() {
+        let path = std::env::temp_dir().join(format!("nats-buffer-{}", nuid::next()));
+
+        let mut buffer = Buffer::new(64, BufferPolicy::RejectNew, Some(&path)).unwrap();
+        buffer.write_all(b"PUB foo 3\r\nbar\r\n").unwrap();
+        buffer.flush().unwrap();
+
+        // A partially written message is not persisted.
+        buffer.write_all(b"PUB foo").unwrap();
+        drop(buffer);
+
+        let mut buffer = Buffer::new(8, BufferPolicy::RejectNew, Some(&path)).unwrap();
+        assert_eq!(buffer.clear(), b"PUB foo 3\r\nbar\r\n");
+
+        buffer.discard_persisted().unwrap();
+        drop(buffer);
+
+        let mut buffer = Buffer::new(8, BufferPolicy::RejectNew, Some(&path)).unwrap();
+        assert!(buffer.clear().is_empty());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn incomplete_persisted_message_is_truncated() {
+        let path = std::env::temp_dir().join(format!("nats-buffer-{}", nuid::next()));
+
+        // A crash cut the last message short while it was being written.
+        std::fs::write(&path, b"PUB foo 3\r\nbar\r\nPUB foo 10\r\nbaz").unwrap();
+
+        let mut buffer = Buffer::new(64, BufferPolicy::RejectNew, Some(&path)).unwrap();
+        assert_eq!(buffer.len(), 1);
+        assert_eq!(buffer.clear(), b"PUB foo 3\r\nbar\r\n");
+        drop(buffer);
+        assert_eq!(std::fs::read(&path).unwrap(), b"PUB foo 3\r\nbar\r\n");
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reject_new_when_full() {
+        let mut buffer = Buffer::new(24, BufferPolicy::RejectNew, None).unwrap();
+        buffer.write_all(b"PUB foo 3\r\nbar\r\n").unwrap();
+        buffer.flush().unwrap();
+
+        assert!(buffer.write_all(b"PUB foo 3\r\nbaz\r\n").is_err());
+        assert!(buffer.write_all(b"PUB a 0\r\n\r\n").is_err());
+        assert_eq!(buffer.clear(), b"PUB foo 3\r\nbar\r\n");
+    }
+
+    #[test]
+    fn drop_oldest_when_full() {
+        let mut buffer = Buffer::new(40, BufferPolicy::DropOldest, None).unwrap();
+        for payload in &[b"one", b"two", b"six"] {
+            buffer.write_all(b"PUB foo 3\r\n").unwrap();
+            buffer.write_all(&payload[..]).unwrap();
+            buffer.write_all(b"\r\n").unwrap();
+            buffer.flush().unwrap();
+        }
+        assert_eq!(buffer.dropped(), 1);
+        assert_eq!(buffer.clear(), b"PUB foo 3\r\ntwo\r\nPUB foo 3\r\nsix\r\n");
+        assert_eq!(buffer.dropped(), 0);
+
+        // A message larger than the whole buffer is rejected without
+        // evicting anything.
+        buffer.write_all(b"PUB foo 3\r\nbar\r\n").unwrap();
+        buffer.flush().unwrap();
+        assert!(buffer.write_all(&[0; 41]).is_err());
+        buffer.write_all(b"PUB foo 3\r\nbaz\r\n").unwrap();
+        buffer.flush().unwrap();
+        assert_eq!(buffer.clear(), b"PUB foo 3\r\nbar\r\nPUB foo 3\r\nbaz\r\n");
+    }
+
+    #[test]
+    fn counts_complete_messages() {
+        let path = std::env::temp_dir().join(format!("nats-buffer-{}", nuid::next()));
+
+        let mut buffer = Buffer::new(128, BufferPolicy::DropOldest, Some(&path)).unwrap();
+        buffer.write_all(b"PUB foo 4\r\na\r\nb\r\n").unwrap();
+        buffer.flush().unwrap();
+        buffer
+            .write_all(b"HPUB foo bar 12 14\r\nNATS/1.0\r\n\r\nhi\r\n")
+            .unwrap();
+        buffer.flush().unwrap();
+        buffer.write_all(b"PUB foo").unwrap();
+        assert_eq!(buffer.len(), 2);
+        drop(buffer);
+
+        // Replayed messages are split at their boundaries, so the oldest one
+        // is evicted on its own.
+        let mut buffer = Buffer::new(48, BufferPolicy::DropOldest, Some(&path)).unwrap();
+        assert_eq!(buffer.len(), 2);
+        buffer.write_all(b"PUB foo 0\r\n\r\n").unwrap();
+        buffer.flush().unwrap();
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(
+            buffer.clear(),
+            &b"HPUB foo bar 12 14\r\nNATS/1.0\r\n\r\nhi\r\nPUB foo 0\r\n\r\n"[..]
+        );
+        assert_eq!(buffer.len(), 0);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod flusher {
+    use super::*;
+    use crate::mock_server::{MockServer, Step};
+
+    #[test]
+    fn respawned_when_dead() {
+        let (pub_tx, pub_rx) = channel::unbounded();
+        let url = MockServer::new().serve(move |_, line| {
+            if line.starts_with("PUB foo") {
+                pub_tx.send(()).ok();
+            }
+            Ok(Step::Continue)
+        });
+
+        let client = Client::connect(&url, Options::default()).unwrap();
+
+        // Simulate the flusher thread dying by dropping its receiver.
+        let (flush_kicker, _) = channel::bounded(1);
+        client.state.write.lock().flush_kicker = flush_kicker;
+
+        client.publish("foo", None, None, b"bar").unwrap();
+        pub_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        client.close();
+    }
+}
+
+#[cfg(test)]
+mod sids {
+    use super::*;
+    use crate::mock_server::MockServer;
+    use std::collections::HashSet;
+
+    #[test]
+    fn unique_under_concurrent_subscribes() {
+        let url = MockServer::new().answer_pings();
+        let client = Client::connect(&url, Options::default()).unwrap();
+
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let client = client.clone();
+                thread::spawn(move || {
+                    let mut sids = Vec::new();
+                    for _ in 0..200 {
+                        let (sid, _receiver) = client.subscribe("foo", None).unwrap();
+                        client.unsubscribe(sid).unwrap();
+                        sids.push(sid);
+                    }
+                    sids
+                })
+            })
+            .collect();
+
+        let mut seen = HashSet::new();
+        for thread in threads {
+            for sid in thread.join().unwrap() {
+                assert!(seen.insert(sid), "sid {} was assigned twice", sid);
+            }
+        }
+        assert_eq!(seen.len(), 8 * 200);
+        assert!(client.state.read.lock().subscriptions.is_empty());
+
+        client.close();
+    }
+}
+
+#[cfg(test)]
+mod pongs {
+    use super::*;
+    use crate::mock_server::MockServer;
+
+    #[test]
+    fn flush_reuses_pong_slots() {
+        let url = MockServer::new().answer_pings();
+        let client = Client::connect(&url, Options::default()).unwrap();
+
+        // Sequential flushes allocate a single slot and keep reusing it.
+        for _ in 0..100 {
+            client.flush(Duration::from_secs(10)).unwrap();
+        }
+        client.ping(Duration::from_secs(10)).unwrap();
+        assert_eq!(client.pong_slots.lock().len(), 1);
+
+        // Concurrent flushes allocate at most one slot per waiter.
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let client = client.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        client.flush(Duration::from_secs(10)).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let slots = client.pong_slots.lock().len();
+        assert!((1..=4).contains(&slots), "{} slots allocated", slots);
+
+        client.close();
+    }
+}
+
+#[cfg(test)]
+mod error_callback {
+    use super::*;
+    use crate::mock_server::{MockServer, Step};
+
+    #[test]
+    fn replaced_at_runtime() {
+        // Report an error before answering every PING after the handshake.
+        let mut pings = 0;
+        let url = MockServer::new().serve(move |session, line| {
+            if line == "PING" {
+                if pings > 0 {
+                    session.write(format!("-ERR 'error {}'\r\n", pings))?;
+                }
+                pings += 1;
+            }
+            Ok(Step::Continue)
+        });
+
+        let (first_tx, first_rx) = channel::unbounded();
+        let options = Options::new().error_callback(move |err| {
+            first_tx.send(err.to_string()).ok();
+        });
+        let client = Client::connect(&url, options).unwrap();
+
+        client.flush(Duration::from_secs(10)).unwrap();
+        assert_eq!(first_rx.try_recv().unwrap(), "error 1");
+
+        let (second_tx, second_rx) = channel::unbounded();
+        client.set_error_callback(move |err| {
+            second_tx.send(err.to_string()).ok();
+        });
+
+        client.flush(Duration::from_secs(10)).unwrap();
+        assert_eq!(second_rx.try_recv().unwrap(), "error 2");
+        assert!(first_rx.try_recv().is_err());
+
+        client.close();
+    }
+}
+
+#[cfg(test)]
+mod stale_connection {
+    use super::*;
+    use crate::mock_server::{MockServer, Step};
+
+    #[test]
+    fn reconnects_without_reporting() {
+        // Report the first connection as stale once subscribed, without
+        // closing it.
+        let url = MockServer::new().serve(|session, line| {
+            if line.starts_with("SUB ") && session.index == 0 {
+                session.write(b"-ERR 'Stale Connection'\r\n")?;
+            }
+            Ok(Step::Continue)
+        });
+
+        let (err_tx, err_rx) = channel::unbounded();
+        let (reconnect_tx, reconnect_rx) = channel::unbounded();
+        let options = Options::new()
+            .error_callback(move |err| err_tx.send(err.to_string()).unwrap())
+            .reconnect_callback(move || reconnect_tx.send(()).unwrap());
+        let client = Client::connect(&url, options).unwrap();
+        client.subscribe("foo", None).unwrap();
+
+        let timeout = Duration::from_secs(5);
+        reconnect_rx.recv_timeout(timeout).unwrap();
+        client.flush(timeout).unwrap();
+        assert!(err_rx.try_recv().is_err());
+
+        client.close();
+    }
+}
+
+#[cfg(test)]
+mod keepalive {
+    use super::*;
+    use crate::mock_server::MockServer;
+
+    #[test]
+    fn disabled_still_flushes() {
+        let (url, pub_rx) = MockServer::new().reporting("PUB ");
+        let options = Options::new().disable_keepalive();
+        let client = Client::connect(&url, options).unwrap();
+
+        // The flusher still writes out published messages without an explicit
+        // flush.
+        client.publish("foo", None, None, b"bar").unwrap();
+        let line = pub_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(line, "PUB foo 3");
+
+        client.close();
+    }
+}
+
+#[cfg(test)]
+mod shutdown {
+    use super::*;
+    use crate::mock_server::{MockServer, ROOT_CA};
+
+    #[test]
+    fn drain_and_close_joins_threads() {
+        // The server never sends anything but PONGs, so the client thread
+        // only exits if the connection is shut down.
+        let url = MockServer::new().answer_pings();
+
+        let closed = Arc::new(AtomicBool::new(false));
+        let disconnected = Arc::new(AtomicBool::new(false));
+        let options = Options::new()
+            .close_callback({
+                let closed = closed.clone();
+                move || closed.store(true, Ordering::SeqCst)
+            })
+            .disconnect_callback({
+                let disconnected = disconnected.clone();
+                move || disconnected.store(true, Ordering::SeqCst)
+            });
+        let client = Client::connect(&url, options).unwrap();
+        client.publish("foo", None, None, b"bar").unwrap();
+
+        let handle = client.drain_and_close(Duration::from_secs(10)).unwrap();
+        handle.join(Duration::from_secs(10)).unwrap();
+
+        assert_eq!(*client.threads.running.lock(), 0);
+        assert!(closed.load(Ordering::SeqCst));
+        assert!(!disconnected.load(Ordering::SeqCst));
+    }
+
+    /// Fills the socket buffers, then leaves a message in the writer.
+    fn fill(client: &Client) {
+        let mut write = client.state.write.lock();
+        let writer = write.writer.as_mut().unwrap();
+        let mut stream = writer.get_ref();
+        stream
+            .set_write_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        stream.write_all(&vec![0; 64 * 1024 * 1024]).unwrap_err();
+        stream.set_write_timeout(None).unwrap();
+        writer.write_all(b"PUB foo 3\r\nbar\r\n").unwrap();
+    }
+
+    #[test]
+    fn drain_and_close_closes_when_flush_fails() {
+        let (url, done_tx) = MockServer::new().stall_after_handshake();
+        let options = Options::new().shutdown_flush_timeout(Duration::from_millis(100));
+        let client = Client::connect(&url, options).unwrap();
+        fill(&client);
+
+        client
+            .drain_and_close(Duration::from_millis(100))
+            .unwrap_err();
+        assert_eq!(
+            client.check_shutdown().unwrap_err().kind(),
+            ErrorKind::NotConnected
+        );
+
+        done_tx.send(()).unwrap();
+    }
+
+    /// Closes a client whose server stopped reading, with a message left in
+    /// the writer, and checks that the final flush gives up in time.
+    fn close_stalled(server: MockServer, options: Options) {
+        let (url, done_tx) = server.stall_after_handshake();
+        let options = options.shutdown_flush_timeout(Duration::from_millis(100));
+        let client = Client::connect(&url, options).unwrap();
+        fill(&client);
+
+        let start = Instant::now();
+        client.close();
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        done_tx.send(()).unwrap();
+    }
+
+    #[test]
+    fn close_gives_up_on_stalled_socket() {
+        close_stalled(MockServer::new(), Options::new());
+    }
+
+    #[test]
+    fn close_gives_up_on_stalled_tls_stream() {
+        let options = Options::new().add_root_certificate(ROOT_CA);
+        close_stalled(MockServer::with_tls(), options);
+    }
+}
+
+#[cfg(test)]
+mod bounded_wildcard {
+    use super::*;
+    use crate::mock_server::{MockServer, Step};
+
+    #[test]
+    fn drops_deep_subjects() {
+        // Publish to every subscription.
+        let url = MockServer::new().serve(|session, line| {
+            if line.starts_with("SUB ") {
+                let sid = line.rsplit(' ').next().unwrap();
+                session.write(format!(
+                    "MSG events.a.b {sid} 1\r\nx\r\nMSG events.a {sid} 1\r\ny\r\n",
+                    sid = sid
+                ))?;
+            }
+            Ok(Step::Continue)
+        });
+
+        let client = Client::connect(&url, Options::default()).unwrap();
+
+        let err = client
+            .subscribe_bounded_wildcard("events.a.>", 2)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        let (_sid, receiver) = client.subscribe_bounded_wildcard("events.>", 2).unwrap();
+        client.flush(Duration::from_secs(10)).unwrap();
+
+        let msg = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(msg.subject, "events.a");
+        assert!(receiver.try_recv().is_err());
+
+        client.close();
+    }
+}
+
+#[cfg(test)]
+mod connect_timeout {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn unresponsive_server() {
+        // A listener that never accepts, so the INFO never arrives.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("nats://{}", listener.local_addr().unwrap());
+
+        let start = Instant::now();
+        let options = Options::new().connect_timeout(Duration::from_millis(200));
+        match Client::connect(&url, options) {
+            Ok(_) => panic!("connected to an unresponsive server"),
+            Err(err) => assert_eq!(err.kind(), ErrorKind::TimedOut),
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
+
+#[cfg(test)]
+mod buffer_capacity {
+    use super::*;
+    use crate::mock_server::{MockServer, Step};
+
+    #[test]
+    fn tiny_buffers() {
+        // Echo published messages back to the first subscription.
+        let url = MockServer::new().serve(|session, line| {
+            if let Some(args) = line.strip_prefix("PUB ") {
+                let subject = args.split(' ').next().unwrap();
+                let payload = session.read_line()?;
+                session.write(format!(
+                    "MSG {} 1 {}\r\n{}\r\n",
+                    subject,
+                    payload.len(),
+                    payload
+                ))?;
+            }
+            Ok(Step::Continue)
+        });
+
+        let options = Options::new()
+            .read_buffer_capacity(0)
+            .write_buffer_capacity(8);
+        let client = Client::connect(&url, options).unwrap();
+        let (_sid, receiver) = client.subscribe("foo", None).unwrap();
+
+        let payload = "a payload larger than both buffers";
+        client
+            .publish("foo", None, None, payload.as_bytes())
+            .unwrap();
+        client.flush(Duration::from_secs(10)).unwrap();
+
+        let msg = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(msg.data, payload.as_bytes());
+
+        client.close();
+    }
+}
+
+#[cfg(test)]
+mod connect_callback {
+    use super::*;
+    use crate::mock_server::{MockServer, Step};
+
+    #[test]
+    fn runs_once() {
+        // Drop the first connection after the handshake.
+        let url = MockServer::new().serve(|session, line| {
+            if line == "PING" && session.index == 0 {
+                session.write(b"PONG\r\n")?;
+                return Ok(Step::Disconnect);
+            }
+            Ok(Step::Continue)
+        });
+
+        let (connect_tx, connect_rx) = channel::unbounded();
+        let (reconnect_tx, reconnect_rx) = channel::unbounded();
+        let options = Options::new()
+            .connect_callback(move || connect_tx.send(()).unwrap())
+            .reconnect_callback(move || reconnect_tx.send(()).unwrap());
+        let client = Client::connect(&url, options).unwrap();
+
+        reconnect_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(connect_rx.try_iter().count(), 1);
+
+        client.close();
+    }
+
+    #[test]
+    fn can_flush() {
+        let url = MockServer::new().answer_pings();
+
+        let (client_tx, client_rx) = channel::bounded::<Client>(1);
+        let (flushed_tx, flushed_rx) = channel::bounded(1);
+        let options = Options::new().connect_callback(move || {
+            let client = client_rx.recv().unwrap();
+            flushed_tx
+                .send(client.flush(Duration::from_secs(5)))
+                .unwrap();
+        });
+        let client = Client::connect(&url, options).unwrap();
+        client_tx.send(client.clone()).unwrap();
+
+        flushed_rx
+            .recv_timeout(Duration::from_secs(10))
+            .unwrap()
+            .unwrap();
+
+        client.close();
+    }
+}
+
+#[cfg(test)]
+mod ack_window {
+    use super::*;
+    use crate::mock_server::{MockServer, Step};
+
+    #[test]
+    fn holds_messages_until_acked() {
+        // Deliver four messages once subscribed, and report acks.
+        let (ack_tx, ack_rx) = channel::unbounded();
+        let url = MockServer::new().serve(move |session, line| {
+            if line.starts_with("SUB ") {
+                for i in 1..=4 {
+                    session.write(format!("MSG jobs 1 $JS.ACK.jobs.{} 1\r\n{}\r\n", i, i))?;
+                }
+            } else if line.starts_with("PUB $JS.ACK.") {
+                ack_tx.send(line.to_string()).ok();
+            }
+            Ok(Step::Continue)
+        });
+
+        let client = Client::connect(&url, Options::new()).unwrap();
+        let err = client.subscribe_with_ack_window("jobs", 0).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        let (_, messages) = client.subscribe_with_ack_window("jobs", 2).unwrap();
+        let timeout = Duration::from_secs(5);
+        let first = messages.recv_timeout(timeout).unwrap();
+        let second = messages.recv_timeout(timeout).unwrap();
+        assert_eq!((&first.data[..], &second.data[..]), (&b"1"[..], &b"2"[..]));
+        client.flush(timeout).unwrap();
+        assert!(messages.try_recv().is_err());
+
+        // Progress acks and repeated acks do not free a place.
+        first.ack_kind(crate::jetstream::AckKind::Progress).unwrap();
+        client.flush(timeout).unwrap();
+        assert!(messages.try_recv().is_err());
+        first.ack().unwrap();
+        first.clone().ack().unwrap();
+        assert_eq!(messages.recv_timeout(timeout).unwrap().data, b"3");
+        assert!(messages.try_recv().is_err());
+
+        second.ack().unwrap();
+        assert_eq!(messages.recv_timeout(timeout).unwrap().data, b"4");
+        assert_eq!(
+            ack_rx.recv_timeout(timeout).unwrap(),
+            "PUB $JS.ACK.jobs.1 4"
+        );
+
+        client.close();
+    }
+
+    /// Serves two JetStream messages once subscribed.
+    fn serve_two() -> String {
+        MockServer::new().serve(|session, line| {
+            if line.starts_with("SUB ") {
+                for i in 1..=2 {
+                    session.write(format!("MSG jobs 1 $JS.ACK.jobs.{} 1\r\n{}\r\n", i, i))?;
+                }
+            }
+            Ok(Step::Continue)
+        })
+    }
+
+    #[test]
+    fn place_kept_through_async_conversion() {
+        let client = Client::connect(&serve_two(), Options::new()).unwrap();
+        let (_, messages) = client.subscribe_with_ack_window("jobs", 1).unwrap();
+        let timeout = Duration::from_secs(5);
+
+        let first = messages.recv_timeout(timeout).unwrap().into_async();
+        Message::from(first).ack().unwrap();
+        assert_eq!(messages.recv_timeout(timeout).unwrap().data, b"2");
+
+        client.close();
+    }
+
+    #[test]
+    fn overflowing_message_gives_place_back() {
+        let client = Client::connect(&serve_two(), Options::new()).unwrap();
+        let (_, messages) = client
+            .subscribe_with_filter("jobs", None, None, Some(1), Some(2), None)
+            .unwrap();
+        let timeout = Duration::from_secs(5);
+        client.flush(timeout).unwrap();
+
+        // The second message did not fit into the channel and was diverted.
+        let first = messages.recv_timeout(timeout).unwrap();
+        first.ack().unwrap();
+        let read = client.state.read.lock();
+        let window = read.subscriptions[&1].ack_window.as_ref().unwrap();
+        assert_eq!(window.in_flight, 0);
+        drop(read);
+
+        client.close();
+    }
+}
+
+#[cfg(test)]
+mod ack_tracking {
+    use super::*;
+    use crate::jetstream::{AckKind, AckOptions};
+    use crate::mock_server::{MockServer, Step};
+
+    #[test]
+    fn buffered_ack_sent_by_drain() {
+        // Deliver a message once subscribed, and report PUBs.
+        let (pub_tx, pub_rx) = channel::unbounded();
+        let url = MockServer::new().serve(move |session, line| {
+            if line.starts_with("SUB ") {
+                session.write(b"MSG jobs 1 $JS.ACK.jobs.1 1\r\n1\r\n")?;
+            } else if line.starts_with("PUB ") {
+                pub_tx.send(line.to_string()).ok();
+            }
+            Ok(Step::Continue)
+        });
+
+        let client = Client::connect(&url, Options::new()).unwrap();
+        let (_, messages) = client.subscribe("jobs", None).unwrap();
+        let msg = messages.recv_timeout(Duration::from_secs(5)).unwrap();
+        msg.ack().unwrap();
+
+        // Draining flushes the ack before closing.
+        client.drain_jetstream(Duration::from_secs(5)).unwrap();
+        assert_eq!(pub_rx.try_recv().unwrap(), "PUB $JS.ACK.jobs.1 0");
+    }
+
+    #[test]
+    fn double_ack_retries_stop_on_close() {
+        // Deliver a message once subscribed, and never confirm acks.
+        let url = MockServer::new().serve(|session, line| {
+            if line.starts_with("SUB ") {
+                session.write(b"MSG jobs 1 $JS.ACK.jobs.1 1\r\n1\r\n")?;
+            }
+            Ok(Step::Continue)
+        });
+
+        let client = Client::connect(&url, Options::new()).unwrap();
+        let (_, messages) = client.subscribe("jobs", None).unwrap();
+        let msg = messages.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        let acker = thread::spawn(move || {
+            let options = AckOptions {
+                attempt_timeout: Duration::from_millis(50),
+                ..AckOptions::default()
+            };
+            msg.double_ack_with_options(AckKind::Ack, options)
+        });
+        thread::sleep(Duration::from_millis(200));
+
+        // Closing stops the retries and releases the in-flight ack.
+        client.close();
+        let err = acker.join().unwrap().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotConnected);
+        assert_eq!(*client.acks.in_flight.lock(), 0);
+    }
+}
+
+#[cfg(test)]
+mod ack_token {
+    use super::*;
+    use crate::jetstream::AckKind;
+    use crate::mock_server::{MockServer, Step};
+
+    const REPLY: &str = "$JS.ACK.hub.ACCHASH.jobs.worker.1.5.3.1700000000000000000.0.tok42";
+
+    #[test]
+    fn acks_on_the_reply_subject_carrying_the_token() {
+        // Deliver a message with a token in its reply subject once
+        // subscribed, and report PUBs.
+        let (pub_tx, pub_rx) = channel::unbounded();
+        let url = MockServer::new().serve(move |session, line| {
+            if line.starts_with("SUB ") {
+                session.write(format!("MSG jobs 1 {} 1\r\n1\r\n", REPLY))?;
+            } else if line.starts_with("PUB ") {
+                pub_tx.send(line.to_string()).ok();
+            }
+            Ok(Step::Continue)
+        });
+
+        let client = Client::connect(&url, Options::new()).unwrap();
+        let (_, messages) = client.subscribe("jobs", None).unwrap();
+        let msg = messages.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(msg.jetstream_message_info().unwrap().token, Some("tok42"));
+
+        let err = msg.ack_kind_with_token(AckKind::Ack, "bogus").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        msg.ack_kind_with_token(AckKind::Ack, "tok42").unwrap();
+        let line = pub_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(line, format!("PUB {} 4", REPLY));
+        assert!(pub_rx.try_recv().is_err());
+
+        client.close();
+    }
+}
+
+#[cfg(test)]
+mod publish_reliable {
+    use super::*;
+    use crate::mock_server::{MockServer, Step};
+
+    #[test]
+    fn retried_after_reconnect() {
+        // Drop the first connection once it receives a PUB, and report PUBs
+        // on the second one.
+        let (pub_tx, pub_rx) = channel::unbounded();
+        let url = MockServer::new().serve(move |session, line| {
+            if line.starts_with("PUB ") {
+                if session.index == 0 {
+                    return Ok(Step::Disconnect);
+                }
+                pub_tx.send(line.to_string()).ok();
+            }
+            Ok(Step::Continue)
+        });
+
+        let client = Client::connect(&url, Options::new()).unwrap();
+        client
+            .publish_reliable("foo", None, None, b"bar", Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(pub_rx.try_recv().unwrap(), "PUB foo 3");
+
+        client.close();
+    }
+}
+
+#[cfg(test)]
+mod nonce {
+    use super::*;
+    use crate::mock_server::{self, MockServer, Step};
+
+    fn info(nonce: &str) -> String {
+        mock_server::info(&[("auth_required", true.into()), ("nonce", nonce.into())])
+    }
+
+    #[test]
+    fn resigned_when_changed() {
+        // Challenge the client with a new nonce after the handshake, and
+        // report CONNECT messages.
+        let (connect_tx, connect_rx) = channel::unbounded();
+        let mut handshake = true;
+        let url = MockServer::with_info(info("first")).serve(move |session, line| {
+            if line == "PING" && handshake {
+                handshake = false;
+                session.write(b"PONG\r\n")?;
+                session.write(info("first"))?;
+                session.write(info("second"))?;
+                return Ok(Step::Handled);
+            }
+            if let Some(args) = line.strip_prefix("CONNECT ") {
+                let connect: serde_json::Value = serde_json::from_str(args).unwrap();
+                connect_tx.send(connect["sig"].clone()).ok();
+            }
+            Ok(Step::Continue)
+        });
+
+        let options = Options::new().jwt("user.jwt".to_string(), |nonce| {
+            let mut sig = b"signed:".to_vec();
+            sig.extend_from_slice(nonce);
+            sig
+        });
+        let client = Client::connect(&url, options).unwrap();
+
+        let timeout = Duration::from_secs(5);
+        let sig = connect_rx.recv_timeout(timeout).unwrap();
+        assert_eq!(sig, base64_url::encode(b"signed:first").as_str());
+
+        // The repeated nonce is ignored, and the new one is signed.
+        let sig = connect_rx.recv_timeout(timeout).unwrap();
+        assert_eq!(sig, base64_url::encode(b"signed:second").as_str());
+        client.flush(timeout).unwrap();
+        assert!(connect_rx.try_recv().is_err());
+
+        client.close();
+    }
+}
+
+#[cfg(test)]
+mod streaming_subscription {
+    use super::*;
+    use crate::mock_server::{MockServer, Step};
+
+    #[test]
+    fn payload_streamed_past_size_limit() {
+        // Once both subscriptions are made, send a large message followed by
+        // a small one.
+        let payload: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8).collect();
+        let blob = payload.clone();
+        let url = MockServer::new().serve(move |session, line| {
+            if line.starts_with("SUB small ") {
+                session.write(format!(
+                    "HMSG blobs 1 12 {}\r\nNATS/1.0\r\n\r\n",
+                    blob.len() + 12
+                ))?;
+                session.write(&blob)?;
+                session.write(b"\r\nMSG small 2 2\r\nok\r\n")?;
+            }
+            Ok(Step::Continue)
+        });
+
+        let options = Options::new().max_incoming_message_size(1024);
+        let client = Client::connect(&url, options).unwrap();
+        let (_, streamed) = client.subscribe_streaming("blobs").unwrap();
+        let (_, messages) = client.subscribe("small", None).unwrap();
+
+        let timeout = Duration::from_secs(5);
+        let msg = streamed.recv_timeout(timeout).unwrap();
+        assert_eq!((msg.subject.as_str(), msg.len), ("blobs", payload.len()));
+        assert!(msg.headers.is_some());
+        let mut data = Vec::new();
+        msg.into_reader().read_to_end(&mut data).unwrap();
+        assert!(data == payload);
+        assert_eq!(messages.recv_timeout(timeout).unwrap().data, b"ok");
+
+        client.close();
+    }
+}
+
+#[cfg(test)]
+mod flush_if_pending {
+    use super::*;
+    use crate::mock_server::MockServer;
+
+    #[test]
+    fn skips_round_trip_when_idle() {
+        let (url, ping_rx) = MockServer::new().reporting("PING");
+        let client = Client::connect(&url, Options::new()).unwrap();
+        let timeout = Duration::from_secs(5);
+        client.flush(timeout).unwrap();
+        while ping_rx.try_recv().is_ok() {}
+
+        // Only the strict flush makes a round trip.
+        client.flush_if_pending(timeout).unwrap();
+        client.flush(timeout).unwrap();
+        assert_eq!(ping_rx.try_iter().count(), 1);
+
+        client.close();
+        let err = client.flush_if_pending(timeout).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotConnected);
+    }
+}
+
+#[cfg(test)]
+mod connect_lazy {
+    use super::*;
+    use crate::mock_server::MockServer;
+
+    #[test]
+    fn publishes_buffered_until_connected() {
+        // The server only starts the handshake once it is served.
+        let server = MockServer::new();
+        let client = Client::connect_lazy(&server.url(), Options::new()).unwrap();
+        client.publish("foo", None, None, b"early").unwrap();
+        assert_eq!(client.buffered_publish_count(), 1);
+
+        let (_, pub_rx) = server.reporting("PUB ");
+        let timeout = Duration::from_secs(5);
+        assert_eq!(pub_rx.recv_timeout(timeout).unwrap(), "PUB foo 5");
+        assert_eq!(client.buffered_publish_count(), 0);
+
+        client.close();
+    }
+}
+
+#[cfg(test)]
+mod dead_letter {
+    use super::*;
+    use crate::mock_server::{MockServer, Step};
+
+    #[test]
+    fn overflow_diverted() {
+        // Send three replies to the inbox once subscribed.
+        let url = MockServer::new().serve(|session, line| {
+            if line.starts_with("SUB ") {
+                let inbox = line.split(' ').nth(1).unwrap();
+                for i in 1..=3 {
+                    session.write(format!("MSG {} 1 1\r\n{}\r\n", inbox, i))?;
+                }
+            }
+            Ok(Step::Continue)
+        });
+
+        let (dead_tx, dead_rx) = channel::unbounded();
+        let options = Options::new()
+            .request_inbox_capacity(1)
+            .dead_letter_callback(move |msg| dead_tx.send(msg.data.clone()).unwrap());
+        let client = Client::connect(&url, options).unwrap();
+        let (_, sub) = client.new_respond_inbox().unwrap();
+
+        let timeout = Duration::from_secs(5);
+        assert_eq!(dead_rx.recv_timeout(timeout).unwrap(), b"2");
+        assert_eq!(dead_rx.recv_timeout(timeout).unwrap(), b"3");
+        assert_eq!(sub.next_timeout(timeout).unwrap().data, b"1");
+
+        client.close();
+    }
+}
+
+#[cfg(test)]
+mod slow_consumer_risk {
+    use super::*;
+    use crate::mock_server::{self, MockServer, Step};
+
+    #[test]
+    fn backlog_against_limits() {
+        // Advertise a byte limit only, and send two messages once
+        // subscribed.
+        let info = mock_server::info(&[("max_pending", 1000.into())]);
+        let url = MockServer::with_info(info).serve(|session, line| {
+            if line.starts_with("SUB ") {
+                session.write(b"MSG foo 1 1\r\na\r\nMSG foo 1 1\r\nb\r\n")?;
+            }
+            Ok(Step::Continue)
+        });
+
+        let client = Client::connect(&url, Options::new().pending_limits(0, 4)).unwrap();
+        assert_eq!(
+            client.pending_limits(),
+            PendingLimits {
+                bytes: 1000,
+                messages: 4,
+            }
+        );
+        assert_eq!(client.slow_consumer_risk(), 0.0);
+
+        // The messages arrive before the PONG of the flush.
+        let (_, messages) = client.subscribe("foo", None).unwrap();
+        client.flush(Duration::from_secs(5)).unwrap();
+        assert_eq!(client.slow_consumer_risk(), 0.5);
+
+        messages.recv().unwrap();
+        messages.recv().unwrap();
+        assert_eq!(client.slow_consumer_risk(), 0.0);
+
+        client.close();
+    }
+
+    #[test]
+    fn payload_bytes_against_limit() {
+        // Send two messages once subscribed.
+        let url = MockServer::new().serve(|session, line| {
+            if line.starts_with("SUB ") {
+                session.write(b"MSG foo 1 4\r\nabcd\r\nMSG foo 1 2\r\nef\r\n")?;
+            }
+            Ok(Step::Continue)
+        });
+
+        let client = Client::connect(&url, Options::new().pending_limits(8, 0)).unwrap();
+
+        // Unflushed publishes are not part of the backlog.
+        client.publish("bar", None, None, b"0123456789").unwrap();
+        assert_eq!(client.slow_consumer_risk(), 0.0);
+
+        let (_, messages) = client.subscribe("foo", None).unwrap();
+        client.flush(Duration::from_secs(5)).unwrap();
+        assert_eq!(client.slow_consumer_risk(), 0.75);
+
+        messages.recv().unwrap();
+        assert_eq!(client.slow_consumer_risk(), 0.25);
+
+        client.close();
+    }
+}
+
+#[cfg(test)]
+mod auto_close_idle {
+    use super::*;
+    use crate::mock_server::{MockServer, Step};
+
+    #[test]
+    fn closes_once_unused() {
+        let url = MockServer::new().answer_pings();
+
+        let (closed_tx, closed_rx) = channel::unbounded();
+        let options = Options::new()
+            .auto_close_idle(Duration::from_millis(200))
+            .close_callback(move || closed_tx.send(()).unwrap());
+        let client = Client::connect(&url, options).unwrap();
+
+        // A subscription keeps the client open.
+        let (sid, _messages) = client.subscribe("foo", None).unwrap();
+        assert!(closed_rx.recv_timeout(Duration::from_millis(500)).is_err());
+
+        // Publishing restarts the idle period.
+        client.unsubscribe(sid).unwrap();
+        for _ in 0..4 {
+            thread::sleep(Duration::from_millis(100));
+            client.publish("foo", None, None, b"bar").unwrap();
+        }
+        assert!(closed_rx.try_recv().is_err());
+
+        closed_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        let err = client.publish("foo", None, None, b"bar").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotConnected);
+    }
+
+    #[test]
+    fn idle_checks_do_not_send_keepalive_pings() {
+        // Answer only the PING of the handshake, and report later PINGs and
+        // reconnects.
+        let (event_tx, event_rx) = channel::unbounded();
+        let mut pings = 0;
+        let url = MockServer::new().serve(move |session, line| {
+            if session.index > 0 {
+                event_tx.send("reconnect").ok();
+            }
+            if line == "PING" {
+                pings += 1;
+                if pings > 1 {
+                    event_tx.send("PING").ok();
+                    return Ok(Step::Handled);
+                }
+            }
+            Ok(Step::Continue)
+        });
+
+        let options = Options::new().auto_close_idle(Duration::from_millis(50));
+        let client = Client::connect(&url, options).unwrap();
+        let (_sid, _messages) = client.subscribe("foo", None).unwrap();
+
+        // Nothing has been received for longer than the PING interval, but
+        // the last keepalive PING was not that long ago either.
+        if let Some(last_active) = Instant::now().checked_sub(Duration::from_secs(3 * 60)) {
+            client.state.read.lock().last_active = last_active;
+        }
+        assert!(event_rx.recv_timeout(Duration::from_millis(500)).is_err());
+
+        client.close();
+    }
+}
+
+#[cfg(test)]
+mod ping_during_reconnect {
+    use super::*;
+    use crate::mock_server::{MockServer, Step};
+
+    #[test]
+    fn reconnects_promptly() {
+        // Send a PING on the first connection when told to, and report the
+        // lines received on the second one.
+        let (ping_tx, ping_rx) = channel::unbounded::<()>();
+        let (line_tx, line_rx) = channel::unbounded();
+        let url = MockServer::new().serve(move |session, line| {
+            if session.index == 0 && line.starts_with("CONNECT ") {
+                let mut writer = session.writer()?;
+                let ping_rx = ping_rx.clone();
+                thread::spawn(move || {
+                    if ping_rx.recv().is_ok() {
+                        writer.write_all(b"PING\r\n").ok();
+                    }
+                });
+            } else if session.index > 0 {
+                line_tx.send(line.to_string()).ok();
+            }
+            Ok(Step::Continue)
+        });
+
+        let client = Client::connect(&url, Options::new()).unwrap();
+
+        // Drop the writer as a failed write would, then have the server ping.
+        client.state.write.lock().writer = None;
+        ping_tx.send(()).unwrap();
+
+        let timeout = Duration::from_secs(5);
+        let line = line_rx.recv_timeout(timeout).unwrap();
+        assert!(line.starts_with("CONNECT "));
+        client.wait_connected(timeout).unwrap();
+
+        client.close();
+    }
+}
+
+#[cfg(test)]
+mod subscribe_sync {
+    use super::*;
+    use crate::mock_server::{MockServer, Step};
+
+    #[test]
+    fn registered_before_returning() {
+        let (url, sub_rx) = MockServer::new().reporting("SUB ");
+        let client = Client::connect(&url, Options::new()).unwrap();
+        let timeout = Duration::from_secs(5);
+        client.subscribe_sync("foo", None, timeout).unwrap();
+        assert_eq!(sub_rx.try_recv().unwrap(), "SUB foo 1");
+        client
+            .subscribe_sync("bar", Some("workers"), timeout)
+            .unwrap();
+        assert_eq!(sub_rx.try_recv().unwrap(), "SUB bar workers 2");
+
+        client.close();
+    }
+
+    #[test]
+    fn fails_on_permissions_violation() {
+        // Deny subscriptions to "secret", and report UNSUBs.
+        let (unsub_tx, unsub_rx) = channel::unbounded();
+        let url = MockServer::new().serve(move |session, line| {
+            if line.starts_with("SUB secret ") {
+                session.write("-ERR 'Permissions Violation for Subscription to \"secret\"'\r\n")?;
+            } else if line.starts_with("UNSUB ") {
+                unsub_tx.send(line.to_string()).ok();
+            }
+            Ok(Step::Continue)
+        });
+        let client = Client::connect(&url, Options::new()).unwrap();
+        let timeout = Duration::from_secs(5);
+
+        let err = client.subscribe_sync("secret", None, timeout).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        assert_eq!(unsub_rx.recv_timeout(timeout).unwrap(), "UNSUB 1");
+        client.subscribe_sync("public", None, timeout).unwrap();
+
+        client.close();
+    }
+}
+
+#[cfg(test)]
+mod responder_inbox {
+    use super::*;
+    use crate::mock_server::{MockServer, Step};
+
+    #[test]
+    fn receives_many_responses() {
+        // Send three responses to the inbox once subscribed.
+        let url = MockServer::new().serve(|session, line| {
+            if line.starts_with("SUB ") {
+                let inbox = line.split(' ').nth(1).unwrap();
+                for i in 1..=3 {
+                    session.write(format!("MSG {} 1 1\r\n{}\r\n", inbox, i))?;
+                }
+            }
+            Ok(Step::Continue)
+        });
+
+        // The request inbox capacity does not apply.
+        let options = Options::new().request_inbox_capacity(1);
+        let client = Client::connect(&url, options).unwrap();
+        let (inbox, sub) = client.responder_inbox().unwrap();
+        assert!(inbox.starts_with("_INBOX."));
+        client.flush(Duration::from_secs(5)).unwrap();
+
+        let timeout = Duration::from_secs(5);
+        for expected in &[b"1", b"2", b"3"] {
+            let msg = sub.next_timeout(timeout).unwrap();
+            assert_eq!(msg.subject, inbox);
+            assert_eq!(&msg.data, expected);
+        }
+
+        client.close();
+    }
+}
+
+#[cfg(test)]
+mod unknown_op {
+    use super::*;
+    use crate::mock_server::{MockServer, Step};
+
+    #[test]
+    fn counted_and_reported() {
+        // Send two junk lines before the PONG for a flush.
+        let mut pings = 0;
+        let url = MockServer::new().serve(move |session, line| {
+            if line == "PING" {
+                pings += 1;
+                if pings == 2 {
+                    session.write(b"HELLO there\r\nBOGUS\r\n")?;
+                }
+            }
+            Ok(Step::Continue)
+        });
+
+        let (line_tx, line_rx) = channel::unbounded();
+        let options =
+            Options::new().unknown_op_callback(move |line| line_tx.send(line.to_string()).unwrap());
+        let client = Client::connect(&url, options).unwrap();
+        assert_eq!(client.unknown_op_count(), 0);
+
+        client.flush(Duration::from_secs(5)).unwrap();
+        assert_eq!(client.unknown_op_count(), 2);
+        assert_eq!(line_rx.try_recv().unwrap(), "HELLO there");
+        assert_eq!(line_rx.try_recv().unwrap(), "BOGUS");
+
+        client.close();
+    }
+}
+
+#[cfg(test)]
+mod publish_timeout {
+    use super::*;
+    use crate::mock_server::{MockServer, ROOT_CA};
+
+    fn assert_times_out(client: &Client) {
+        // Larger than the socket buffers, so the write cannot complete.
+        let msg = vec![0_u8; 64 * 1024 * 1024];
+        let start = Instant::now();
+        let err = client
+            .publish_with_timeout("foo", None, None, &msg, Duration::from_millis(100))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn stalled_socket_times_out() {
+        let (url, done_tx) = MockServer::new().stall_after_handshake();
+        let client = Client::connect(&url, Options::default()).unwrap();
+
+        assert_times_out(&client);
+
+        done_tx.send(()).unwrap();
+        client.close();
+    }
+
+    #[test]
+    fn stalled_tls_stream_times_out() {
+        let (url, done_tx) = MockServer::with_tls().stall_after_handshake();
+        let options = Options::default().add_root_certificate(ROOT_CA);
+        let client = Client::connect(&url, options).unwrap();
+
+        assert_times_out(&client);
+
+        done_tx.send(()).unwrap();
+        client.close();
+    }
+}
+
+#[cfg(test)]
+mod flush_and_drain_writer {
+    use super::*;
+    use crate::mock_server::{MockServer, Step, ROOT_CA};
+
+    /// Writes PONGs until the socket buffers are full, so that the TLS
+    /// session holds back the records it could not send, and returns the
+    /// number of bytes written.
+    fn fill(client: &Client) -> usize {
+        let write = client.state.write.lock();
+        let mut stream = write.writer.as_ref().unwrap().get_ref();
+        stream
+            .set_write_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let pongs = b"PONG\r\n".repeat(1024);
+        let mut written = 0;
+        loop {
+            match stream.write(&pongs) {
+                Ok(n) => written += n,
+                Err(err) => {
+                    assert_eq!(err.kind(), ErrorKind::TimedOut);
+                    break;
+                }
+            }
+        }
+        stream.set_write_timeout(None).unwrap();
+        written
+    }
+
+    #[test]
+    fn sends_records_held_by_tls_session() {
+        // Stop reading after the handshake until told to resume, then report
+        // every PONG received.
+        let (resume_tx, resume_rx) = channel::bounded::<()>(1);
+        let (pong_tx, pong_rx) = channel::unbounded();
+        let url = MockServer::with_tls().serve(move |session, line| match line {
+            "PING" => {
+                session.write(b"PONG\r\n")?;
+                resume_rx.recv().ok();
+                Ok(Step::Handled)
+            }
+            "PONG" => {
+                pong_tx.send(()).ok();
+                Ok(Step::Handled)
+            }
+            _ => Ok(Step::Continue),
+        });
+
+        let options = Options::new().add_root_certificate(ROOT_CA);
+        let client = Client::connect(&url, options).unwrap();
+        let written = fill(&client);
+
+        resume_tx.send(()).unwrap();
+        client
+            .flush_and_drain_writer(Duration::from_secs(10))
+            .unwrap();
+
+        for _ in 0..written / 6 {
+            pong_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+
+        client.close();
+    }
+    #[test]
+    fn times_out_on_stalled_tls_stream() {
+        let (url, done_tx) = MockServer::with_tls().stall_after_handshake();
+
+        let options = Options::new().add_root_certificate(ROOT_CA);
+        let client = Client::connect(&url, options).unwrap();
+        fill(&client);
+
+        let start = Instant::now();
+        let err = client
+            .flush_and_drain_writer(Duration::from_millis(100))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        done_tx.send(()).unwrap();
+        client.close();
+    }
+}
+
+#[cfg(test)]
+mod server_info_changes {
+    use super::*;
+    use crate::mock_server::{self, MockServer, Step};
+
+    #[test]
+    fn keeps_latest_updates_across_reconnects() {
+        // Send more updates than are held once subscribed, then disconnect.
+        let url = MockServer::new().serve(|session, line| {
+            if line.starts_with("SUB ") && session.index == 0 {
+                for i in 0..20 {
+                    let id = format!("update-{}", i);
+                    session.write(mock_server::info(&[("server_id", id.into())]))?;
+                }
+                return Ok(Step::Disconnect);
+            }
+            Ok(Step::Continue)
+        });
+
+        let (reconnect_tx, reconnect_rx) = channel::unbounded();
+        let options = Options::new().reconnect_callback(move || reconnect_tx.send(()).unwrap());
+        let client = Client::connect(&url, options).unwrap();
+        let changes = client.server_info_changes();
+        client.subscribe("foo", None).unwrap();
+        reconnect_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        let ids: Vec<String> = std::iter::from_fn(|| changes.try_next())
+            .map(|info| info.server_id)
+            .collect();
+        let mut expected: Vec<String> = (5..20).map(|i| format!("update-{}", i)).collect();
+        expected.push("test".to_string());
+        assert_eq!(ids, expected);
+
+        client.close();
+    }
+}
@@ -0,0 +1,147 @@
+// Copyright 2020-2021 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::prelude::*;
+use std::io::{self, Error, ErrorKind};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+
+use crate::Headers;
+
+/// The largest decompressed payload accepted for messages that are not tied
+/// to a connection, which is the default `max_payload` of the server.
+pub(crate) const DEFAULT_MAX_DECOMPRESSED: usize = 1024 * 1024;
+
+/// A payload compression algorithm, announced to receivers through the
+/// `Content-Encoding` header.
+///
+/// Requires the `compression` feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Compression {
+    /// gzip, as produced by `flate2`.
+    Gzip,
+    /// Zstandard.
+    Zstd,
+}
+
+impl Compression {
+    /// Returns the value of the `Content-Encoding` header for this
+    /// algorithm.
+    ///
+    /// # Example
+    /// ```
+    /// assert_eq!(nats::Compression::Gzip.as_str(), "gzip");
+    /// assert_eq!(nats::Compression::Zstd.as_str(), "zstd");
+    /// ```
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        }
+    }
+}
+
+/// Compresses the data, returning it together with the headers announcing
+/// the algorithm.
+pub(crate) fn compress(data: &[u8], algorithm: Compression) -> io::Result<(Headers, Vec<u8>)> {
+    let compressed = match algorithm {
+        Compression::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(data)?;
+            encoder.finish()?
+        }
+        Compression::Zstd => zstd::encode_all(data, 0)?,
+    };
+
+    let mut headers = Headers::default();
+    headers.set_content_encoding(algorithm.as_str());
+    Ok((headers, compressed))
+}
+
+/// Decompresses a payload according to its `Content-Encoding` header,
+/// copying it unchanged if there is none. Fails with `InvalidData` if the
+/// decompressed payload would be larger than `max_size`.
+pub(crate) fn decompress(
+    headers: Option<&Headers>,
+    data: &[u8],
+    max_size: usize,
+) -> io::Result<Vec<u8>> {
+    match headers.and_then(Headers::content_encoding) {
+        None | Some("identity") => Ok(data.to_vec()),
+        Some("gzip") => read_capped(GzDecoder::new(data), max_size),
+        Some("zstd") => read_capped(zstd::Decoder::new(data)?, max_size),
+        Some(other) => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("unsupported content encoding: {}", other),
+        )),
+    }
+}
+
+/// Reads the decompressed payload, stopping as soon as it grows past
+/// `max_size`.
+fn read_capped(decoder: impl Read, max_size: usize) -> io::Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    decoder
+        .take(max_size as u64 + 1)
+        .read_to_end(&mut decompressed)?;
+    if decompressed.len() > max_size {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("decompressed payload exceeds {} bytes", max_size),
+        ));
+    }
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod round_trip {
+    use super::*;
+
+    #[test]
+    fn all_algorithms() {
+        let data: Vec<u8> = (0..10_000).map(|i| (i % 7) as u8).collect();
+        for algorithm in &[Compression::Gzip, Compression::Zstd] {
+            let (headers, compressed) = compress(&data, *algorithm).unwrap();
+            assert!(compressed.len() < data.len());
+            assert_eq!(headers.content_encoding(), Some(algorithm.as_str()));
+            assert_eq!(
+                decompress(Some(&headers), &compressed, data.len()).unwrap(),
+                data
+            );
+        }
+    }
+
+    #[test]
+    fn unencoded_and_unsupported() {
+        assert_eq!(decompress(None, b"plain", 5).unwrap(), b"plain");
+
+        let mut headers = Headers::default();
+        headers.set_content_encoding("br");
+        let err = decompress(Some(&headers), b"data", 4).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        headers.set_content_encoding("gzip");
+        assert!(decompress(Some(&headers), b"not gzip", 8).is_err());
+    }
+
+    #[test]
+    fn capped_output() {
+        let data = vec![0; 100_000];
+        for algorithm in &[Compression::Gzip, Compression::Zstd] {
+            let (headers, compressed) = compress(&data, *algorithm).unwrap();
+            let err = decompress(Some(&headers), &compressed, data.len() - 1).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+    }
+}
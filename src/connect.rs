@@ -11,6 +11,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
+
 use crate::SecureString;
 
 /// Info to construct a CONNECT message.
@@ -66,6 +68,9 @@ pub struct ConnectInfo {
 
     /// Whether the client supports no_responders.
     pub no_responders: bool,
+
+    /// Key-value metadata describing the client, shown in server monitoring.
+    pub metadata: HashMap<String, String>,
 }
 
 impl ConnectInfo {
@@ -101,6 +106,13 @@ impl ConnectInfo {
         if let Some(s) = &self.auth_token {
             obj.insert("auth_token", s.to_string()).ok()?;
         }
+        if !self.metadata.is_empty() {
+            let mut metadata = json::JsonValue::new_object();
+            for (key, value) in &self.metadata {
+                metadata.insert(key, value.as_str()).ok()?;
+            }
+            obj.insert("metadata", metadata).ok()?;
+        }
         Some(obj.dump())
     }
 }
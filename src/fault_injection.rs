@@ -11,15 +11,118 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+//! Fault injection for testing, enabled by the `fault_injection` feature.
+//!
+//! By default, random delays and I/O failures are injected throughout the
+//! client. Tests that need to reproduce a specific failure can install their
+//! own `FaultInjector` with `set_fault_injector`. The injector is global to
+//! the process, so tests that install one should not run concurrently with
+//! other tests.
+//!
+//! # Example
+//! ```
+//! use std::io;
+//! use std::sync::atomic::{AtomicUsize, Ordering};
+//!
+//! use nats::fault_injection::{self, FaultInjector};
+//!
+//! /// Fails the write of every publish after the third one.
+//! struct FailAfterThree(AtomicUsize);
+//!
+//! impl FaultInjector for FailAfterThree {
+//!     fn delay(&self) {}
+//!
+//!     fn io_failure(&self) -> io::Result<()> {
+//!         Ok(())
+//!     }
+//!
+//!     fn write_failure(&self) -> io::Result<()> {
+//!         if self.0.fetch_add(1, Ordering::SeqCst) >= 3 {
+//!             Err(io::Error::new(io::ErrorKind::Other, "injected fault"))
+//!         } else {
+//!             Ok(())
+//!         }
+//!     }
+//! }
+//!
+//! fault_injection::set_fault_injector(FailAfterThree(AtomicUsize::new(0)));
+//! # fault_injection::reset_fault_injector();
+//! ```
+
 #![allow(clippy::float_arithmetic)]
 
 use std::io::{self, Error, ErrorKind};
 use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use std::sync::Arc;
+
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+
+/// Decides where delays and I/O failures are injected into the client.
+pub trait FaultInjector: Send + Sync {
+    /// Called before operations that trigger cross-thread communication.
+    ///
+    /// Defaults to `random_delay`.
+    fn delay(&self) {
+        random_delay();
+    }
+
+    /// Called before connecting and reconnecting. Returning an error fails
+    /// that step, as if the underlying I/O had failed.
+    ///
+    /// Defaults to `random_io_failure`.
+    fn io_failure(&self) -> io::Result<()> {
+        random_io_failure()
+    }
+
+    /// Called before a published message is written to the connection.
+    /// Returning an error is treated as a failed write, which disconnects the
+    /// client.
+    ///
+    /// Never fails by default.
+    fn write_failure(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The default injector, using random delays and I/O failures.
+struct RandomFaults;
+
+impl FaultInjector for RandomFaults {}
+
+static INJECTOR: Lazy<RwLock<Arc<dyn FaultInjector>>> =
+    Lazy::new(|| RwLock::new(Arc::new(RandomFaults)));
+
+/// Installs the injector used by all clients in this process.
+pub fn set_fault_injector<F: FaultInjector + 'static>(injector: F) {
+    *INJECTOR.write() = Arc::new(injector);
+}
+
+/// Restores the default injector, using random delays and I/O failures.
+pub fn reset_fault_injector() {
+    set_fault_injector(RandomFaults);
+}
+
+fn injector() -> Arc<dyn FaultInjector> {
+    INJECTOR.read().clone()
+}
+
+pub(crate) fn inject_delay() {
+    injector().delay();
+}
+
+pub(crate) fn inject_io_failure() -> io::Result<()> {
+    injector().io_failure()
+}
+
+pub(crate) fn inject_write_failure() -> io::Result<()> {
+    injector().write_failure()
+}
 
 /// This function is useful for inducing random jitter into our operations that
 /// trigger cross-thread communication, shaking out more possible interleavings
 /// quickly. It gets fully eliminated by the compiler in non-test code.
-pub fn inject_delay() {
+pub fn random_delay() {
     use std::thread;
     use std::time::Duration;
 
@@ -58,7 +161,7 @@ pub fn inject_delay() {
 
 /// This allows our IO error handling code to be tested by
 /// injecting failures sometimes.
-pub fn inject_io_failure() -> io::Result<()> {
+pub fn random_io_failure() -> io::Result<()> {
     if fastrand::i32(..100) == 0 {
         Err(Error::new(ErrorKind::Other, "injected fault"))
     } else {
@@ -12,10 +12,12 @@
 // limitations under the License.
 
 use std::{
+    borrow::Cow,
     collections::{HashMap, HashSet},
     convert::TryFrom,
     iter::{FromIterator, IntoIterator},
     ops::Deref,
+    str::FromStr,
 };
 
 use log::trace;
@@ -25,12 +27,56 @@ const HEADER_LINE_LEN: usize = HEADER_LINE.len();
 
 pub const STATUS_HEADER: &str = "Status";
 pub const DESCRIPTION_HEADER: &str = "Description";
+pub const CONTENT_TYPE_HEADER: &str = "Content-Type";
+pub const CONTENT_ENCODING_HEADER: &str = "Content-Encoding";
 
 pub const NATS_MSG_ID: &str = "Nats-Msg-Id";
 pub const NATS_EXPECTED_STREAM: &str = "Nats-Expected-Stream";
 pub const NATS_EXPECTED_LAST_MSG_ID: &str = "Nats-Expected-Last-Msg-Id";
 pub const NATS_EXPECTED_LAST_SEQUENCE: &str = "Nats-Expected-Last-Sequence";
 pub const NATS_EXPECTED_LAST_SUBJECT_SEQUENCE: &str = "Nats-Expected-Last-Subject-Sequence";
+pub const NATS_ROLLUP: &str = "Nats-Rollup";
+
+pub const NATS_CHUNK_ID: &str = "Nats-Chunk-Id";
+pub const NATS_CHUNK_INDEX: &str = "Nats-Chunk-Index";
+pub const NATS_CHUNK_TOTAL: &str = "Nats-Chunk-Total";
+
+/// Which prior messages a `JetStream` publish with a `Nats-Rollup` header
+/// replaces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RollupKind {
+    /// Purges the prior messages on the subject of the published message.
+    Subject,
+    /// Purges all prior messages in the stream.
+    All,
+}
+
+impl RollupKind {
+    /// Returns the value of the `Nats-Rollup` header.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RollupKind::Subject => "sub",
+            RollupKind::All => "all",
+        }
+    }
+}
+
+impl FromStr for RollupKind {
+    type Err = std::io::Error;
+
+    /// Parses a `Nats-Rollup` header value, failing with `InvalidInput` for
+    /// anything but `sub` and `all`.
+    fn from_str(value: &str) -> std::io::Result<RollupKind> {
+        match value {
+            "sub" => Ok(RollupKind::Subject),
+            "all" => Ok(RollupKind::All),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("invalid rollup value: {:?}", value),
+            )),
+        }
+    }
+}
 
 /// A multi-map from header name to a set of values for that header
 #[derive(Debug, Default, Clone, PartialEq, Eq)]
@@ -133,18 +179,30 @@ impl TryFrom<&[u8]> for Headers {
     type Error = std::io::Error;
 
     fn try_from(buf: &[u8]) -> std::io::Result<Self> {
-        let mut inner = HashMap::default();
-        let mut lines = if let Ok(line) = std::str::from_utf8(buf) {
-            line.lines().peekable()
+        let (headers, mut malformed) = parse_lenient(buf);
+        if malformed.is_empty() {
+            Ok(headers)
         } else {
-            return parse_error("invalid header received");
-        };
+            parse_error(malformed.swap_remove(0))
+        }
+    }
+}
 
-        if let Some(line) = lines.next() {
-            if !line.starts_with(HEADER_LINE) {
-                return parse_error("version line does not begin with NATS/1.0");
-            }
+/// Parses a header block, skipping the parts that are malformed instead of
+/// failing. Returns the headers that could be parsed along with a
+/// description of each malformed part.
+pub(crate) fn parse_lenient(buf: &[u8]) -> (Headers, Vec<String>) {
+    let mut inner = HashMap::default();
+    let mut malformed = Vec::new();
+
+    let text = String::from_utf8_lossy(buf);
+    if let Cow::Owned(_) = text {
+        malformed.push("invalid header received".to_string());
+    }
+    let mut lines = text.lines().peekable();
 
+    match lines.next() {
+        Some(line) if line.starts_with(HEADER_LINE) => {
             if let Some(slice) = line.get(HEADER_LINE_LEN..).map(|s| s.trim()) {
                 match slice.split_once(' ') {
                     Some((status, description)) => {
@@ -172,34 +230,37 @@ impl TryFrom<&[u8]> for Headers {
                     }
                 }
             }
-        } else {
-            return parse_error("expected header information not present");
-        };
-
-        while let Some(line) = lines.next() {
-            if line.is_empty() {
-                continue;
-            }
+        }
+        Some(line) => malformed.push(format!(
+            "version line does not begin with NATS/1.0: {:?}",
+            line
+        )),
+        None => malformed.push("expected header information not present".to_string()),
+    }
 
-            if let Some((k, v)) = line.split_once(':') {
-                let entry = inner
-                    .entry(k.trim().to_string())
-                    .or_insert_with(HashSet::default);
+    while let Some(line) = lines.next() {
+        if line.is_empty() {
+            continue;
+        }
 
-                let mut s = String::from(v.trim());
-                while let Some(v) = lines.next_if(|s| s.starts_with(is_continuation)) {
-                    s.push(' ');
-                    s.push_str(v.trim());
-                }
+        if let Some((k, v)) = line.split_once(':') {
+            let entry = inner
+                .entry(k.trim().to_string())
+                .or_insert_with(HashSet::default);
 
-                entry.insert(s);
-            } else {
-                return parse_error("malformed header line");
+            let mut s = String::from(v.trim());
+            while let Some(v) = lines.next_if(|s| s.starts_with(is_continuation)) {
+                s.push(' ');
+                s.push_str(v.trim());
             }
-        }
 
-        Ok(Headers { inner })
+            entry.insert(s);
+        } else {
+            malformed.push(format!("malformed header line: {:?}", line));
+        }
     }
+
+    (Headers { inner }, malformed)
 }
 
 impl Deref for Headers {
@@ -211,6 +272,109 @@ impl Deref for Headers {
 }
 
 impl Headers {
+    /// Returns the value of the `Content-Type` header, if set.
+    ///
+    /// # Example
+    /// ```
+    /// let mut headers = nats::Headers::default();
+    /// headers.set_content_type("application/json");
+    /// assert_eq!(headers.content_type(), Some("application/json"));
+    /// ```
+    pub fn content_type(&self) -> Option<&str> {
+        self.inner
+            .get(CONTENT_TYPE_HEADER)?
+            .iter()
+            .next()
+            .map(String::as_str)
+    }
+
+    /// Sets the `Content-Type` header, replacing any existing values.
+    pub fn set_content_type(&mut self, content_type: &str) {
+        self.set(CONTENT_TYPE_HEADER, content_type.to_string());
+    }
+
+    /// Returns the value of the `Content-Encoding` header, if set.
+    pub fn content_encoding(&self) -> Option<&str> {
+        self.inner
+            .get(CONTENT_ENCODING_HEADER)?
+            .iter()
+            .next()
+            .map(String::as_str)
+    }
+
+    /// Sets the `Content-Encoding` header, replacing any existing values.
+    pub fn set_content_encoding(&mut self, content_encoding: &str) {
+        self.set(CONTENT_ENCODING_HEADER, content_encoding.to_string());
+    }
+
+    /// Sets the `Nats-Expected-Stream` header, so that a `JetStream` publish
+    /// is rejected unless the subject is stored in the given stream.
+    pub fn set_expected_stream(&mut self, stream: &str) {
+        self.set(NATS_EXPECTED_STREAM, stream.to_string());
+    }
+
+    /// Sets the `Nats-Expected-Last-Sequence` header, so that a `JetStream`
+    /// publish is rejected unless the last message in the stream has the
+    /// given sequence.
+    ///
+    /// # Example
+    /// ```
+    /// let mut headers = nats::Headers::default();
+    /// headers.set_expected_last_sequence(42);
+    /// assert!(headers.get("Nats-Expected-Last-Sequence").unwrap().contains("42"));
+    /// ```
+    pub fn set_expected_last_sequence(&mut self, sequence: u64) {
+        self.set(NATS_EXPECTED_LAST_SEQUENCE, sequence.to_string());
+    }
+
+    /// Sets the `Nats-Expected-Last-Subject-Sequence` header, so that a
+    /// `JetStream` publish is rejected unless the last message on its
+    /// subject has the given sequence.
+    pub fn set_expected_last_subject_sequence(&mut self, sequence: u64) {
+        self.set(NATS_EXPECTED_LAST_SUBJECT_SEQUENCE, sequence.to_string());
+    }
+
+    /// Sets the `Nats-Expected-Last-Msg-Id` header, so that a `JetStream`
+    /// publish is rejected unless the last message in the stream has the
+    /// given message ID.
+    pub fn set_expected_last_msg_id(&mut self, msg_id: &str) {
+        self.set(NATS_EXPECTED_LAST_MSG_ID, msg_id.to_string());
+    }
+
+    /// Sets the `Nats-Rollup` header, so that a `JetStream` publish purges
+    /// prior messages once it is stored. This requires a stream that allows
+    /// rollups, and gives key-value buckets their last-value semantics.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// let mut headers = nats::Headers::default();
+    /// headers.set_rollup(nats::RollupKind::Subject);
+    /// nc.publish_with_reply_or_headers("$KV.config.timeout", None, Some(&headers), "30s")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_rollup(&mut self, kind: RollupKind) {
+        self.set(NATS_ROLLUP, kind.as_str().to_string());
+    }
+
+    /// Returns the kind of the `Nats-Rollup` header, if there is one.
+    ///
+    /// Fails with `InvalidInput` if the header has a value other than `sub`
+    /// or `all`.
+    pub fn rollup(&self) -> Option<std::io::Result<RollupKind>> {
+        let value = self.inner.get(NATS_ROLLUP)?.iter().next()?;
+        Some(value.parse())
+    }
+
+    /// Sets a header to a single value, replacing any existing values.
+    fn set(&mut self, name: &str, value: String) {
+        let mut values = HashSet::with_capacity(1);
+        values.insert(value);
+        self.inner.insert(name.to_string(), values);
+    }
+
     pub(crate) fn to_bytes(&self) -> Vec<u8> {
         // `<version line>\r\n[headers]\r\n\r\n[payload]\r\n`
         let mut buf = vec![];
@@ -351,3 +515,160 @@ mod try_from {
         );
     }
 }
+
+#[cfg(test)]
+mod lenient {
+    use super::*;
+
+    #[test]
+    fn malformed_line_is_skipped() {
+        let (headers, malformed) =
+            parse_lenient(b"NATS/1.0\r\nX-Test-A: a\r\nbogus\r\nX-Test-B: b\r\n\r\n");
+
+        assert_eq!(
+            headers.inner.get("X-Test-A"),
+            Some(&HashSet::from_iter(vec!["a".to_string()]))
+        );
+        assert_eq!(
+            headers.inner.get("X-Test-B"),
+            Some(&HashSet::from_iter(vec!["b".to_string()]))
+        );
+        assert_eq!(
+            malformed,
+            vec!["malformed header line: \"bogus\"".to_string()]
+        );
+    }
+
+    #[test]
+    fn missing_version_line() {
+        let (headers, malformed) = parse_lenient(b"HTTP/1.1 200\r\nX-Test: a\r\n\r\n");
+
+        assert_eq!(
+            headers.inner.get("X-Test"),
+            Some(&HashSet::from_iter(vec!["a".to_string()]))
+        );
+        assert_eq!(malformed.len(), 1);
+        assert!(malformed[0].starts_with("version line does not begin with NATS/1.0"));
+    }
+
+    #[test]
+    fn empty_block() {
+        let (headers, malformed) = parse_lenient(b"");
+
+        assert!(headers.is_empty());
+        assert_eq!(malformed, vec!["expected header information not present"]);
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let (headers, malformed) = parse_lenient(b"NATS/1.0\r\nX-Test: \xff\r\nX-Ok: ok\r\n\r\n");
+
+        assert_eq!(
+            headers.inner.get("X-Ok"),
+            Some(&HashSet::from_iter(vec!["ok".to_string()]))
+        );
+        assert_eq!(malformed, vec!["invalid header received"]);
+    }
+
+    #[test]
+    fn valid_block() {
+        let (headers, malformed) = parse_lenient(b"NATS/1.0 503\r\n\r\n");
+
+        assert_eq!(
+            headers.inner.get(STATUS_HEADER),
+            Some(&HashSet::from_iter(vec!["503".to_string()]))
+        );
+        assert!(malformed.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod content_type {
+    use super::*;
+
+    #[test]
+    fn parsed() {
+        let headers =
+            Headers::try_from("NATS/1.0\r\nContent-Type: text/plain\r\n".as_bytes()).unwrap();
+        assert_eq!(headers.content_type(), Some("text/plain"));
+    }
+
+    #[test]
+    fn missing() {
+        let headers = Headers::try_from("NATS/1.0\r\nX-Test: a\r\n".as_bytes()).unwrap();
+        assert_eq!(headers.content_type(), None);
+    }
+
+    #[test]
+    fn set_replaces() {
+        let mut headers = Headers::from_iter(vec![("Content-Type", "text/plain")]);
+        headers.set_content_type("application/json");
+        assert_eq!(headers.content_type(), Some("application/json"));
+        assert_eq!(headers.get(CONTENT_TYPE_HEADER).unwrap().len(), 1);
+    }
+}
+
+#[cfg(test)]
+mod expected {
+    use super::*;
+
+    #[test]
+    fn sequences() {
+        let mut headers = Headers::default();
+        headers.set_expected_last_sequence(10);
+        headers.set_expected_last_subject_sequence(3);
+        headers.set_expected_last_sequence(11);
+
+        let bytes = headers.to_bytes();
+        let parsed = Headers::try_from(&bytes[..]).unwrap();
+        assert_eq!(
+            parsed.get(NATS_EXPECTED_LAST_SEQUENCE),
+            Some(&HashSet::from_iter(vec!["11".to_string()]))
+        );
+        assert_eq!(
+            parsed.get(NATS_EXPECTED_LAST_SUBJECT_SEQUENCE),
+            Some(&HashSet::from_iter(vec!["3".to_string()]))
+        );
+    }
+
+    #[test]
+    fn stream_and_msg_id() {
+        let mut headers = Headers::default();
+        headers.set_expected_stream("ORDERS");
+        headers.set_expected_last_msg_id("abc");
+
+        assert!(headers
+            .get(NATS_EXPECTED_STREAM)
+            .unwrap()
+            .contains("ORDERS"));
+        assert!(headers
+            .get(NATS_EXPECTED_LAST_MSG_ID)
+            .unwrap()
+            .contains("abc"));
+    }
+}
+
+#[cfg(test)]
+mod rollup {
+    use super::*;
+
+    #[test]
+    fn set_and_parse() {
+        let mut headers = Headers::default();
+        assert!(headers.rollup().is_none());
+
+        headers.set_rollup(RollupKind::All);
+        headers.set_rollup(RollupKind::Subject);
+        let bytes = headers.to_bytes();
+        let parsed = Headers::try_from(&bytes[..]).unwrap();
+        assert_eq!(
+            parsed.get(NATS_ROLLUP),
+            Some(&HashSet::from_iter(vec!["sub".to_string()]))
+        );
+        assert_eq!(parsed.rollup().unwrap().unwrap(), RollupKind::Subject);
+
+        let headers: Headers = vec![(NATS_ROLLUP, "none")].iter().collect();
+        let err = headers.rollup().unwrap().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+}
@@ -714,6 +714,7 @@ impl JetStream {
             subject,
             maybe_headers.as_ref(),
             maybe_timeout,
+            None,
             msg,
         )?;
 
@@ -1111,6 +1112,10 @@ impl Consumer {
                 ))];
             }
 
+            if let Err(e) = self.js.nc.0.client.check_jetstream_draining() {
+                return vec![Err(e)];
+            }
+
             let subject = format!(
                 "{}CONSUMER.MSG.NEXT.{}.{}",
                 self.js.api_prefix(),
@@ -1199,6 +1204,8 @@ impl Consumer {
                 ));
             }
 
+            self.js.nc.0.client.check_jetstream_draining()?;
+
             let subject = format!(
                 "{}CONSUMER.MSG.NEXT.{}.{}",
                 self.js.api_prefix(),
@@ -1239,6 +1246,8 @@ impl Consumer {
                 ));
             }
 
+            self.js.nc.0.client.check_jetstream_draining()?;
+
             let subject = format!(
                 "{}CONSUMER.MSG.NEXT.{}.{}",
                 self.js.api_prefix(),
@@ -1300,6 +1309,8 @@ impl Consumer {
             ));
         }
 
+        self.js.nc.0.client.check_jetstream_draining()?;
+
         let subject = format!(
             "{}CONSUMER.MSG.NEXT.{}.{}",
             self.js.api_prefix(),
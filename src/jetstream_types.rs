@@ -11,6 +11,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::fmt;
+use std::io::Write;
 use std::time::{Duration, UNIX_EPOCH};
 
 use serde::{Deserialize, Serialize};
@@ -458,6 +460,7 @@ pub struct PublishAck {
 
 /// The kinds of response used for acknowledging a processed message.
 #[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
 pub enum AckKind {
     /// Acknowledges a message was completely handled.
     Ack,
@@ -476,6 +479,42 @@ pub enum AckKind {
     /// Instructs the server to stop redelivery of a message
     /// without acknowledging it as successfully processed.
     Term,
+    /// Like `Nak`, but asks the server to wait before redelivering
+    /// the message. Created with `AckKind::nak_with_delay`.
+    NakWithDelay(NakDelay),
+}
+
+impl AckKind {
+    /// Returns a `Nak` asking the server to redeliver the message only after
+    /// the delay, for backing off after transient failures. Delays beyond
+    /// what the server can represent are capped.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// # let sub = nc.subscribe("orders")?;
+    /// use nats::jetstream::AckKind;
+    /// use std::time::Duration;
+    ///
+    /// let msg = sub.next().unwrap();
+    /// msg.ack_kind(AckKind::nak_with_delay(Duration::from_secs(30)))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn nak_with_delay(delay: Duration) -> AckKind {
+        // The server expects a signed 64-bit number of nanoseconds.
+        let nanos = delay.as_nanos().min(i64::MAX as u128);
+        let mut payload = [0; NAK_DELAY_PAYLOAD_CAPACITY];
+        let mut buf = &mut payload[..];
+        write!(buf, "-NAK {{\"delay\": {}}}", nanos).expect("payload capacity");
+        let len = NAK_DELAY_PAYLOAD_CAPACITY - buf.len();
+        AckKind::NakWithDelay(NakDelay {
+            delay,
+            payload,
+            len,
+        })
+    }
 }
 
 impl AsRef<[u8]> for AckKind {
@@ -487,6 +526,57 @@ impl AsRef<[u8]> for AckKind {
             Progress => b"+WPI",
             Next => b"+NXT",
             Term => b"+TERM",
+            NakWithDelay(nak) => &nak.payload[..nak.len],
+        }
+    }
+}
+
+/// Enough for `-NAK {"delay": <i64::MAX>}`.
+const NAK_DELAY_PAYLOAD_CAPACITY: usize = 40;
+
+/// The serialized payload of `AckKind::NakWithDelay`.
+#[derive(Clone, Copy)]
+pub struct NakDelay {
+    delay: Duration,
+    payload: [u8; NAK_DELAY_PAYLOAD_CAPACITY],
+    len: usize,
+}
+
+impl NakDelay {
+    /// Returns the requested redelivery delay.
+    pub fn delay(&self) -> Duration {
+        self.delay
+    }
+}
+
+impl fmt::Debug for NakDelay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        f.debug_struct("NakDelay")
+            .field("delay", &self.delay)
+            .finish()
+    }
+}
+
+/// Controls how `Message::double_ack_with_options` retries an ack until the
+/// server confirms it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AckOptions {
+    /// How long to wait for the server to confirm each attempt.
+    pub attempt_timeout: Duration,
+    /// How long to wait before the next attempt when the ack could not be
+    /// sent, for example while reconnecting.
+    pub retry_delay: Duration,
+    /// The maximum number of attempts before giving up, or `None` to retry
+    /// until the server confirms the ack.
+    pub max_attempts: Option<usize>,
+}
+
+impl Default for AckOptions {
+    fn default() -> AckOptions {
+        AckOptions {
+            attempt_timeout: Duration::from_millis(100),
+            retry_delay: Duration::from_millis(100),
+            max_attempts: None,
         }
     }
 }
@@ -615,3 +705,21 @@ pub struct ApiStats {
     /// The total number of API requests resulting in errors
     pub errors: u64,
 }
+
+#[cfg(test)]
+mod ack_kind {
+    use super::*;
+
+    #[test]
+    fn nak_with_delay() {
+        let nak = AckKind::nak_with_delay(Duration::from_millis(1500));
+        assert_eq!(nak.as_ref(), br#"-NAK {"delay": 1500000000}"#);
+
+        let nak = AckKind::nak_with_delay(Duration::from_secs(u64::MAX));
+        assert_eq!(nak.as_ref(), br#"-NAK {"delay": 9223372036854775807}"#);
+        match nak {
+            AckKind::NakWithDelay(nak) => assert_eq!(nak.delay(), Duration::from_secs(u64::MAX)),
+            _ => unreachable!(),
+        }
+    }
+}
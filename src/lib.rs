@@ -195,25 +195,37 @@
 pub mod asynk;
 
 mod auth_utils;
+mod chunked;
 mod client;
+#[cfg(feature = "compression")]
+mod compression;
 mod connect;
 mod connector;
 mod headers;
 mod jetstream_types;
 mod message;
+#[cfg(test)]
+mod mock_server;
 mod options;
+mod prepared;
 mod proto;
+mod publisher;
+mod request;
+mod router;
 mod secure_wipe;
+mod streaming;
 mod subscription;
+#[cfg(feature = "json")]
+mod typed;
 
 /// `JetStream` stream management and consumers.
 pub mod jetstream;
 
 #[cfg(feature = "fault_injection")]
-mod fault_injection;
+pub mod fault_injection;
 
 #[cfg(feature = "fault_injection")]
-use fault_injection::{inject_delay, inject_io_failure};
+use fault_injection::{inject_delay, inject_io_failure, inject_write_failure};
 
 #[cfg(not(feature = "fault_injection"))]
 fn inject_delay() {}
@@ -223,6 +235,11 @@ fn inject_io_failure() -> io::Result<()> {
     Ok(())
 }
 
+#[cfg(not(feature = "fault_injection"))]
+fn inject_write_failure() -> io::Result<()> {
+    Ok(())
+}
+
 #[doc(hidden)]
 #[deprecated(since = "0.6.0", note = "this has been renamed to `Options`.")]
 pub type ConnectionOptions = Options;
@@ -233,11 +250,23 @@ use std::{
     time::{Duration, Instant},
 };
 
-pub use headers::Headers;
+pub use chunked::Reassembler;
+pub use client::{BufferedBytes, ServerInfoChanges, ShutdownHandle};
+#[cfg(feature = "compression")]
+pub use compression::Compression;
+pub use headers::{Headers, RollupKind};
 pub use jetstream::JetStreamOptions;
-pub use message::Message;
-pub use options::Options;
+pub use message::{Message, MessageBuilder};
+pub use options::{BufferPolicy, Options, PendingLimits};
+pub use prepared::PreparedPublish;
+pub use proto::ClientOp;
+pub use publisher::Publisher;
+pub use request::{BacklogReplay, CancelToken, RequestIter, RequestToken};
+pub use router::Router;
+pub use streaming::{PayloadReader, StreamingMessage, StreamingSubscription};
 pub use subscription::Subscription;
+#[cfg(feature = "json")]
+pub use typed::JsonSubscription;
 
 /// A re-export of the `rustls` crate used in this crate,
 /// for use in cases where manual client configurations
@@ -255,6 +284,21 @@ const VERSION: &str = env!("CARGO_PKG_VERSION");
 const LANG: &str = "rust";
 const DEFAULT_FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
 
+/// Capabilities of a server that can be checked with
+/// `ServerInfo::supports`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ServerFeature {
+    /// Message headers.
+    Headers,
+    /// Replies with a 503 status when a request has no responders. Requires
+    /// headers.
+    NoResponders,
+    /// Reporting the client IP in the INFO message, as of server version
+    /// 2.1.6.
+    ClientIp,
+}
+
 /// Information sent by the server back to this client
 /// during initial connection, and possibly again later.
 #[allow(unused)]
@@ -279,7 +323,8 @@ pub struct ServerInfo {
     pub max_payload: usize,
     /// The protocol version in use.
     pub proto: i8,
-    /// The server-assigned client ID. This may change during reconnection.
+    /// The server-assigned client ID, as shown in the server's `/connz`
+    /// monitoring endpoint. This may change during reconnection.
     pub client_id: u64,
     /// The version of golang the NATS server was built with.
     pub go: String,
@@ -287,10 +332,18 @@ pub struct ServerInfo {
     pub nonce: String,
     /// A list of server urls that a client can connect to.
     pub connect_urls: Vec<String>,
-    /// The client IP as known by the server.
-    pub client_ip: String,
+    /// The client IP as known by the server, if reported.
+    ///
+    /// Supported as of server version 2.1.6.
+    pub client_ip: Option<String>,
     /// Whether the server supports headers.
     pub headers: bool,
+    /// The number of bytes the server buffers for this client before
+    /// disconnecting it as a slow consumer, if advertised.
+    pub max_pending: Option<usize>,
+    /// The number of messages the server buffers for this client before
+    /// disconnecting it as a slow consumer, if advertised.
+    pub max_pending_msgs: Option<usize>,
 }
 
 impl ServerInfo {
@@ -306,17 +359,68 @@ impl ServerInfo {
             tls_required: obj["tls_required"].as_bool().unwrap_or(false),
             max_payload: obj["max_payload"].as_usize()?,
             proto: obj["proto"].as_i8()?,
-            client_id: obj["client_id"].as_u64()?,
+            client_id: obj["client_id"].as_u64().unwrap_or_default(),
             go: obj["go"].take_string()?,
             nonce: obj["nonce"].take_string().unwrap_or_default(),
             connect_urls: obj["connect_urls"]
                 .members_mut()
                 .filter_map(|m| m.take_string())
                 .collect(),
-            client_ip: obj["client_ip"].take_string().unwrap_or_default(),
+            client_ip: obj["client_ip"].take_string().filter(|ip| !ip.is_empty()),
             headers: obj["headers"].as_bool().unwrap_or(false),
+            max_pending: obj["max_pending"].as_usize(),
+            max_pending_msgs: obj["max_pending_msgs"].as_usize(),
         })
     }
+
+    /// Returns `true` if the server supports the feature, based on the flags
+    /// and version it reported.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// if nc.server_info().supports(nats::ServerFeature::Headers) {
+    ///     println!("headers are supported");
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn supports(&self, feature: ServerFeature) -> bool {
+        match feature {
+            ServerFeature::Headers | ServerFeature::NoResponders => self.headers,
+            ServerFeature::ClientIp => matches!(self.version_parts(), Some(v) if v >= (2, 1, 6)),
+        }
+    }
+
+    /// Parses the server version into its major, minor and patch numbers,
+    /// which compare in version order.
+    ///
+    /// Pre-release and build suffixes such as `-beta.1` are ignored. Returns
+    /// `None` if the version is not of the form `major.minor.patch`.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// if matches!(nc.server_info().version_parts(), Some(v) if v >= (2, 9, 0)) {
+    ///     println!("server is 2.9.0 or newer");
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn version_parts(&self) -> Option<(u16, u16, u16)> {
+        let mut parts = self
+            .version
+            .split(&['-', '+'][..])
+            .next()?
+            .split('.')
+            .map(|part| part.parse::<u16>().ok());
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(major), Some(minor), Some(patch), None) => Some((major?, minor?, patch?)),
+            _ => None,
+        }
+    }
 }
 
 /// A NATS connection.
@@ -355,8 +459,17 @@ impl Connection {
         Ok(Connection(Arc::new(Inner { client })))
     }
 
+    /// Connects on a URL with the given options in the background.
+    pub(crate) fn connect_lazy_with_options(url: &str, options: Options) -> io::Result<Connection> {
+        let client = Client::connect_lazy(url, options)?;
+        Ok(Connection(Arc::new(Inner { client })))
+    }
+
     /// Create a subscription for the given NATS connection.
     ///
+    /// To load balance messages across subscribers, use `queue_subscribe`
+    /// instead.
+    ///
     /// # Example
     /// ```
     /// # fn main() -> std::io::Result<()> {
@@ -369,6 +482,41 @@ impl Connection {
         self.do_subscribe(subject, None)
     }
 
+    /// Create a subscription, optionally as part of a queue group, and make
+    /// a round trip to the server so that it has registered the
+    /// subscription before this returns. Messages published right
+    /// afterwards, even from other connections, are then not missed.
+    ///
+    /// Unlike `subscribe`, which reports permissions violations
+    /// asynchronously through the `error_callback`, this fails with
+    /// `PermissionDenied` if the subscription is not allowed for the
+    /// credentials in use. Fails with `TimedOut` if the server does not
+    /// respond within 10 seconds. In both cases the subscription is removed
+    /// again.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// let sub = nc.subscribe_sync("foo", None)?;
+    /// nc.publish("foo", "Hello World!")?;
+    /// assert!(sub.next().is_some());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn subscribe_sync(&self, subject: &str, queue: Option<&str>) -> io::Result<Subscription> {
+        let (sid, receiver) =
+            self.0
+                .client
+                .subscribe_sync(subject, queue, DEFAULT_FLUSH_TIMEOUT)?;
+        Ok(Subscription::new(
+            sid,
+            subject.to_string(),
+            receiver,
+            self.0.client.clone(),
+        ))
+    }
+
     /// Create a queue subscription for the given NATS connection.
     ///
     /// # Example
@@ -383,6 +531,223 @@ impl Connection {
         self.do_subscribe(subject, Some(queue))
     }
 
+    /// Create a subscription that only receives messages matching the given
+    /// predicate, optionally as part of a queue group. Messages for which
+    /// the predicate returns `false` are dropped before they reach the
+    /// subscription.
+    ///
+    /// The predicate runs on the thread reading from the server, so it should
+    /// be cheap and must not block.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// let sub = nc.subscribe_filtered("events.>", None, |msg| !msg.data.is_empty())?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn subscribe_filtered<F>(
+        &self,
+        subject: &str,
+        queue: Option<&str>,
+        predicate: F,
+    ) -> io::Result<Subscription>
+    where
+        F: Fn(&client::Message) -> bool + Send + Sync + 'static,
+    {
+        let (sid, receiver) = self
+            .0
+            .client
+            .subscribe_filtered(subject, queue, predicate)?;
+        Ok(Subscription::new(
+            sid,
+            subject.to_string(),
+            receiver,
+            self.0.client.clone(),
+        ))
+    }
+
+    /// Create a subscription that drops messages whose subject has more than
+    /// `max_token_depth` tokens, to cap the fan-in of broad wildcard
+    /// subscriptions such as `>`.
+    ///
+    /// Messages are dropped on the thread reading from the server, before
+    /// they are queued for the subscription. Fails with `InvalidInput` if the
+    /// subject itself has more tokens than `max_token_depth`.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// // Receives `events.orders`, but not `events.orders.created`.
+    /// let sub = nc.subscribe_bounded_wildcard("events.>", 2)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn subscribe_bounded_wildcard(
+        &self,
+        subject: &str,
+        max_token_depth: usize,
+    ) -> io::Result<Subscription> {
+        let (sid, receiver) = self
+            .0
+            .client
+            .subscribe_bounded_wildcard(subject, max_token_depth)?;
+        Ok(Subscription::new(
+            sid,
+            subject.to_string(),
+            receiver,
+            self.0.client.clone(),
+        ))
+    }
+
+    /// Create a subscription that holds back new messages while
+    /// `max_pending` delivered messages have not been acked, for client-side
+    /// flow control of at-least-once processing.
+    ///
+    /// A message stops counting against the window once it is acked with
+    /// `ack`, `ack_sync`, `ack_kind` or `double_ack`, except for
+    /// `AckKind::Progress`. Held back messages are delivered in order as acks
+    /// catch up, so every delivered message must eventually be acked. Fails
+    /// with `InvalidInput` if `max_pending` is zero.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// let sub = nc.subscribe_with_ack_window("orders.deliver", 16)?;
+    /// for msg in sub.messages() {
+    ///     println!("processing {}", msg);
+    ///     msg.ack()?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn subscribe_with_ack_window(
+        &self,
+        subject: &str,
+        max_pending: usize,
+    ) -> io::Result<Subscription> {
+        let (sid, receiver) = self
+            .0
+            .client
+            .subscribe_with_ack_window(subject, max_pending)?;
+        Ok(Subscription::new(
+            sid,
+            subject.to_string(),
+            receiver,
+            self.0.client.clone(),
+        ))
+    }
+
+    /// Create a subscription whose message payloads are read from the
+    /// connection as they are consumed, rather than being loaded into memory
+    /// first. Suited for messages of many megabytes.
+    ///
+    /// Messages are not subject to `Options::max_incoming_message_size`.
+    /// While a payload is being streamed, no other messages are received on
+    /// the connection, so read each payload to the end or drop it promptly
+    /// and don't wait on other subscriptions or requests in the meantime. If
+    /// a reader makes no progress for five seconds, the rest of its payload
+    /// is discarded and reading it fails with `UnexpectedEof`.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// use std::io::Read;
+    ///
+    /// let sub = nc.subscribe_streaming("backups")?;
+    /// # nc.publish("backups", "data")?;
+    /// let msg = sub.next().unwrap();
+    /// let mut reader = msg.into_reader();
+    /// let mut chunk = [0; 4096];
+    /// while reader.read(&mut chunk)? > 0 {}
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn subscribe_streaming(&self, subject: &str) -> io::Result<StreamingSubscription> {
+        let (sid, receiver) = self.0.client.subscribe_streaming(subject)?;
+        Ok(StreamingSubscription::new(
+            sid,
+            receiver,
+            self.0.client.clone(),
+        ))
+    }
+
+    /// Create a subscription whose messages are handled by a closure on a
+    /// separate thread, like `Subscription::with_handler`.
+    ///
+    /// Errors returned by the closure are passed to the error callback, and
+    /// so are panics in it, which are caught so that the following messages
+    /// are still handled.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// let nc = nats::Options::new()
+    ///     .error_callback(|err| println!("handling failed: {}", err))
+    ///     .connect("demo.nats.io")?;
+    /// let handler = nc.subscribe_callback("orders", |msg| {
+    ///     let order = std::str::from_utf8(&msg.data)
+    ///         .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+    ///     println!("received order {}", order);
+    ///     Ok(())
+    /// })?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn subscribe_callback<F>(
+        &self,
+        subject: &str,
+        handler: F,
+    ) -> io::Result<subscription::Handler>
+    where
+        F: Fn(client::Message) -> io::Result<()> + Send + 'static,
+    {
+        Ok(self.subscribe(subject)?.with_reporting_handler(handler))
+    }
+
+    /// Create a subscription, optionally as part of a queue group, and wait
+    /// for the server to accept it.
+    ///
+    /// This is the same as `subscribe_sync`, which fails with
+    /// `ErrorKind::PermissionDenied` if the subscription is not allowed for
+    /// the credentials in use.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// let sub = nc.subscribe_with_permissions_check("foo", None)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn subscribe_with_permissions_check(
+        &self,
+        subject: &str,
+        queue: Option<&str>,
+    ) -> io::Result<Subscription> {
+        self.subscribe_sync(subject, queue)
+    }
+
+    /// Returns a handle to this connection that can only publish messages
+    /// and flush, to hand to components that should not subscribe.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// let publisher = nc.publisher();
+    /// publisher.publish("foo", "Hello World!")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn publisher(&self) -> Publisher {
+        Publisher::new(self.clone())
+    }
+
     /// Publish a message on the given subject.
     ///
     /// # Example
@@ -421,6 +786,29 @@ impl Connection {
             .publish(subject, Some(reply), None, msg.as_ref())
     }
 
+    /// Publish a message by writing a pre-encoded protocol prefix followed by
+    /// the payload and a trailing `\r\n`, bypassing the usual encoding.
+    ///
+    /// The prefix must be a complete operation header such as
+    /// `PUB <subject> <#bytes>\r\n` where `<#bytes>` matches the payload
+    /// length. The caller is responsible for its correctness: nothing is
+    /// validated, the `subject_transform` option is not applied, and a
+    /// malformed prefix will cause the server to close the connection. Prefer
+    /// `PreparedPublish`, which validates the subject once and computes the
+    /// payload length.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// nc.publish_raw(b"PUB foo 5\r\n", b"hello")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn publish_raw(&self, prefix: &[u8], payload: &[u8]) -> io::Result<()> {
+        self.0.client.publish_raw(prefix, payload)
+    }
+
     /// Create a new globally unique inbox which can be used for replies.
     ///
     /// # Example
@@ -436,6 +824,57 @@ impl Connection {
         format!("_INBOX.{}", nuid::next())
     }
 
+    /// Create a new globally unique inbox and subscribe to it, returning both.
+    /// The inbox can be used as the reply subject of messages whose responses
+    /// are then received on the returned subscription.
+    ///
+    /// At most `Options::request_inbox_capacity` responses are queued on the
+    /// subscription, further responses are dropped until it is drained. Use
+    /// `responder_inbox` when many responses are expected on one inbox.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// let (reply, rsub) = nc.new_respond_inbox()?;
+    /// nc.publish_request("foo", &reply, "Help me!")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new_respond_inbox(&self) -> io::Result<(String, Subscription)> {
+        self.0.client.new_respond_inbox()
+    }
+
+    /// Create a new globally unique inbox and subscribe to it, returning both,
+    /// for receiving the responses to many requests through one
+    /// subscription. Unlike `new_respond_inbox`, the number of queued
+    /// responses is not limited.
+    ///
+    /// The inbox can be handed out as the reply subject of any number of
+    /// messages, so responses have to be told apart by the caller, for
+    /// example with a correlation id in a header.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// let (reply, responses) = nc.responder_inbox()?;
+    /// for id in 0..3 {
+    ///     let headers: nats::Headers = vec![("Correlation-Id".to_string(), id.to_string())]
+    ///         .into_iter()
+    ///         .collect();
+    ///     nc.publish_with_reply_or_headers("service", Some(&reply), Some(&headers), "work")?;
+    /// }
+    /// for response in responses.timeout_iter(std::time::Duration::from_secs(1)) {
+    ///     println!("response: {:?}", response.headers);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn responder_inbox(&self) -> io::Result<(String, Subscription)> {
+        self.0.client.responder_inbox()
+    }
+
     /// Publish a message on the given subject as a request and receive the
     /// response.
     ///
@@ -449,12 +888,13 @@ impl Connection {
     /// # }
     /// ```
     pub fn request(&self, subject: &str, msg: impl AsRef<[u8]>) -> io::Result<client::Message> {
-        self.request_with_headers_or_timeout(subject, None, None, msg)
+        self.request_with_headers_or_timeout(subject, None, None, None, msg)
     }
 
     /// Publish a message on the given subject as a request and receive the
     /// response. This call will return after the timeout duration if no
-    /// response is received.
+    /// response is received. The timeout covers the whole request,
+    /// including publishing it.
     ///
     /// # Example
     /// ```
@@ -471,7 +911,71 @@ impl Connection {
         msg: impl AsRef<[u8]>,
         timeout: Duration,
     ) -> io::Result<client::Message> {
-        self.request_with_headers_or_timeout(subject, None, Some(timeout), msg)
+        self.request_with_headers_or_timeout(subject, None, Some(timeout), None, msg)
+    }
+
+    /// Publish a message on the given subject as a request and receive the
+    /// response like `request_timeout`, giving up early with `Interrupted`
+    /// once `cancel` is cancelled. Cancelling a single `CancelToken` aborts
+    /// every request made with it or its clones, for example when shutting
+    /// down.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// let cancel = nats::CancelToken::new();
+    /// let canceller = cancel.clone();
+    /// std::thread::spawn(move || {
+    ///     std::thread::sleep(std::time::Duration::from_millis(100));
+    ///     canceller.cancel();
+    /// });
+    /// let timeout = std::time::Duration::from_secs(60);
+    /// let err = nc.request_with_cancel("slow", "Help me?", timeout, &cancel).unwrap_err();
+    /// assert_eq!(err.kind(), std::io::ErrorKind::Interrupted);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn request_with_cancel(
+        &self,
+        subject: &str,
+        msg: impl AsRef<[u8]>,
+        timeout: Duration,
+        cancel: &CancelToken,
+    ) -> io::Result<client::Message> {
+        self.request_with_headers_or_timeout(subject, None, Some(timeout), Some(cancel), msg)
+    }
+
+    /// Publish a message with headers on the given subject as a request and
+    /// receive the response. This call will return after the timeout
+    /// duration if no response is received. The timeout covers the whole
+    /// request, including publishing it.
+    ///
+    /// Fails with `InvalidInput` if the server does not support headers.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// # nc.subscribe("foo")?.with_handler(move |m| { m.respond("ans=42")?; Ok(()) });
+    /// let headers = [("Trace-Id", "1")].iter().collect();
+    /// let resp = nc.request_with_headers(
+    ///     "foo",
+    ///     &headers,
+    ///     "Help me?",
+    ///     std::time::Duration::from_secs(2),
+    /// )?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn request_with_headers(
+        &self,
+        subject: &str,
+        headers: &Headers,
+        msg: impl AsRef<[u8]>,
+        timeout: Duration,
+    ) -> io::Result<client::Message> {
+        self.request_with_headers_or_timeout(subject, Some(headers), Some(timeout), None, msg)
     }
 
     fn request_with_headers_or_timeout(
@@ -479,16 +983,32 @@ impl Connection {
         subject: &str,
         maybe_headers: Option<&Headers>,
         maybe_timeout: Option<Duration>,
+        maybe_cancel: Option<&CancelToken>,
         msg: impl AsRef<[u8]>,
     ) -> io::Result<client::Message> {
+        // The timeout is an overall deadline, so the time spent subscribing
+        // and publishing is taken out of the time left for the response.
+        let deadline = maybe_timeout.map(|timeout| Instant::now() + timeout);
+
+        // Don't publish a request that has already been cancelled.
+        if maybe_cancel.iter().any(|cancel| cancel.is_cancelled()) {
+            return Err(Error::new(ErrorKind::Interrupted, "request cancelled"));
+        }
+
         // Publish a request.
-        let reply = self.new_inbox();
-        let sub = self.subscribe(&reply)?;
+        let (reply, sub) = self.new_respond_inbox()?;
         self.publish_with_reply_or_headers(subject, Some(reply.as_str()), maybe_headers, msg)?;
 
-        // Wait for the response
-        let result = if let Some(timeout) = maybe_timeout {
-            sub.next_timeout(timeout)
+        // Wait for the response, unsubscribing when returning early.
+        let result = if let Some(cancel) = maybe_cancel {
+            let timeout = deadline.map_or_else(crossbeam_channel::never, crossbeam_channel::at);
+            crossbeam_channel::select! {
+                recv(sub.receiver()) -> response => response.map_err(|_| ErrorKind::ConnectionReset.into()),
+                recv(cancel.receiver()) -> _ => Err(Error::new(ErrorKind::Interrupted, "request cancelled")),
+                recv(timeout) -> _ => Err(Error::new(ErrorKind::TimedOut, "request timed out")),
+            }
+        } else if let Some(deadline) = deadline {
+            sub.next_timeout(deadline.saturating_duration_since(Instant::now()))
         } else if let Some(msg) = sub.next() {
             Ok(msg)
         } else {
@@ -497,7 +1017,7 @@ impl Connection {
 
         // Check for no responder status.
         if let Ok(msg) = result.as_ref() {
-            if msg.is_no_responders() {
+            if msg.is_no_responders() && self.0.client.no_responders_enabled() {
                 return Err(Error::new(ErrorKind::NotFound, "no responders"));
             }
         }
@@ -505,6 +1025,34 @@ impl Connection {
         result
     }
 
+    /// Publish a message on the given subject as a request without waiting
+    /// for the response. The returned `RequestToken` can be used to collect
+    /// the response later, which allows issuing many concurrent requests from
+    /// a single thread.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// # nc.subscribe("foo")?.with_handler(move |m| { m.respond("ans=42")?; Ok(()) });
+    /// let tokens = vec![
+    ///     nc.request_async("foo", "Help me?")?,
+    ///     nc.request_async("foo", "Help me too?")?,
+    /// ];
+    /// for token in tokens {
+    ///     let resp = token.wait(std::time::Duration::from_secs(2))?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn request_async(&self, subject: &str, msg: impl AsRef<[u8]>) -> io::Result<RequestToken> {
+        // Publish a request.
+        let (reply, sub) = self.new_respond_inbox()?;
+        self.publish_with_reply_or_headers(subject, Some(reply.as_str()), None, msg)?;
+
+        Ok(RequestToken::new(sub))
+    }
+
     /// Publish a message on the given subject as a request and allow multiple
     /// responses.
     ///
@@ -527,9 +1075,77 @@ impl Connection {
         Ok(sub)
     }
 
-    /// Flush a NATS connection by sending a `PING` protocol and waiting for the
-    /// responding `PONG`. Will fail with `TimedOut` if the server does not
-    /// respond with in 10 seconds. Will fail with `NotConnected` if the
+    /// Subscribe to live updates on `subject` and request a snapshot of the
+    /// current state on `snapshot_subject`, such as a `JetStream` direct get
+    /// of the last message, combining both without a gap.
+    ///
+    /// The subscription is created before the snapshot is requested, so
+    /// updates published in the meantime are buffered and replayed after the
+    /// snapshot. A snapshot reply with a 404 status, which is how a
+    /// `JetStream` direct get reports a missing message, means that there is
+    /// no current state.
+    ///
+    /// Fails with `TimedOut` if the snapshot is not received within the
+    /// timeout and with `NotFound` if nobody responds to the request.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// # nc.subscribe("config.get")?.with_handler(move |m| { m.respond("v1")?; Ok(()) });
+    /// let timeout = std::time::Duration::from_secs(2);
+    /// let cache = nc.subscribe_with_backlog_replay("config.updates", "config.get", "", timeout)?;
+    /// for value in cache.take(1) {
+    ///     println!("current value: {}", value);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn subscribe_with_backlog_replay(
+        &self,
+        subject: &str,
+        snapshot_subject: &str,
+        snapshot_request: impl AsRef<[u8]>,
+        timeout: Duration,
+    ) -> io::Result<BacklogReplay> {
+        // The server processes the SUB before the request on this connection,
+        // so updates published after the snapshot are always received.
+        let sub = self.subscribe(subject)?;
+        let snapshot = self.request_timeout(snapshot_subject, snapshot_request, timeout)?;
+        Ok(BacklogReplay::new(snapshot, sub))
+    }
+
+    /// Publish a message on the given subject as a request and iterate over
+    /// the responses as they arrive, waiting at most `timeout` for each one.
+    ///
+    /// Iteration ends once no response arrives within the timeout. It fails
+    /// with `NotFound` if there were no responders. Dropping the iterator
+    /// unsubscribes from the responses, which allows stopping early.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// # nc.subscribe("foo")?.with_handler(move |m| { m.respond("ans=42")?; Ok(()) });
+    /// let timeout = std::time::Duration::from_millis(500);
+    /// for resp in nc.request_iter("foo", "Help", timeout)?.take(3) {
+    ///     println!("got response: {}", resp?);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn request_iter(
+        &self,
+        subject: &str,
+        msg: impl AsRef<[u8]>,
+        timeout: Duration,
+    ) -> io::Result<RequestIter> {
+        Ok(RequestIter::new(self.request_multi(subject, msg)?, timeout))
+    }
+
+    /// Flush a NATS connection by sending a `PING` protocol and waiting for the
+    /// responding `PONG`. Will fail with `TimedOut` if the server does not
+    /// respond with in 10 seconds. Will fail with `NotConnected` if the
     /// server is not currently connected. Will fail with `BrokenPipe` if
     /// the connection to the server is lost.
     ///
@@ -563,6 +1179,124 @@ impl Connection {
         self.0.client.flush(duration)
     }
 
+    /// Flush a NATS connection like `flush`, but only if there are buffered
+    /// bytes that have not been written to the socket yet. Otherwise returns
+    /// immediately without a round trip, so it is cheap to call defensively
+    /// in a loop. Unlike `flush`, it does not check that the server is alive.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// for i in 0..10 {
+    ///     if i % 3 == 0 {
+    ///         nc.publish("foo", "Hello World!")?;
+    ///     }
+    ///     nc.flush_if_pending()?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn flush_if_pending(&self) -> io::Result<()> {
+        self.0.client.flush_if_pending(DEFAULT_FLUSH_TIMEOUT)
+    }
+
+    /// Flush a NATS connection like `flush_timeout` and return the round
+    /// trip time observed while waiting for the `PONG`. This both ensures
+    /// that buffered messages reached the server and measures latency,
+    /// without a separate `rtt` round trip.
+    ///
+    /// If the client is disconnected, the returned duration includes the
+    /// time spent waiting for the reconnect.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// nc.publish("foo", "Hello World!")?;
+    /// let rtt = nc.flush_timed(std::time::Duration::from_secs(5))?;
+    /// println!("flushed, server rtt: {:?}", rtt);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn flush_timed(&self, timeout: Duration) -> io::Result<Duration> {
+        self.0.client.flush_timed(timeout)
+    }
+
+    /// Flushes buffered messages into the socket and, where the platform
+    /// supports it (Linux, Android, macOS and iOS), waits until the kernel
+    /// has sent them and the server has acknowledged receiving them at the
+    /// TCP level. Unlike `flush`, this does not make a round trip to the
+    /// server, but it guarantees that nothing is left in local buffers, which
+    /// is useful right before `close` in batch jobs.
+    ///
+    /// Fails with `NotConnected` if the client is not currently connected
+    /// and with `TimedOut` if the send buffer is not drained in time.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// nc.publish("foo", "Hello World!")?;
+    /// nc.flush_and_drain_writer(std::time::Duration::from_secs(5))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn flush_and_drain_writer(&self, timeout: Duration) -> io::Result<()> {
+        self.0.client.flush_and_drain_writer(timeout)
+    }
+
+    /// Blocks until the connection to a server is established, which is
+    /// useful for waiting on a reconnect to complete. Fails with `TimedOut`
+    /// if the connection is not established within the timeout, and with
+    /// `NotConnected` if the connection has been closed.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// nc.wait_connected(std::time::Duration::from_secs(5))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn wait_connected(&self, timeout: Duration) -> io::Result<()> {
+        self.0.client.wait_connected(timeout)
+    }
+
+    /// Returns the number of flushes that are waiting for a `PONG` from the
+    /// server. Combined with `rtt`, this can be used to detect round trips
+    /// backing up and to slow down publishing accordingly.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// println!("pending flushes: {}", nc.pending_flushes());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn pending_flushes(&self) -> usize {
+        self.0.client.pending_flushes()
+    }
+
+    /// Returns `true` if the current connection is secured by TLS.
+    ///
+    /// This reflects the negotiated outcome of the most recent connection
+    /// rather than the configured options, so it may change after a
+    /// reconnect to a different server.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// println!("encrypted: {}", nc.is_tls());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn is_tls(&self) -> bool {
+        self.0.client.is_tls()
+    }
+
     /// Close a NATS connection. All clones of
     /// this `Connection` will also be closed,
     /// as the backing IO threads are shared.
@@ -598,9 +1332,190 @@ impl Connection {
     /// # }
     /// ```
     pub fn rtt(&self) -> io::Result<Duration> {
-        let start = Instant::now();
-        self.flush()?;
-        Ok(start.elapsed())
+        self.flush_timed(DEFAULT_FLUSH_TIMEOUT)
+    }
+
+    /// Replaces the callback invoked when an async error from the server has
+    /// been received, overriding the one set with `Options::error_callback`.
+    ///
+    /// The callback can be replaced at any time, including from within the
+    /// callback itself, for example to escalate after repeated failures.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// nc.set_error_callback(|err| eprintln!("connection received an error: {}", err));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_error_callback<F>(&self, cb: F)
+    where
+        F: Fn(Error) + Send + Sync + 'static,
+    {
+        self.0.client.set_error_callback(cb);
+    }
+
+    /// Returns how many outgoing bytes are waiting in the reconnect buffer
+    /// while disconnected, and in the write buffer of the current connection
+    /// before being flushed.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// nc.publish("foo", "bar")?;
+    /// let buffered = nc.buffered_bytes();
+    /// println!("reconnect: {}, writer: {}", buffered.reconnect, buffered.writer);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn buffered_bytes(&self) -> BufferedBytes {
+        self.0.client.buffered_bytes()
+    }
+
+    /// Returns the limits on pending data the server enforces before
+    /// disconnecting this client as a slow consumer.
+    ///
+    /// Limits the server does not advertise in its INFO are taken from the
+    /// estimates configured with `Options::pending_limits`.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// let limits = nc.pending_limits();
+    /// println!("bytes: {}, messages: {}", limits.bytes, limits.messages);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn pending_limits(&self) -> PendingLimits {
+        self.0.client.pending_limits()
+    }
+
+    /// Estimates how close this client is to being disconnected as a slow
+    /// consumer, from 0.0 for no backlog to 1.0 for a backlog at the pending
+    /// limits.
+    ///
+    /// This is the larger of the fractions of the byte and message limits
+    /// taken by messages that have arrived but were not received from their
+    /// subscriptions yet, including those held back by an ack window.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// if nc.slow_consumer_risk() > 0.8 {
+    ///     eprintln!("falling behind the server");
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn slow_consumer_risk(&self) -> f32 {
+        self.0.client.slow_consumer_risk()
+    }
+
+    /// Returns how many lines the server sent that were not a known
+    /// protocol operation, over the lifetime of this connection.
+    ///
+    /// A growing count can point at a protocol version mismatch or a proxy
+    /// injecting data into the connection.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// assert_eq!(nc.unknown_op_count(), 0);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn unknown_op_count(&self) -> usize {
+        self.0.client.unknown_op_count()
+    }
+
+    /// Returns how many complete messages are waiting in the reconnect
+    /// buffer while disconnected, which would be lost if the buffer is
+    /// dropped or the oldest messages are evicted.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// if nc.buffered_publish_count() > 0 {
+    ///     println!("{} messages waiting for a reconnect", nc.buffered_publish_count());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn buffered_publish_count(&self) -> usize {
+        self.0.client.buffered_publish_count()
+    }
+
+    /// Nudges the background flusher to write buffered messages to the
+    /// server soon, without blocking.
+    ///
+    /// This is a lighter alternative to `flush` when batching publishes: it
+    /// does not wait for a round trip to the server, so it gives no
+    /// guarantee that the messages were received.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// for i in 0..100 {
+    ///     nc.publish("foo", i.to_string())?;
+    /// }
+    /// nc.kick_flush();
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn kick_flush(&self) {
+        self.0.client.kick_flush();
+    }
+
+    /// Checks that the connection is alive by sending a `PING` and waiting
+    /// for the responding `PONG`.
+    ///
+    /// Unlike `flush_timeout`, this is a liveness probe: it fails with
+    /// `NotConnected` right away if the client is reconnecting rather than
+    /// waiting for the connection to be reestablished, and with `TimedOut`
+    /// if the server does not respond in time. Messages already written to
+    /// the connection are still sent ahead of the `PING`.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// nc.ping(std::time::Duration::from_secs(1))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn ping(&self, timeout: Duration) -> io::Result<()> {
+        self.0.client.ping(timeout)
+    }
+
+    /// Returns the server information each time the server sends an updated
+    /// `INFO`, for example when a server joins the cluster, and each time
+    /// the client reconnects.
+    ///
+    /// Up to 16 updates are held until they are received, after which the
+    /// oldest ones are discarded.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// let changes = nc.server_info_changes();
+    /// std::thread::spawn(move || {
+    ///     for info in changes {
+    ///         println!("cluster urls: {:?}", info.connect_urls);
+    ///     }
+    /// });
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn server_info_changes(&self) -> ServerInfoChanges {
+        self.0.client.server_info_changes()
     }
 
     /// Returns the client IP as known by the server.
@@ -616,8 +1531,8 @@ impl Connection {
     pub fn client_ip(&self) -> io::Result<std::net::IpAddr> {
         let info = self.0.client.server_info();
 
-        match info.client_ip.as_str() {
-            "" => Err(Error::new(
+        match info.client_ip.as_deref() {
+            None => Err(Error::new(
                 ErrorKind::Other,
                 &*format!(
                     "client_ip was not provided by the server. It is \
@@ -626,14 +1541,14 @@ impl Connection {
                     info.version
                 ),
             )),
-            ip => match ip.parse() {
+            Some(ip) => match ip.parse() {
                 Ok(addr) => Ok(addr),
                 Err(_) => Err(Error::new(
                     ErrorKind::InvalidData,
                     &*format!(
                         "client_ip provided by the server cannot be parsed. \
                          The server provided IP: {}",
-                        info.client_ip
+                        ip
                     ),
                 )),
             },
@@ -695,6 +1610,52 @@ impl Connection {
         Ok(())
     }
 
+    /// Flush and close the connection like `drain`, returning a handle that
+    /// can be used to wait until the background threads of the connection
+    /// have exited and the final flush has completed.
+    ///
+    /// Unlike `close`, the connection to the server is shut down right away,
+    /// so that the threads exit without waiting for further messages from
+    /// the server.
+    ///
+    /// If the flush fails, the connection is closed all the same and the
+    /// error is returned.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// nc.publish("foo", "bar")?;
+    /// let handle = nc.drain_and_close()?;
+    /// handle.join(std::time::Duration::from_secs(5))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn drain_and_close(&self) -> io::Result<ShutdownHandle> {
+        self.0.client.drain_and_close(DEFAULT_FLUSH_TIMEOUT)
+    }
+
+    /// Drain a connection used by `JetStream` pull consumers. New batches
+    /// stop being fetched, in-flight `ack` and `double_ack` calls are given
+    /// until `timeout` to complete, and only then are all subscriptions torn
+    /// down and the connection closed. This avoids redelivery of messages
+    /// whose acks were still being sent during a graceful shutdown.
+    ///
+    /// Fails with `TimedOut` if the in-flight acks did not complete in time,
+    /// in which case the connection is closed regardless.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// nc.drain_jetstream(std::time::Duration::from_secs(5))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn drain_jetstream(&self, timeout: Duration) -> io::Result<()> {
+        self.0.client.drain_jetstream(timeout)
+    }
+
     /// Publish a message which may have a reply subject or headers set.
     ///
     /// # Example
@@ -722,6 +1683,255 @@ impl Connection {
         self.0.client.publish(subject, reply, headers, msg.as_ref())
     }
 
+    /// Publish a message like `publish_with_reply_or_headers`, returning the
+    /// number of bytes it takes on the wire, including the subject, headers
+    /// and protocol overhead.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// let written = nc.publish_sized("foo", None, None, "bar")?;
+    /// assert_eq!(written, "PUB foo 3\r\nbar\r\n".len());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn publish_sized(
+        &self,
+        subject: &str,
+        reply: Option<&str>,
+        headers: Option<&Headers>,
+        msg: impl AsRef<[u8]>,
+    ) -> io::Result<usize> {
+        self.0
+            .client
+            .publish_sized(subject, reply, headers, msg.as_ref())
+    }
+
+    /// Publish a message and flush the connection, returning once the server
+    /// has received the message. Fails with the publishing error without
+    /// flushing if the message cannot be published.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// let timeout = std::time::Duration::from_secs(2);
+    /// nc.publish_and_flush("foo", None, None, "Hello World!", timeout)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn publish_and_flush(
+        &self,
+        subject: &str,
+        reply: Option<&str>,
+        headers: Option<&Headers>,
+        msg: impl AsRef<[u8]>,
+        timeout: Duration,
+    ) -> io::Result<()> {
+        self.0
+            .client
+            .publish_and_flush(subject, reply, headers, msg.as_ref(), timeout)
+    }
+
+    /// Publish a message and wait until the server has received it. If the
+    /// connection is lost meanwhile, waits for the reconnect and publishes
+    /// the message once more.
+    ///
+    /// Fails if the message cannot be confirmed within the timeout, which
+    /// bounds the whole call including the wait for a reconnect. A retried
+    /// message may be delivered twice if the first attempt reached the
+    /// server before the connection was lost.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// let timeout = std::time::Duration::from_secs(10);
+    /// nc.publish_reliable("foo", None, None, "Hello World!", timeout)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn publish_reliable(
+        &self,
+        subject: &str,
+        reply: Option<&str>,
+        headers: Option<&Headers>,
+        msg: impl AsRef<[u8]>,
+        timeout: Duration,
+    ) -> io::Result<()> {
+        self.0
+            .client
+            .publish_reliable(subject, reply, headers, msg.as_ref(), timeout)
+    }
+
+    /// Publish a message, failing with `TimedOut` instead of blocking if the
+    /// socket stalls for longer than the timeout while the message is
+    /// written. Unlike `publish_and_flush`, this does not wait for the
+    /// server to receive the message.
+    ///
+    /// Since a timed out write may leave a partial message on the socket,
+    /// the connection is reestablished afterwards.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// let timeout = std::time::Duration::from_millis(100);
+    /// nc.publish_with_timeout("foo", None, None, "Hello World!", timeout)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn publish_with_timeout(
+        &self,
+        subject: &str,
+        reply: Option<&str>,
+        headers: Option<&Headers>,
+        msg: impl AsRef<[u8]>,
+        timeout: Duration,
+    ) -> io::Result<()> {
+        self.0
+            .client
+            .publish_with_timeout(subject, reply, headers, msg.as_ref(), timeout)
+    }
+
+    /// Publish a message only if the client is currently connected.
+    ///
+    /// Returns `Ok(false)` without buffering the message if the client is
+    /// reconnecting, so it will not be replayed once the connection is
+    /// reestablished. This is useful for best-effort messages such as
+    /// telemetry.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// if !nc.publish_if_connected("telemetry", None, None, "cpu=42")? {
+    ///     println!("dropped telemetry while disconnected");
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn publish_if_connected(
+        &self,
+        subject: &str,
+        reply: Option<&str>,
+        headers: Option<&Headers>,
+        msg: impl AsRef<[u8]>,
+    ) -> io::Result<bool> {
+        self.0
+            .client
+            .publish_if_connected(subject, reply, headers, msg.as_ref())
+    }
+
+    /// Publish data that may exceed the server's maximum payload size as a
+    /// sequence of chunks of at most `chunk_size` bytes, returning the number
+    /// of published chunks. Use `Subscription::reassemble` to receive the
+    /// data as a single message.
+    ///
+    /// Each chunk is tagged with the `Nats-Chunk-Id`, `Nats-Chunk-Index` and
+    /// `Nats-Chunk-Total` headers, so the server must support headers. The
+    /// chunk size is capped so that every chunk including its headers fits
+    /// into the server's maximum payload.
+    ///
+    /// Fails with `InvalidInput` if `chunk_size` is zero. Chunks published
+    /// before an error are not retracted.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// let blob = vec![0; 4 * 1024 * 1024];
+    /// let chunks = nc.publish_chunked("blobs", &blob, 512 * 1024)?;
+    /// assert!(chunks >= 8);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn publish_chunked(
+        &self,
+        subject: &str,
+        data: impl AsRef<[u8]>,
+        chunk_size: usize,
+    ) -> io::Result<usize> {
+        chunked::publish(&self.0.client, subject, data.as_ref(), chunk_size)
+    }
+
+    /// Publish a value serialized as JSON.
+    ///
+    /// Requires the `json` feature.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// nc.publish_json("points", &(3, 4))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "json")]
+    pub fn publish_json<T: serde::Serialize + ?Sized>(
+        &self,
+        subject: &str,
+        value: &T,
+    ) -> io::Result<()> {
+        self.publish(subject, serde_json::to_vec(value)?)
+    }
+
+    /// Create a subscription whose messages are deserialized from JSON.
+    /// Messages that are not valid JSON for `T` yield an `InvalidData`
+    /// error rather than being dropped.
+    ///
+    /// Requires the `json` feature.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// let points = nc.subscribe_json::<(i32, i32)>("points")?;
+    /// nc.publish_json("points", &(3, 4))?;
+    /// let (x, y) = points.next_timeout(std::time::Duration::from_secs(5))?;
+    /// println!("received ({}, {})", x, y);
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "json")]
+    pub fn subscribe_json<T: serde::de::DeserializeOwned>(
+        &self,
+        subject: &str,
+    ) -> io::Result<JsonSubscription<T>> {
+        self.subscribe(subject).map(JsonSubscription::new)
+    }
+
+    /// Compress the data with the given algorithm and publish it with a
+    /// `Content-Encoding` header announcing the algorithm, so that receivers
+    /// can restore it with `Message::decompressed`.
+    ///
+    /// Requires the `compression` feature and a server supporting headers.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// let sub = nc.subscribe("logs")?;
+    /// nc.publish_compressed("logs", "a long log line", nats::Compression::Zstd)?;
+    /// let msg = sub.next().unwrap();
+    /// assert_eq!(msg.decompressed()?, b"a long log line");
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "compression")]
+    pub fn publish_compressed(
+        &self,
+        subject: &str,
+        data: impl AsRef<[u8]>,
+        algorithm: Compression,
+    ) -> io::Result<()> {
+        let (headers, compressed) = compression::compress(data.as_ref(), algorithm)?;
+        self.0
+            .client
+            .publish(subject, None, Some(&headers), &compressed)
+    }
+
     /// Returns the maximum payload size the most recently
     /// connected server will accept.
     ///
@@ -760,3 +1970,197 @@ impl Connection {
             .try_publish(subject, reply, headers, msg.as_ref())
     }
 }
+
+#[cfg(test)]
+mod server_info {
+    use super::*;
+
+    fn info(version: &str, headers: bool) -> ServerInfo {
+        ServerInfo {
+            version: version.to_string(),
+            headers,
+            ..ServerInfo::default()
+        }
+    }
+
+    #[test]
+    fn supports() {
+        assert!(info("2.2.0", true).supports(ServerFeature::Headers));
+        assert!(info("2.2.0", true).supports(ServerFeature::NoResponders));
+        assert!(!info("2.2.0", false).supports(ServerFeature::NoResponders));
+
+        assert!(info("2.1.6", false).supports(ServerFeature::ClientIp));
+        assert!(info("2.10.0-beta.1", false).supports(ServerFeature::ClientIp));
+        assert!(!info("2.1.5", false).supports(ServerFeature::ClientIp));
+        assert!(!info("2.1", false).supports(ServerFeature::ClientIp));
+        assert!(!info("unknown", false).supports(ServerFeature::ClientIp));
+    }
+
+    #[test]
+    fn version_parts() {
+        assert_eq!(info("2.9.0", false).version_parts(), Some((2, 9, 0)));
+        assert_eq!(
+            info("2.10.3-beta.1", false).version_parts(),
+            Some((2, 10, 3))
+        );
+        assert_eq!(
+            info("2.2.0+build.5", false).version_parts(),
+            Some((2, 2, 0))
+        );
+        assert!(info("2.10.0", false).version_parts() > info("2.9.22", false).version_parts());
+
+        assert_eq!(info("", false).version_parts(), None);
+        assert_eq!(info("2.9", false).version_parts(), None);
+        assert_eq!(info("2.9.0.1", false).version_parts(), None);
+        assert_eq!(info("v2.9.0", false).version_parts(), None);
+    }
+
+    #[test]
+    fn pending_limits() {
+        let base = r#""server_id":"test","host":"127.0.0.1","port":4222,"version":"2.2.0","go":"go1.16","proto":1,"max_payload":1048576"#;
+
+        let info = ServerInfo::parse(&format!("{{{}}}", base)).unwrap();
+        assert_eq!(info.max_pending, None);
+        assert_eq!(info.max_pending_msgs, None);
+
+        let info = ServerInfo::parse(&format!(
+            r#"{{{},"max_pending":67108864,"max_pending_msgs":1000}}"#,
+            base
+        ))
+        .unwrap();
+        assert_eq!(info.max_pending, Some(67_108_864));
+        assert_eq!(info.max_pending_msgs, Some(1000));
+    }
+}
+
+#[cfg(test)]
+mod subscribe_callback {
+    use super::*;
+    use crate::mock_server::{MockServer, Step};
+
+    #[test]
+    fn errors_and_panics_reported() {
+        // Deliver three messages once subscribed.
+        let url = MockServer::new().serve(|session, line| {
+            if line.starts_with("SUB ") {
+                for data in &["fail", "panic", "ok"] {
+                    session.write(format!("MSG jobs 1 {}\r\n{}\r\n", data.len(), data))?;
+                }
+            }
+            Ok(Step::Continue)
+        });
+
+        let (err_tx, err_rx) = crossbeam_channel::unbounded();
+        let nc = Options::new()
+            .error_callback(move |err| err_tx.send(err.to_string()).unwrap())
+            .connect(&url)
+            .unwrap();
+        let (handled_tx, handled_rx) = crossbeam_channel::unbounded();
+        let _handler = nc
+            .subscribe_callback("jobs", move |msg| match &msg.data[..] {
+                b"fail" => Err(Error::new(ErrorKind::Other, "job failed")),
+                b"panic" => panic!("job panicked"),
+                _ => {
+                    handled_tx.send(()).unwrap();
+                    Ok(())
+                }
+            })
+            .unwrap();
+
+        let timeout = Duration::from_secs(5);
+        handled_rx.recv_timeout(timeout).unwrap();
+        assert_eq!(err_rx.recv_timeout(timeout).unwrap(), "job failed");
+        assert_eq!(
+            err_rx.recv_timeout(timeout).unwrap(),
+            "handler for subscription to \"jobs\" panicked: job panicked"
+        );
+
+        nc.close();
+    }
+}
+
+#[cfg(test)]
+mod request_cancel {
+    use super::*;
+    use crate::mock_server::MockServer;
+    use std::thread;
+
+    #[test]
+    fn cancelled_request_unsubscribes() {
+        // The server never replies to requests.
+        let (url, unsub_rx) = MockServer::new().reporting("UNSUB ");
+        let nc = Options::new().connect(&url).unwrap();
+        let cancel = CancelToken::new();
+        let canceller = cancel.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            canceller.cancel();
+        });
+
+        let start = Instant::now();
+        let timeout = Duration::from_secs(60);
+        let err = nc
+            .request_with_cancel("slow", "", timeout, &cancel)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Interrupted);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        unsub_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        nc.close();
+    }
+
+    #[test]
+    fn cancelled_request_is_not_published() {
+        let (url, pub_rx) = MockServer::new().reporting("PUB ");
+        let nc = Options::new().connect(&url).unwrap();
+        let cancel = CancelToken::new();
+        cancel.cancel();
+
+        let timeout = Duration::from_secs(60);
+        let err = nc
+            .request_with_cancel("slow", "", timeout, &cancel)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Interrupted);
+        nc.flush().unwrap();
+        assert!(pub_rx.try_recv().is_err());
+
+        nc.close();
+    }
+}
+
+#[cfg(test)]
+mod subscription_iter {
+    use super::*;
+    use crate::mock_server::{MockServer, Step};
+
+    #[test]
+    fn iterates_and_unsubscribes_on_drop() {
+        // Deliver three messages once subscribed, and report UNSUBs.
+        let (unsub_tx, unsub_rx) = crossbeam_channel::unbounded();
+        let url = MockServer::new().serve(move |session, line| {
+            if line.starts_with("SUB ") {
+                for i in 1..=3 {
+                    session.write(format!("MSG events 1 1\r\n{}\r\n", i))?;
+                }
+            } else if line.starts_with("UNSUB ") {
+                unsub_tx.send(line.to_string()).ok();
+            }
+            Ok(Step::Continue)
+        });
+
+        let nc = Options::new().connect(&url).unwrap();
+        let data: Vec<Vec<u8>> = nc
+            .subscribe("events")
+            .unwrap()
+            .take(2)
+            .map(|msg| msg.data)
+            .collect();
+        assert_eq!(data, vec![b"1".to_vec(), b"2".to_vec()]);
+        assert_eq!(
+            unsub_rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            "UNSUB 1"
+        );
+
+        nc.close();
+    }
+}
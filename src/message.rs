@@ -12,8 +12,9 @@
 // limitations under the License.
 
 use std::fmt::{self};
+use std::io;
 
-use crate::Headers;
+use crate::{Connection, Headers};
 
 /// Message representing NATS message.
 #[derive(Clone)]
@@ -32,6 +33,193 @@ pub struct Message {
     pub headers: Option<Headers>,
 }
 
+/// Implements the accessors shared by `Message`, `client::Message` and
+/// `asynk::Message`, which all have the public `subject`, `data` and
+/// `headers` fields and a private `max_decompressed_size` method.
+macro_rules! message_accessors {
+    () => {
+        /// Converts the payload into `Bytes`, which can be cheaply cloned and
+        /// sliced when fanning the payload out to several consumers.
+        ///
+        /// The payload is handed over without being copied.
+        #[cfg(feature = "bytes")]
+        pub fn data_bytes(self) -> bytes::Bytes {
+            bytes::Bytes::from(self.data)
+        }
+
+        /// Returns the value of the `Content-Type` header, if set.
+        pub fn content_type(&self) -> Option<&str> {
+            self.headers.as_ref()?.content_type()
+        }
+
+        /// Returns the payload decompressed according to its
+        /// `Content-Encoding` header, as set by
+        /// `Connection::publish_compressed`, or a copy of it if the header is
+        /// not set.
+        ///
+        /// Fails with `InvalidData` for unsupported encodings and for
+        /// payloads that decompress to more than
+        /// `Options::max_incoming_message_size` of the connection the message
+        /// was received on, or the server's `max_payload` if that is not set.
+        /// Messages that are not tied to a connection are limited to 1 MiB,
+        /// the default `max_payload` of the server.
+        ///
+        /// Requires the `compression` feature.
+        #[cfg(feature = "compression")]
+        pub fn decompressed(&self) -> std::io::Result<Vec<u8>> {
+            crate::compression::decompress(
+                self.headers.as_ref(),
+                &self.data,
+                self.max_decompressed_size(),
+            )
+        }
+
+        /// Returns an iterator over the `.` separated tokens of the subject,
+        /// without allocating.
+        ///
+        /// # Example
+        /// ```
+        /// let msg = nats::MessageBuilder::new("events.acme.created").build();
+        /// let tokens: Vec<&str> = msg.subject_tokens().collect();
+        /// assert_eq!(tokens, ["events", "acme", "created"]);
+        /// ```
+        pub fn subject_tokens(&self) -> impl Iterator<Item = &str> {
+            self.subject.split('.')
+        }
+
+        /// Returns the token of the subject at the zero-based index, if the
+        /// subject has that many tokens.
+        ///
+        /// # Example
+        /// ```
+        /// let msg = nats::MessageBuilder::new("events.acme.created").build();
+        /// assert_eq!(msg.subject_token(1), Some("acme"));
+        /// assert_eq!(msg.subject_token(3), None);
+        /// ```
+        pub fn subject_token(&self, index: usize) -> Option<&str> {
+            self.subject_tokens().nth(index)
+        }
+
+        /// Returns a mutable reference to the headers of this message,
+        /// initializing them to an empty set of headers if none are present.
+        ///
+        /// # Example
+        /// ```
+        /// # use std::{collections::HashSet, iter::FromIterator};
+        /// let mut msg = nats::Message {
+        ///     subject: "foo".to_string(),
+        ///     reply: None,
+        ///     data: b"hello".to_vec(),
+        ///     headers: None,
+        /// };
+        ///
+        /// msg.headers_mut()
+        ///     .inner
+        ///     .insert("X-Trace-Id".to_string(), HashSet::from_iter(vec!["1".to_string()]));
+        ///
+        /// assert_eq!(msg.headers.unwrap().len(), 1);
+        /// ```
+        pub fn headers_mut(&mut self) -> &mut crate::Headers {
+            self.headers.get_or_insert_with(crate::Headers::default)
+        }
+    };
+}
+
+pub(crate) use message_accessors;
+
+impl Message {
+    message_accessors!();
+
+    /// Returns the largest payload `decompressed` may produce.
+    #[cfg(feature = "compression")]
+    fn max_decompressed_size(&self) -> usize {
+        crate::compression::DEFAULT_MAX_DECOMPRESSED
+    }
+}
+
+/// A fluent builder for a `Message`, which can either be built or published
+/// directly.
+///
+/// # Example
+/// ```
+/// # fn main() -> std::io::Result<()> {
+/// # let nc = nats::connect("demo.nats.io")?;
+/// nats::MessageBuilder::new("orders.created")
+///     .reply("orders.replies")
+///     .header("X-Trace-Id", "1")
+///     .data("order 42")
+///     .publish(&nc)?;
+///
+/// let msg = nats::MessageBuilder::new("orders.created")
+///     .data("order 42")
+///     .build();
+/// assert_eq!(msg.subject, "orders.created");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct MessageBuilder {
+    msg: Message,
+}
+
+impl MessageBuilder {
+    /// Starts building an empty message on the subject.
+    pub fn new(subject: &str) -> MessageBuilder {
+        MessageBuilder {
+            msg: Message {
+                subject: subject.to_string(),
+                reply: None,
+                data: Vec::new(),
+                headers: None,
+            },
+        }
+    }
+
+    /// Sets the reply subject.
+    pub fn reply(mut self, reply: &str) -> MessageBuilder {
+        self.msg.reply = Some(reply.to_string());
+        self
+    }
+
+    /// Replaces all headers.
+    pub fn headers(mut self, headers: Headers) -> MessageBuilder {
+        self.msg.headers = Some(headers);
+        self
+    }
+
+    /// Adds a value for the header, keeping its existing values.
+    pub fn header(mut self, name: &str, value: &str) -> MessageBuilder {
+        self.msg
+            .headers_mut()
+            .inner
+            .entry(name.to_string())
+            .or_default()
+            .insert(value.to_string());
+        self
+    }
+
+    /// Sets the payload.
+    pub fn data(mut self, data: impl Into<Vec<u8>>) -> MessageBuilder {
+        self.msg.data = data.into();
+        self
+    }
+
+    /// Returns the built message.
+    pub fn build(self) -> Message {
+        self.msg
+    }
+
+    /// Publishes the message on the connection.
+    pub fn publish(&self, nc: &Connection) -> io::Result<()> {
+        nc.publish_with_reply_or_headers(
+            &self.msg.subject,
+            self.msg.reply.as_deref(),
+            self.msg.headers.as_ref(),
+            &self.msg.data,
+        )
+    }
+}
+
 impl fmt::Debug for Message {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         f.debug_struct("Message")
@@ -0,0 +1,295 @@
+// Copyright 2020-2021 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A scripted NATS server for unit tests that need to observe or inject
+//! individual protocol lines.
+
+use std::io::{self, BufRead, BufReader, Error, ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+
+use crossbeam_channel as channel;
+
+use crate::auth_utils;
+use crate::rustls::{NoClientAuth, ServerConfig, ServerSession, StreamOwned};
+
+/// The CA that signed the certificate of a server created with
+/// `MockServer::with_tls`.
+pub(crate) const ROOT_CA: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/configs/certs/rootCA.pem"
+);
+
+/// Returns an INFO line with the given fields added to the defaults.
+pub(crate) fn info(fields: &[(&str, serde_json::Value)]) -> String {
+    let mut info = serde_json::json!({
+        "server_id": "test",
+        "host": "127.0.0.1",
+        "port": 4222,
+        "version": "2.2.0",
+        "go": "go1.16",
+        "proto": 1,
+        "max_payload": 1_048_576,
+    });
+    for (name, value) in fields {
+        info[*name] = value.clone();
+    }
+    format!("INFO {}\r\n", info)
+}
+
+/// What the server does after a hook has seen a line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Step {
+    /// Answer the line with a PONG if it is a PING, and keep reading.
+    Continue,
+    /// Keep reading without answering, because the hook already did.
+    Handled,
+    /// Close the connection and wait for the next client.
+    Disconnect,
+}
+
+/// The connection to a client, upgraded to TLS if the server requires it.
+enum Stream {
+    Tcp(TcpStream),
+    Tls(Box<StreamOwned<ServerSession, TcpStream>>),
+}
+
+impl Stream {
+    fn tcp(&self) -> &TcpStream {
+        match self {
+            Stream::Tcp(tcp) => tcp,
+            Stream::Tls(tls) => &tls.sock,
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(tcp) => tcp.read(buf),
+            Stream::Tls(tls) => tls.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(tcp) => tcp.write(buf),
+            Stream::Tls(tls) => tls.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(tcp) => tcp.flush(),
+            Stream::Tls(tls) => tls.flush(),
+        }
+    }
+}
+
+/// A client connection, passed to the hook with every line.
+pub(crate) struct Session {
+    /// The number of connections accepted before this one.
+    pub(crate) index: usize,
+    reader: BufReader<Stream>,
+}
+
+impl Session {
+    /// Writes raw protocol data to the client.
+    pub(crate) fn write(&mut self, data: impl AsRef<[u8]>) -> io::Result<()> {
+        let stream = self.reader.get_mut();
+        stream.write_all(data.as_ref())?;
+        stream.flush()
+    }
+
+    /// Reads the next line, such as a payload following a PUB, without the
+    /// trailing CRLF.
+    pub(crate) fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        if !line.ends_with("\r\n") {
+            return Err(Error::new(ErrorKind::InvalidData, "line without CRLF"));
+        }
+        line.truncate(line.len() - 2);
+        Ok(line)
+    }
+
+    /// Returns a handle for writing to the client from another thread.
+    ///
+    /// Only plain TCP sessions have one.
+    pub(crate) fn writer(&self) -> io::Result<TcpStream> {
+        match self.reader.get_ref() {
+            Stream::Tcp(tcp) => tcp.try_clone(),
+            Stream::Tls(_) => Err(Error::new(
+                ErrorKind::Other,
+                "cannot write to a TLS session from another thread",
+            )),
+        }
+    }
+
+    /// Upgrades the connection to TLS after the INFO has been sent.
+    fn start_tls(&mut self, config: &Option<Arc<ServerConfig>>) -> io::Result<()> {
+        let config = match config {
+            Some(config) => config,
+            None => return Ok(()),
+        };
+        let tcp = self.reader.get_ref().tcp().try_clone()?;
+        let tls = StreamOwned::new(ServerSession::new(config), tcp);
+        self.reader = BufReader::new(Stream::Tls(Box::new(tls)));
+        Ok(())
+    }
+}
+
+/// A server on a local port that sends an INFO to every client, passes each
+/// line it receives to a hook and answers PINGs unless told otherwise.
+///
+/// Connections are served one after another, so a client that reconnects
+/// gets a new session once the previous one has ended.
+pub(crate) struct MockServer {
+    listener: TcpListener,
+    info: String,
+    tls: Option<Arc<ServerConfig>>,
+}
+
+impl MockServer {
+    /// Binds a server sending the default INFO.
+    pub(crate) fn new() -> MockServer {
+        MockServer::with_info(info(&[]))
+    }
+
+    /// Binds a server sending the given INFO line.
+    pub(crate) fn with_info(info: String) -> MockServer {
+        MockServer {
+            listener: TcpListener::bind("127.0.0.1:0").unwrap(),
+            info,
+            tls: None,
+        }
+    }
+
+    /// Binds a server that requires TLS, using the certificate for
+    /// `localhost` in `tests/configs/certs`.
+    ///
+    /// Clients have to trust `tests/configs/certs/rootCA.pem`, see
+    /// `ROOT_CA`.
+    pub(crate) fn with_tls() -> MockServer {
+        let certs =
+            auth_utils::parse_certs(include_bytes!("../tests/configs/certs/server-cert.pem"))
+                .unwrap();
+        let key =
+            auth_utils::parse_key(include_bytes!("../tests/configs/certs/server-key.pem")).unwrap();
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config.set_single_cert(certs, key).unwrap();
+
+        // The certificate names `localhost`, which the client checks against
+        // the host in the INFO.
+        let info = info(&[("host", "localhost".into()), ("tls_required", true.into())]);
+        MockServer {
+            tls: Some(Arc::new(config)),
+            ..MockServer::with_info(info)
+        }
+    }
+
+    /// Returns the address the server listens on.
+    pub(crate) fn addr(&self) -> SocketAddr {
+        self.listener.local_addr().unwrap()
+    }
+
+    /// Returns the URL for connecting to the server.
+    pub(crate) fn url(&self) -> String {
+        format!("nats://{}", self.addr())
+    }
+
+    /// Serves clients on a background thread, only answering PINGs.
+    pub(crate) fn answer_pings(self) -> String {
+        self.serve(|_, _| Ok(Step::Continue))
+    }
+
+    /// Serves clients on a background thread, answering PINGs and reporting
+    /// every line that starts with the prefix.
+    pub(crate) fn reporting(self, prefix: &'static str) -> (String, channel::Receiver<String>) {
+        let (line_tx, line_rx) = channel::unbounded();
+        let url = self.serve(move |_, line| {
+            if line.starts_with(prefix) {
+                line_tx.send(line.to_string()).ok();
+            }
+            Ok(Step::Continue)
+        });
+        (url, line_rx)
+    }
+
+    /// Serves clients on a background thread, stopping to read from a client
+    /// once the handshake is complete, until the returned sender is used.
+    pub(crate) fn stall_after_handshake(self) -> (String, channel::Sender<()>) {
+        let (done_tx, done_rx) = channel::bounded::<()>(1);
+        let url = self.serve(move |session, line| {
+            if line == "PING" {
+                session.write(b"PONG\r\n")?;
+                done_rx.recv().ok();
+                return Ok(Step::Disconnect);
+            }
+            Ok(Step::Continue)
+        });
+        (url, done_tx)
+    }
+
+    /// Serves clients on a background thread, calling the hook with every
+    /// line received, and returns the URL of the server.
+    ///
+    /// A session ends when the client goes away, the hook fails or returns
+    /// `Step::Disconnect`.
+    pub(crate) fn serve<F>(self, mut hook: F) -> String
+    where
+        F: FnMut(&mut Session, &str) -> io::Result<Step> + Send + 'static,
+    {
+        let url = self.url();
+        thread::spawn(move || {
+            for index in 0.. {
+                let stream = match self.listener.accept() {
+                    Ok((stream, _)) => stream,
+                    Err(_) => return,
+                };
+                let mut session = Session {
+                    index,
+                    reader: BufReader::new(Stream::Tcp(stream)),
+                };
+                if session.write(&self.info).is_ok() && session.start_tls(&self.tls).is_ok() {
+                    run(&mut session, &mut hook);
+                }
+                session.reader.get_ref().tcp().shutdown(Shutdown::Both).ok();
+            }
+        });
+        url
+    }
+}
+
+/// Feeds the lines of a session to the hook until it ends.
+fn run<F>(session: &mut Session, hook: &mut F)
+where
+    F: FnMut(&mut Session, &str) -> io::Result<Step>,
+{
+    while let Ok(line) = session.read_line() {
+        match hook(session, &line) {
+            Ok(Step::Continue) if line == "PING" => {
+                if session.write(b"PONG\r\n").is_err() {
+                    return;
+                }
+            }
+            Ok(Step::Continue) | Ok(Step::Handled) => {}
+            Ok(Step::Disconnect) | Err(_) => return,
+        }
+    }
+}
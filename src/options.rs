@@ -11,7 +11,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::borrow::Cow;
 use std::cmp;
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fmt;
 use std::io;
@@ -21,10 +23,34 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use crate::auth_utils;
-use crate::secure_wipe::SecureString;
+use crate::proto::ClientOp;
+use crate::secure_wipe::{SecureString, SecureVec};
 use crate::Client;
 use crate::Connection;
 
+/// What to do with messages published while disconnected once the reconnect
+/// buffer is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BufferPolicy {
+    /// Reject new messages with an error until the connection is
+    /// reestablished.
+    RejectNew,
+
+    /// Evict the oldest buffered messages to make room for new ones.
+    DropOldest,
+}
+
+/// Limits on the data a server buffers for a client before disconnecting
+/// it as a slow consumer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PendingLimits {
+    /// Maximum number of pending bytes. Zero means unlimited.
+    pub bytes: usize,
+
+    /// Maximum number of pending messages. Zero means unlimited.
+    pub messages: usize,
+}
+
 /// Connect options.
 pub struct Options {
     pub(crate) auth: AuthStyle,
@@ -32,17 +58,44 @@ pub struct Options {
     pub(crate) no_echo: bool,
     pub(crate) max_reconnects: Option<usize>,
     pub(crate) reconnect_buffer_size: usize,
+    pub(crate) reconnect_buffer_persist: Option<PathBuf>,
+    pub(crate) reconnect_buffer_policy: BufferPolicy,
+    pub(crate) fail_when_disconnected: bool,
+    pub(crate) max_incoming_message_size: Option<usize>,
+    pub(crate) request_inbox_capacity: usize,
+    pub(crate) pending_limits: PendingLimits,
+    pub(crate) default_reply_subject: Option<String>,
+    pub(crate) reload_credentials_on_reconnect: bool,
+    pub(crate) no_responders: bool,
+    pub(crate) tcp_nodelay: bool,
+    pub(crate) tcp_keepalive: Option<Duration>,
+    pub(crate) keepalive_pings: bool,
+    pub(crate) auto_close_idle: Option<Duration>,
+    pub(crate) shutdown_flush_timeout: Duration,
+    pub(crate) connect_timeout: Option<Duration>,
+    pub(crate) read_buffer_capacity: usize,
+    pub(crate) write_buffer_capacity: usize,
+    pub(crate) connection_metadata: HashMap<String, String>,
     pub(crate) tls_required: bool,
     pub(crate) certificates: Vec<PathBuf>,
     pub(crate) client_cert: Option<PathBuf>,
     pub(crate) client_key: Option<PathBuf>,
+    pub(crate) client_cert_pem: Option<(Vec<u8>, SecureVec)>,
     pub(crate) tls_client_config: crate::rustls::ClientConfig,
 
     pub(crate) error_callback: ErrorCallback,
     pub(crate) disconnect_callback: Callback,
+    pub(crate) connect_callback: Callback,
     pub(crate) reconnect_callback: Callback,
+    pub(crate) reconnect_attempt_callback: Callback<ReconnectAttemptFn>,
     pub(crate) reconnect_delay_callback: ReconnectDelayCallback,
     pub(crate) close_callback: Callback,
+    pub(crate) replayed_callback: Callback<CountFn>,
+    pub(crate) dropped_callback: Callback<CountFn>,
+    pub(crate) subject_transform: SubjectTransform,
+    pub(crate) outgoing_op_callback: Callback<OutgoingOpFn>,
+    pub(crate) dead_letter_callback: Callback<MessageFn>,
+    pub(crate) unknown_op_callback: Callback<LineFn>,
 }
 
 impl fmt::Debug for Options {
@@ -52,17 +105,56 @@ impl fmt::Debug for Options {
             .entry(&"name", &self.name)
             .entry(&"no_echo", &self.no_echo)
             .entry(&"reconnect_buffer_size", &self.reconnect_buffer_size)
+            .entry(&"reconnect_buffer_persist", &self.reconnect_buffer_persist)
+            .entry(&"reconnect_buffer_policy", &self.reconnect_buffer_policy)
+            .entry(&"fail_when_disconnected", &self.fail_when_disconnected)
+            .entry(
+                &"max_incoming_message_size",
+                &self.max_incoming_message_size,
+            )
+            .entry(&"request_inbox_capacity", &self.request_inbox_capacity)
+            .entry(&"pending_limits", &self.pending_limits)
+            .entry(&"default_reply_subject", &self.default_reply_subject)
+            .entry(
+                &"reload_credentials_on_reconnect",
+                &self.reload_credentials_on_reconnect,
+            )
+            .entry(&"no_responders", &self.no_responders)
             .entry(&"max_reconnects", &self.max_reconnects)
+            .entry(&"tcp_nodelay", &self.tcp_nodelay)
+            .entry(&"tcp_keepalive", &self.tcp_keepalive)
+            .entry(&"keepalive_pings", &self.keepalive_pings)
+            .entry(&"auto_close_idle", &self.auto_close_idle)
+            .entry(&"shutdown_flush_timeout", &self.shutdown_flush_timeout)
+            .entry(&"connect_timeout", &self.connect_timeout)
+            .entry(&"read_buffer_capacity", &self.read_buffer_capacity)
+            .entry(&"write_buffer_capacity", &self.write_buffer_capacity)
+            .entry(&"connection_metadata", &self.connection_metadata)
             .entry(&"tls_required", &self.tls_required)
             .entry(&"certificates", &self.certificates)
             .entry(&"client_cert", &self.client_cert)
             .entry(&"client_key", &self.client_key)
+            .entry(
+                &"client_cert_pem",
+                &self.client_cert_pem.as_ref().map(|_| "XXXXXXXX"),
+            )
             .entry(&"tls_client_config", &"XXXXXXXX")
             .entry(&"error_callback", &self.error_callback)
             .entry(&"disconnect_callback", &self.disconnect_callback)
+            .entry(&"connect_callback", &self.connect_callback)
             .entry(&"reconnect_callback", &self.reconnect_callback)
+            .entry(
+                &"reconnect_attempt_callback",
+                &self.reconnect_attempt_callback,
+            )
             .entry(&"reconnect_delay_callback", &"set")
             .entry(&"close_callback", &self.close_callback)
+            .entry(&"replayed_callback", &self.replayed_callback)
+            .entry(&"dropped_callback", &self.dropped_callback)
+            .entry(&"outgoing_op_callback", &self.outgoing_op_callback)
+            .entry(&"dead_letter_callback", &self.dead_letter_callback)
+            .entry(&"unknown_op_callback", &self.unknown_op_callback)
+            .entry(&"subject_transform", &self.subject_transform)
             .finish()
     }
 }
@@ -74,16 +166,46 @@ impl Default for Options {
             name: None,
             no_echo: false,
             reconnect_buffer_size: 8 * 1024 * 1024,
+            reconnect_buffer_persist: None,
+            reconnect_buffer_policy: BufferPolicy::RejectNew,
+            fail_when_disconnected: false,
+            max_incoming_message_size: None,
+            request_inbox_capacity: 64,
+            pending_limits: PendingLimits {
+                bytes: 64 * 1024 * 1024,
+                messages: 512 * 1024,
+            },
+            default_reply_subject: None,
+            reload_credentials_on_reconnect: true,
+            no_responders: true,
             max_reconnects: Some(60),
+            tcp_nodelay: true,
+            tcp_keepalive: None,
+            keepalive_pings: true,
+            auto_close_idle: None,
+            shutdown_flush_timeout: Duration::from_secs(5),
+            connect_timeout: None,
+            read_buffer_capacity: 32 * 1024,
+            write_buffer_capacity: 32 * 1024,
+            connection_metadata: HashMap::new(),
             tls_required: false,
             certificates: Vec::new(),
             client_cert: None,
             client_key: None,
+            client_cert_pem: None,
             error_callback: ErrorCallback(None),
             disconnect_callback: Callback(None),
+            connect_callback: Callback(None),
             reconnect_callback: Callback(None),
+            reconnect_attempt_callback: Callback(None),
             reconnect_delay_callback: ReconnectDelayCallback(Box::new(backoff)),
             close_callback: Callback(None),
+            replayed_callback: Callback(None),
+            dropped_callback: Callback(None),
+            subject_transform: SubjectTransform(None),
+            outgoing_op_callback: Callback(None),
+            dead_letter_callback: Callback(None),
+            unknown_op_callback: Callback(None),
             tls_client_config: crate::rustls::ClientConfig::default(),
         }
     }
@@ -183,22 +305,7 @@ impl Options {
     /// ```
     pub fn with_credentials(path: impl AsRef<Path>) -> Options {
         Options {
-            auth: AuthStyle::Credentials {
-                jwt_cb: {
-                    let path = path.as_ref().to_owned();
-                    Arc::new(move || {
-                        let (jwt, _kp) = auth_utils::load_creds(&path)?;
-                        Ok(jwt)
-                    })
-                },
-                sig_cb: {
-                    let path = path.as_ref().to_owned();
-                    Arc::new(move |nonce| {
-                        let (_jwt, kp) = auth_utils::load_creds(&path)?;
-                        auth_utils::sign_nonce(nonce, &kp)
-                    })
-                },
-            },
+            auth: AuthStyle::CredentialsFile(path.as_ref().to_owned()),
             ..Default::default()
         }
     }
@@ -305,6 +412,39 @@ impl Options {
         }
     }
 
+    /// Authenticate with a user JWT and a function that signs the server
+    /// nonce, for when the JWT is fetched at runtime and the nkey seed is
+    /// kept outside of this process, e.g. in an HSM.
+    ///
+    /// The signature function is called with the nonce during every CONNECT
+    /// handshake and must return the raw signature, which is encoded before
+    /// being sent to the server. Replaces any previously configured
+    /// authentication.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # fn main() -> std::io::Result<()> {
+    /// # let seed = "SUANQDPB2RUOE4ETUA26CNX7FUKE5ZZKFCQIIW63OX225F2CO7UEXTM7ZY";
+    /// # let kp = nkeys::KeyPair::from_seed(seed).unwrap();
+    /// # let jwt = String::from("eyJ0eXAiOiJqd3QiLCJhbGciOiJlZDI1NTE5...");
+    /// let nc = nats::Options::new()
+    ///     .jwt(jwt, move |nonce| kp.sign(nonce).unwrap())
+    ///     .connect("connect.ngs.global")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn jwt<S>(mut self, jwt: String, sig_cb: S) -> Options
+    where
+        S: Fn(&[u8]) -> Vec<u8> + Send + Sync + 'static,
+    {
+        let jwt = SecureString::from(jwt);
+        self.auth = AuthStyle::Credentials {
+            jwt_cb: Arc::new(move || Ok(jwt.clone())),
+            sig_cb: Arc::new(move |nonce| Ok(base64_url::encode(&sig_cb(nonce)).into())),
+        };
+        self
+    }
+
     /// Set client certificate and private key files.
     ///
     /// # Example
@@ -322,6 +462,28 @@ impl Options {
         self
     }
 
+    /// Set the client certificate and private key from memory, for setups
+    /// where they are never written to disk.
+    ///
+    /// The certificate is a PEM certificate chain and the key a PEM-encoded
+    /// PKCS#8 private key. Takes precedence over `client_cert`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # fn main() -> std::io::Result<()> {
+    /// # let cert = std::fs::read("client-cert.pem")?;
+    /// # let key = std::fs::read("client-key.pem")?;
+    /// let nc = nats::Options::new()
+    ///     .client_cert_pem(cert, key)
+    ///     .connect("nats://localhost:4443")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn client_cert_pem(mut self, cert: Vec<u8>, key: Vec<u8>) -> Options {
+        self.client_cert_pem = Some((cert, SecureVec::from(key)));
+        self
+    }
+
     /// Set the default TLS config that will be used
     /// for connections. Note that this is less secure
     /// than specifying TLS certificate file paths
@@ -372,6 +534,31 @@ impl Options {
         self
     }
 
+    /// Attach key-value metadata to the connection, such as the region,
+    /// version or pod name of the application. The metadata is sent in the
+    /// CONNECT message and shown by servers that support it in their
+    /// connection monitoring, which helps attributing connections to
+    /// deployments.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # use std::collections::HashMap;
+    /// let mut metadata = HashMap::new();
+    /// metadata.insert("region".to_string(), "eu-west-1".to_string());
+    /// metadata.insert("version".to_string(), "1.4.2".to_string());
+    ///
+    /// let nc = nats::Options::new()
+    ///     .connection_metadata(metadata)
+    ///     .connect("demo.nats.io")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn connection_metadata(mut self, metadata: HashMap<String, String>) -> Options {
+        self.connection_metadata = metadata;
+        self
+    }
+
     /// Select option to not deliver messages that we have published.
     ///
     /// # Example
@@ -413,6 +600,10 @@ impl Options {
     /// when accepting outgoing traffic in disconnected
     /// mode.
     ///
+    /// A size of `0` disables buffering: publishing while
+    /// disconnected then fails with `ErrorKind::NotConnected`,
+    /// the same as with `fail_when_disconnected`.
+    ///
     /// The default value is 8mb.
     ///
     /// # Example
@@ -429,6 +620,397 @@ impl Options {
         self
     }
 
+    /// Mirror messages buffered while disconnected to an append-only file at
+    /// the given path, so that they survive a crash of the process or the
+    /// machine. Each message is synced to disk before the publish that
+    /// buffered it returns, which makes publishing while disconnected
+    /// slower.
+    ///
+    /// When connecting, any messages left in the file by a previous process
+    /// are loaded into the reconnect buffer and published once the
+    /// connection is established. The file is truncated after buffered
+    /// messages have been written to a new connection.
+    ///
+    /// This provides at-least-once delivery of buffered messages across
+    /// restarts: messages that were written to a connection right before a
+    /// crash may be published again.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let path = std::env::temp_dir().join("nats-reconnect-buffer");
+    /// let nc = nats::Options::new()
+    ///     .reconnect_buffer_persist(&path)
+    ///     .connect("demo.nats.io")?;
+    /// # std::fs::remove_file(path)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn reconnect_buffer_persist<P: AsRef<Path>>(mut self, path: P) -> Options {
+        self.reconnect_buffer_persist = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// Set what happens to messages published while disconnected once the
+    /// reconnect buffer is full.
+    ///
+    /// With `BufferPolicy::RejectNew`, which is the default, publishing fails
+    /// until the connection is reestablished. With `BufferPolicy::DropOldest`,
+    /// the oldest buffered messages are discarded to make room, which suits
+    /// data such as telemetry where only recent messages matter. Messages
+    /// larger than the whole buffer are always rejected.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// let nc = nats::Options::new()
+    ///     .reconnect_buffer_policy(nats::BufferPolicy::DropOldest)
+    ///     .connect("demo.nats.io")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn reconnect_buffer_policy(mut self, policy: BufferPolicy) -> Options {
+        self.reconnect_buffer_policy = policy;
+        self
+    }
+
+    /// Set the maximum size in bytes of incoming messages, including their
+    /// headers.
+    ///
+    /// Larger messages are skipped without being buffered in memory and the
+    /// `error_callback` is called with an error of kind
+    /// `ErrorKind::InvalidData`. By default, the size of incoming messages is
+    /// only limited by the server's `max_payload`.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// let nc = nats::Options::new()
+    ///     .max_incoming_message_size(64 * 1024)
+    ///     .connect("demo.nats.io")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn max_incoming_message_size(mut self, max_incoming_message_size: usize) -> Options {
+        self.max_incoming_message_size = Some(max_incoming_message_size);
+        self
+    }
+
+    /// Set how many replies can be queued on the inbox subscription of a
+    /// request before further replies are dropped.
+    ///
+    /// This applies to `request`, `request_async`, `new_respond_inbox` and
+    /// `JetStream` double acks, which usually expect a single reply. It does
+    /// not apply to `request_multi`.
+    ///
+    /// The default value is 64.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// let nc = nats::Options::new()
+    ///     .request_inbox_capacity(8)
+    ///     .connect("demo.nats.io")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn request_inbox_capacity(mut self, request_inbox_capacity: usize) -> Options {
+        self.request_inbox_capacity = request_inbox_capacity;
+        self
+    }
+
+    /// Set the estimated limits on pending bytes and messages that the
+    /// server enforces before disconnecting this client as a slow consumer.
+    ///
+    /// They are used by `Connection::pending_limits` and
+    /// `Connection::slow_consumer_risk` when the server does not advertise
+    /// its limits. Zero means unlimited.
+    ///
+    /// The defaults are 64MB, matching the server's default `max_pending`,
+    /// and 524288 messages.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// let nc = nats::Options::new()
+    ///     .pending_limits(32 * 1024 * 1024, 100_000)
+    ///     .connect("demo.nats.io")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn pending_limits(mut self, bytes: usize, messages: usize) -> Options {
+        self.pending_limits = PendingLimits { bytes, messages };
+        self
+    }
+
+    /// Set a subject that `Message::respond` publishes to when the message
+    /// being responded to has no reply subject.
+    ///
+    /// If `None`, responding to such a message fails with
+    /// `ErrorKind::InvalidInput`, which is also the default.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// let nc = nats::Options::new()
+    ///     .default_reply_subject("gateway.replies".to_string())
+    ///     .connect("demo.nats.io")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn default_reply_subject<T: Into<Option<String>>>(mut self, subject: T) -> Options {
+        self.default_reply_subject = subject.into();
+        self
+    }
+
+    /// Set whether the `.creds` file given to `Options::with_credentials` is
+    /// read again on every connection attempt.
+    ///
+    /// When enabled, which is the default, a rotated credential is picked up
+    /// on the next reconnect without restarting the process. When disabled,
+    /// the file is read once and the credentials are kept in memory for the
+    /// lifetime of the client.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # fn main() -> std::io::Result<()> {
+    /// let nc = nats::Options::with_credentials("path/to/my.creds")
+    ///     .reload_credentials_on_reconnect(false)
+    ///     .connect("connect.ngs.global")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn reload_credentials_on_reconnect(mut self, reload: bool) -> Options {
+        self.reload_credentials_on_reconnect = reload;
+        self
+    }
+
+    /// Set whether to ask the server for no responders replies, which make
+    /// requests to subjects without subscribers fail with
+    /// `ErrorKind::NotFound` instead of waiting for the timeout.
+    ///
+    /// No responders replies are requested only from servers that advertise
+    /// header support. Enabled by default; disable it for servers that send
+    /// them incorrectly.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// let nc = nats::Options::new()
+    ///     .no_responders(false)
+    ///     .connect("demo.nats.io")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn no_responders(mut self, no_responders: bool) -> Options {
+        self.no_responders = no_responders;
+        self
+    }
+
+    /// Return an error with `ErrorKind::NotConnected` from publishing methods
+    /// while the client is disconnected, instead of writing the messages into
+    /// the reconnect buffer.
+    ///
+    /// By default, messages published while disconnected are buffered and
+    /// sent to the server once the connection is reestablished.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// let nc = nats::Options::new()
+    ///     .fail_when_disconnected()
+    ///     .connect("demo.nats.io")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn fail_when_disconnected(mut self) -> Options {
+        self.fail_when_disconnected = true;
+        self
+    }
+
+    /// Set whether `TCP_NODELAY` is enabled on the socket,
+    /// disabling Nagle's algorithm.
+    ///
+    /// The default value is `true`.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// let nc = nats::Options::new()
+    ///     .tcp_nodelay(false)
+    ///     .connect("demo.nats.io")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn tcp_nodelay(mut self, tcp_nodelay: bool) -> Options {
+        self.tcp_nodelay = tcp_nodelay;
+        self
+    }
+
+    /// Enable TCP keepalive on the socket, probing the connection after
+    /// it has been idle for the given duration. This complements the
+    /// client side `PING`s and helps with NATs and firewalls that drop
+    /// idle connections.
+    ///
+    /// The idle duration is only applied on platforms that support
+    /// configuring it, others fall back to the system default.
+    ///
+    /// If `None`, the system default for the socket is left untouched,
+    /// which is also the default value.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # use std::time::Duration;
+    /// let nc = nats::Options::new()
+    ///     .tcp_keepalive(Duration::from_secs(60))
+    ///     .connect("demo.nats.io")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn tcp_keepalive<T: Into<Option<Duration>>>(mut self, tcp_keepalive: T) -> Options {
+        self.tcp_keepalive = tcp_keepalive.into();
+        self
+    }
+
+    /// Stop the client from sending `PING`s while the connection is idle and
+    /// from disconnecting when they go unanswered. The background flusher
+    /// then only writes out buffered messages.
+    ///
+    /// With keepalive disabled, a server that silently went away is only
+    /// noticed once a write fails, so the application becomes responsible
+    /// for probing liveness itself, for example by periodically calling
+    /// `Connection::ping` or `Connection::flush`.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # use std::time::Duration;
+    /// let nc = nats::Options::new()
+    ///     .disable_keepalive()
+    ///     .connect("demo.nats.io")?;
+    /// nc.ping(Duration::from_secs(5))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn disable_keepalive(mut self) -> Options {
+        self.keepalive_pings = false;
+        self
+    }
+
+    /// Close the client once it has gone unused for the given duration,
+    /// which stops its background threads.
+    ///
+    /// The client counts as unused while it has no subscriptions and
+    /// nothing is published, which includes requests as they publish and
+    /// subscribe. Once closed, operations fail with `NotConnected` and the
+    /// close callback is executed.
+    ///
+    /// By default, idle clients are not closed.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # use std::time::Duration;
+    /// let nc = nats::Options::new()
+    ///     .auto_close_idle(Duration::from_secs(5 * 60))
+    ///     .connect("demo.nats.io")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn auto_close_idle(mut self, idle: Duration) -> Options {
+        self.auto_close_idle = Some(idle);
+        self
+    }
+
+    /// Set how long the final flush of buffered messages may block when the
+    /// client shuts down, so that closing the connection terminates promptly
+    /// even if the server stopped reading from the socket.
+    ///
+    /// A zero duration skips the final flush. The default value is 5
+    /// seconds.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # use std::time::Duration;
+    /// let nc = nats::Options::new()
+    ///     .shutdown_flush_timeout(Duration::from_secs(1))
+    ///     .connect("demo.nats.io")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn shutdown_flush_timeout(mut self, shutdown_flush_timeout: Duration) -> Options {
+        self.shutdown_flush_timeout = shutdown_flush_timeout;
+        self
+    }
+
+    /// Set how long establishing the initial connection may take, including
+    /// the TCP connect and the handshake with the server, before `connect`
+    /// fails with `ErrorKind::TimedOut`. This keeps `connect` from blocking
+    /// on a server that accepts connections but never responds.
+    ///
+    /// The timeout is also applied to the TCP connect of every reconnect
+    /// attempt, and to reading the server's first responses on connections
+    /// without TLS.
+    ///
+    /// If `None`, which is the default, connecting waits indefinitely.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # use std::time::Duration;
+    /// let nc = nats::Options::new()
+    ///     .connect_timeout(Duration::from_secs(5))
+    ///     .connect("demo.nats.io")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn connect_timeout<T: Into<Option<Duration>>>(mut self, connect_timeout: T) -> Options {
+        self.connect_timeout = connect_timeout.into();
+        self
+    }
+
+    /// Set the capacity in bytes of the buffer used for reading from the
+    /// server. A larger buffer reduces the number of system calls when
+    /// receiving large messages, a smaller one saves memory.
+    ///
+    /// Defaults to 32KB. A capacity of zero is treated as one byte.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// let nc = nats::Options::new()
+    ///     .read_buffer_capacity(256 * 1024)
+    ///     .connect("demo.nats.io")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn read_buffer_capacity(mut self, capacity: usize) -> Options {
+        self.read_buffer_capacity = capacity.max(1);
+        self
+    }
+
+    /// Set the capacity in bytes of the buffer used for writing to the
+    /// server. Messages that do not fit into the buffer are written out
+    /// directly.
+    ///
+    /// Defaults to 32KB.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// let nc = nats::Options::new()
+    ///     .write_buffer_capacity(4 * 1024)
+    ///     .connect("demo.nats.io")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn write_buffer_capacity(mut self, capacity: usize) -> Options {
+        self.write_buffer_capacity = capacity;
+        self
+    }
+
     /// Establish a `Connection` with a NATS server.
     ///
     /// Multiple servers may be specified by separating
@@ -462,6 +1044,30 @@ impl Options {
         Connection::connect_with_options(nats_url, self)
     }
 
+    /// Establish a `Connection` in the background, returning immediately
+    /// instead of waiting for the handshake with the server to complete.
+    ///
+    /// Messages published before the connection is established are kept in
+    /// the reconnect buffer and sent once connected, unless publishing while
+    /// disconnected fails with `NotConnected` as configured. Only invalid
+    /// URLs and options are reported as errors here. If the client gives up connecting, the error is passed to the
+    /// error callback and the connection is closed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// let nc = nats::Options::new()
+    ///     .error_callback(|err| println!("failed to connect: {}", err))
+    ///     .connect_lazy("demo.nats.io")?;
+    /// nc.publish("foo", "sent once connected")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn connect_lazy(self, nats_url: &str) -> io::Result<Connection> {
+        Connection::connect_lazy_with_options(nats_url, self)
+    }
+
     /// Set a callback to be executed when an async error from
     /// a server has been received.
     ///
@@ -479,7 +1085,7 @@ impl Options {
     where
         F: Fn(Error) + Send + Sync + 'static,
     {
-        self.error_callback = ErrorCallback(Some(Box::new(cb)));
+        self.error_callback = ErrorCallback::new(cb);
         self
     }
 
@@ -504,6 +1110,34 @@ impl Options {
         self
     }
 
+    /// Set a callback to be executed once, when the first connection to a
+    /// server has been established, for example to run one-time setup.
+    ///
+    /// Unlike `reconnect_callback`, it does not run when connectivity has
+    /// been reestablished.
+    ///
+    /// The callback runs on a thread of its own while the connection is
+    /// already reading from the server, so it can use the connection, for
+    /// example to flush or to send a request.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// let nc = nats::Options::new()
+    ///     .connect_callback(|| println!("connection has been established"))
+    ///     .connect("demo.nats.io")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn connect_callback<F>(mut self, cb: F) -> Self
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.connect_callback = Callback(Some(Box::new(cb)));
+        self
+    }
+
     /// Set a callback to be executed when connectivity to a
     /// server has been reestablished.
     ///
@@ -525,6 +1159,32 @@ impl Options {
         self
     }
 
+    /// Set a callback to be executed each time an attempt to connect to a
+    /// server fails, with the URL of the server and the error. The URL does
+    /// not include credentials.
+    ///
+    /// Unlike `reconnect_callback`, which only runs once a connection has
+    /// been reestablished, this shows why connecting is failing, for example
+    /// because of DNS, refused connections or TLS errors.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// let nc = nats::Options::new()
+    ///     .reconnect_attempt_callback(|url, err| println!("failed to connect to {}: {}", url, err))
+    ///     .connect("demo.nats.io")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn reconnect_attempt_callback<F>(mut self, cb: F) -> Self
+    where
+        F: Fn(&str, &Error) + Send + Sync + 'static,
+    {
+        self.reconnect_attempt_callback = Callback(Some(Box::new(cb)));
+        self
+    }
+
     /// Set a callback to be executed when the client has been
     /// closed due to exhausting reconnect retries to known servers
     /// or by completing a drain request.
@@ -548,6 +1208,51 @@ impl Options {
         self
     }
 
+    /// Set a callback to be executed with the number of messages from the
+    /// reconnect buffer that were sent once a connection has been
+    /// established.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// let nc = nats::Options::new()
+    ///     .replayed_callback(|count| println!("sent {} buffered messages", count))
+    ///     .connect("demo.nats.io")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn replayed_callback<F>(mut self, cb: F) -> Self
+    where
+        F: Fn(usize) + Send + Sync + 'static,
+    {
+        self.replayed_callback = Callback(Some(Box::new(cb)));
+        self
+    }
+
+    /// Set a callback to be executed with the number of messages that were
+    /// evicted from the reconnect buffer by `BufferPolicy::DropOldest`
+    /// while disconnected, once a connection has been established.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// let nc = nats::Options::new()
+    ///     .reconnect_buffer_policy(nats::BufferPolicy::DropOldest)
+    ///     .dropped_callback(|count| println!("lost {} buffered messages", count))
+    ///     .connect("demo.nats.io")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn dropped_callback<F>(mut self, cb: F) -> Self
+    where
+        F: Fn(usize) + Send + Sync + 'static,
+    {
+        self.dropped_callback = Callback(Some(Box::new(cb)));
+        self
+    }
+
     /// Set a callback to be executed for calculating the backoff duration
     /// to wait before a server reconnection attempt.
     ///
@@ -577,6 +1282,120 @@ impl Options {
         self
     }
 
+    /// Set a function that rewrites the subject of every published message,
+    /// including responses and acknowledgements. This can be used to
+    /// transparently redirect traffic without changing call sites, for
+    /// example during a migration between subject hierarchies.
+    ///
+    /// The transform runs before the message is validated and encoded, so
+    /// any checks are applied to the rewritten subject.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # use std::borrow::Cow;
+    /// let nc = nats::Options::new()
+    ///     .subject_transform(|subject| match subject.strip_prefix("orders.v1.") {
+    ///         Some(rest) => Cow::Owned(format!("orders.v2.{}", rest)),
+    ///         None => Cow::Borrowed(subject),
+    ///     })
+    ///     .connect("demo.nats.io")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn subject_transform<F>(mut self, transform: F) -> Self
+    where
+        F: Fn(&str) -> Cow<'_, str> + Send + Sync + 'static,
+    {
+        self.subject_transform = SubjectTransform(Some(Box::new(transform)));
+        self
+    }
+
+    /// Set a callback to be executed for every protocol operation the client
+    /// writes to the server, such as `PUB`, `SUB`, `UNSUB`, `PING` and
+    /// `PONG`, including the `CONNECT` and `PING` of each handshake. This is
+    /// useful for protocol-level debugging and metrics.
+    ///
+    /// The callback runs while the connection is locked for writing, so it
+    /// should be cheap and must not use the connection.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # use std::sync::{Arc, atomic::{AtomicUsize, Ordering}};
+    /// let pubs = Arc::new(AtomicUsize::new(0));
+    /// let pubs_2 = pubs.clone();
+    /// let nc = nats::Options::new()
+    ///     .outgoing_op_callback(move |op| {
+    ///         if let nats::ClientOp::Pub { .. } = op {
+    ///             pubs_2.fetch_add(1, Ordering::Relaxed);
+    ///         }
+    ///     })
+    ///     .connect("demo.nats.io")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn outgoing_op_callback<F>(mut self, cb: F) -> Self
+    where
+        F: Fn(&ClientOp<'_>) + Send + Sync + 'static,
+    {
+        self.outgoing_op_callback = Callback(Some(Box::new(cb)));
+        self
+    }
+
+    /// Set a callback to be executed with each message that is dropped
+    /// because the channel of its subscription is full, such as replies
+    /// beyond `request_inbox_capacity`, instead of discarding it silently.
+    ///
+    /// The callback runs on the thread reading from the server, so it
+    /// should be cheap and must not block.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// let nc = nats::Options::new()
+    ///     .dead_letter_callback(|msg| println!("dropped message on {}", msg.subject))
+    ///     .connect("demo.nats.io")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn dead_letter_callback<F>(mut self, cb: F) -> Self
+    where
+        F: Fn(&crate::client::Message) + Send + Sync + 'static,
+    {
+        self.dead_letter_callback = Callback(Some(Box::new(cb)));
+        self
+    }
+
+    /// Set a callback to be executed with each line received from the
+    /// server that is not a known protocol operation.
+    ///
+    /// Such lines are otherwise only logged. They can point at a protocol
+    /// version mismatch or a proxy injecting data into the connection.
+    /// The callback runs on the thread reading from the server, so it
+    /// should be cheap and must not block.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// let nc = nats::Options::new()
+    ///     .unknown_op_callback(|line| eprintln!("unknown op from server: {}", line))
+    ///     .connect("demo.nats.io")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn unknown_op_callback<F>(mut self, cb: F) -> Self
+    where
+        F: Fn(&str) + Send + Sync + 'static,
+    {
+        self.unknown_op_callback = Callback(Some(Box::new(cb)));
+        self
+    }
+
     /// Setting this requires that TLS be set for all server connections.
     ///
     /// If you only want to use TLS for some server connections, you may
@@ -629,7 +1448,10 @@ pub(crate) enum AuthStyle {
     /// Authenticate using a username and password.
     UserPass(String, String),
 
-    /// Authenticate using a `.creds` file.
+    /// Authenticate using a `.creds` file that is loaded when connecting.
+    CredentialsFile(PathBuf),
+
+    /// Authenticate using credentials loaded by callbacks.
     Credentials {
         /// Securely loads the user JWT.
         jwt_cb: Arc<dyn Fn() -> io::Result<SecureString> + Send + Sync>,
@@ -654,6 +1476,9 @@ impl fmt::Debug for AuthStyle {
             AuthStyle::UserPass(user, pass) => {
                 f.debug_tuple("Token").field(user).field(pass).finish()
             }
+            AuthStyle::CredentialsFile(path) => {
+                f.debug_tuple("CredentialsFile").field(path).finish()
+            }
             AuthStyle::Credentials { .. } => f.debug_struct("Credentials").finish(),
             AuthStyle::NKey { .. } => f.debug_struct("NKey").finish(),
         }
@@ -666,8 +1491,33 @@ impl Default for AuthStyle {
     }
 }
 
-#[derive(Default)]
-pub(crate) struct Callback(Option<Box<dyn Fn() + Send + Sync + 'static>>);
+type CallbackFn = dyn Fn() + Send + Sync + 'static;
+type CountFn = dyn Fn(usize) + Send + Sync + 'static;
+type ReconnectAttemptFn = dyn Fn(&str, &Error) + Send + Sync + 'static;
+type OutgoingOpFn = dyn Fn(&ClientOp<'_>) + Send + Sync + 'static;
+type MessageFn = dyn Fn(&crate::client::Message) + Send + Sync + 'static;
+type LineFn = dyn Fn(&str) + Send + Sync + 'static;
+
+/// A user callback that may be unset, in which case calling it does nothing.
+pub(crate) struct Callback<F: ?Sized = CallbackFn>(Option<Box<F>>);
+
+impl<F: ?Sized> Default for Callback<F> {
+    fn default() -> Callback<F> {
+        Callback(None)
+    }
+}
+
+impl<F: ?Sized> fmt::Debug for Callback<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        f.debug_map()
+            .entry(
+                &"callback",
+                if self.0.is_some() { &"set" } else { &"unset" },
+            )
+            .finish()
+    }
+}
+
 impl Callback {
     pub fn call(&self) {
         if let Some(callback) = self.0.as_ref() {
@@ -676,11 +1526,63 @@ impl Callback {
     }
 }
 
-impl fmt::Debug for Callback {
+impl Callback<CountFn> {
+    pub fn call(&self, count: usize) {
+        if let Some(callback) = self.0.as_ref() {
+            callback(count);
+        }
+    }
+}
+
+impl Callback<ReconnectAttemptFn> {
+    pub fn call(&self, url: &str, err: &Error) {
+        if let Some(callback) = self.0.as_ref() {
+            callback(url, err);
+        }
+    }
+}
+
+impl Callback<OutgoingOpFn> {
+    pub fn call(&self, op: &ClientOp<'_>) {
+        if let Some(callback) = self.0.as_ref() {
+            callback(op);
+        }
+    }
+}
+
+impl Callback<MessageFn> {
+    pub fn call(&self, msg: &crate::client::Message) {
+        if let Some(callback) = self.0.as_ref() {
+            callback(msg);
+        }
+    }
+}
+
+impl Callback<LineFn> {
+    pub fn call(&self, line: &str) {
+        if let Some(callback) = self.0.as_ref() {
+            callback(line);
+        }
+    }
+}
+
+type SubjectTransformFn = dyn Fn(&str) -> Cow<'_, str> + Send + Sync + 'static;
+
+pub(crate) struct SubjectTransform(Option<Box<SubjectTransformFn>>);
+impl SubjectTransform {
+    pub fn call<'a>(&self, subject: &'a str) -> Cow<'a, str> {
+        match self.0.as_ref() {
+            Some(transform) => transform(subject),
+            None => Cow::Borrowed(subject),
+        }
+    }
+}
+
+impl fmt::Debug for SubjectTransform {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         f.debug_map()
             .entry(
-                &"callback",
+                &"subject_transform",
                 if self.0.is_some() { &"set" } else { &"unset" },
             )
             .finish()
@@ -696,6 +1598,13 @@ impl ReconnectDelayCallback {
 
 pub(crate) struct ErrorCallback(Option<Box<dyn Fn(Error) + Send + Sync + 'static>>);
 impl ErrorCallback {
+    pub fn new<F>(cb: F) -> ErrorCallback
+    where
+        F: Fn(Error) + Send + Sync + 'static,
+    {
+        ErrorCallback(Some(Box::new(cb)))
+    }
+
     pub fn call(&self, client: &Client, err: Error) {
         if let Some(callback) = self.0.as_ref() {
             callback(err);
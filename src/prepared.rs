@@ -0,0 +1,72 @@
+// Copyright 2020-2021 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::{self, Error, ErrorKind};
+
+use crate::Connection;
+
+/// A publisher for a fixed subject whose `PUB` prefix is validated and
+/// encoded once, so that only the payload varies between publishes.
+///
+/// Unlike `Connection::publish`, the configured `subject_transform` is not
+/// applied to prepared publishes.
+///
+/// # Example
+/// ```
+/// # fn main() -> std::io::Result<()> {
+/// # let nc = nats::connect("demo.nats.io")?;
+/// let prepared = nats::PreparedPublish::new("metrics.cpu")?;
+/// for value in 0..10 {
+///     prepared.publish(&nc, value.to_string())?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct PreparedPublish {
+    /// `PUB <subject> `, awaiting the payload length.
+    prefix: Vec<u8>,
+}
+
+impl PreparedPublish {
+    /// Validates the subject and encodes the `PUB` prefix for it.
+    ///
+    /// Fails with `InvalidInput` if the subject is empty, contains
+    /// whitespace or has empty tokens.
+    pub fn new(subject: &str) -> io::Result<PreparedPublish> {
+        if subject.is_empty()
+            || subject.chars().any(char::is_whitespace)
+            || subject.split('.').any(str::is_empty)
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid subject: {:?}", subject),
+            ));
+        }
+
+        let mut prefix = Vec::with_capacity(subject.len() + 5);
+        prefix.extend_from_slice(b"PUB ");
+        prefix.extend_from_slice(subject.as_bytes());
+        prefix.push(b' ');
+        Ok(PreparedPublish { prefix })
+    }
+
+    /// Publishes the payload on the prepared subject.
+    pub fn publish(&self, nc: &Connection, payload: impl AsRef<[u8]>) -> io::Result<()> {
+        let payload = payload.as_ref();
+        let mut len = itoa::Buffer::new();
+        let len = len.format(payload.len());
+        nc.0.client
+            .write_raw(&[&self.prefix, len.as_bytes(), b"\r\n", payload, b"\r\n"])
+    }
+}
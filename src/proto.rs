@@ -11,13 +11,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::convert::TryFrom;
 use std::io::prelude::*;
 use std::io::{self, Error, ErrorKind};
 use std::str::{self, FromStr};
 
 use crate::connect::ConnectInfo;
-use crate::{inject_io_failure, Headers, ServerInfo};
+use crate::{headers, inject_io_failure, Headers, ServerInfo};
 
 /// A protocol operation sent by the server.
 #[derive(Debug)]
@@ -43,6 +42,25 @@ pub(crate) enum ServerOp {
         payload: Vec<u8>,
     },
 
+    /// A `MSG` or `HMSG` whose size exceeded the maximum incoming message
+    /// size. Its headers and payload were skipped without being read into
+    /// memory.
+    Oversized {
+        subject: String,
+        sid: u64,
+        num_bytes: usize,
+    },
+
+    /// A `MSG` or `HMSG` for a streaming subscription. Its payload and the
+    /// trailing `\r\n` are left in the stream to be read by the caller.
+    Streamed {
+        subject: String,
+        headers: Option<Headers>,
+        sid: u64,
+        reply_to: Option<String>,
+        num_bytes: usize,
+    },
+
     /// `PING`
     Ping,
 
@@ -58,6 +76,10 @@ pub(crate) enum ServerOp {
 
 // adapted from `std::io::BufRead::read_until`, made
 // to use a fixed buffer instead of a growable vector.
+//
+// The line may span any number of `fill_buf` refills. Returns 0 if the
+// stream ended before the line started, and fails with `UnexpectedEof` if
+// it ended in the middle of the line.
 fn read_line<R: BufRead + ?Sized>(r: &mut R, buf: &mut [u8]) -> io::Result<usize> {
     let mut read = 0;
     loop {
@@ -66,13 +88,18 @@ fn read_line<R: BufRead + ?Sized>(r: &mut R, buf: &mut [u8]) -> io::Result<usize
             Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
             Err(e) => return Err(e),
         };
-        let (done, len) = {
-            if let Some(i) = memchr::memchr(b'\n', available) {
-                (true, i + 1)
-            } else {
-                buf[read..read + available.len()].copy_from_slice(available);
-                (false, available.len())
+        if available.is_empty() {
+            if read == 0 {
+                return Ok(0);
             }
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "connection closed in the middle of a command",
+            ));
+        }
+        let (done, len) = match memchr::memchr(b'\n', available) {
+            Some(i) => (true, i + 1),
+            None => (false, available.len()),
         };
         if len + read > buf.len() {
             return Err(Error::new(
@@ -83,16 +110,45 @@ fn read_line<R: BufRead + ?Sized>(r: &mut R, buf: &mut [u8]) -> io::Result<usize
         buf[read..read + len].copy_from_slice(&available[..len]);
         r.consume(len);
         read += len;
-        if done || len == 0 {
+        if done {
             return Ok(read);
         }
     }
 }
 
+/// Skips over a number of bytes in the stream.
+fn skip(stream: impl BufRead, num_bytes: u64) -> io::Result<()> {
+    let skipped = io::copy(&mut stream.take(num_bytes), &mut io::sink())?;
+    if skipped < num_bytes {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "connection closed while skipping an oversized message",
+        ));
+    }
+    Ok(())
+}
+
 /// Decodes a single operation from the server.
 ///
+/// Messages larger than `max_message_size` are skipped and decoded as
+/// `ServerOp::Oversized`.
+///
 /// If the connection is closed, `None` will be returned.
-pub(crate) fn decode(mut stream: impl BufRead) -> io::Result<Option<ServerOp>> {
+pub(crate) fn decode(
+    stream: impl BufRead,
+    max_message_size: Option<usize>,
+) -> io::Result<Option<ServerOp>> {
+    decode_streaming(stream, max_message_size, |_| false)
+}
+
+/// Decodes a single operation from the server like `decode`, except that
+/// messages for sids accepted by `is_streamed` are decoded as
+/// `ServerOp::Streamed` regardless of their size.
+pub(crate) fn decode_streaming(
+    mut stream: impl BufRead,
+    max_message_size: Option<usize>,
+    is_streamed: impl Fn(u64) -> bool,
+) -> io::Result<Option<ServerOp>> {
     // Inject random I/O failures when testing.
     inject_io_failure()?;
 
@@ -174,6 +230,27 @@ pub(crate) fn decode(mut stream: impl BufRead) -> io::Result<Option<ServerOp>> {
             )
         })?;
 
+        // Leave the payload in the stream if it is streamed.
+        if is_streamed(sid) {
+            return Ok(Some(ServerOp::Streamed {
+                subject,
+                headers: None,
+                sid,
+                reply_to,
+                num_bytes: num_bytes as usize,
+            }));
+        }
+
+        // Skip the payload and "\r\n" if the message is too large.
+        if matches!(max_message_size, Some(max) if num_bytes as usize > max) {
+            skip(&mut stream, u64::from(num_bytes) + 2)?;
+            return Ok(Some(ServerOp::Oversized {
+                subject,
+                sid,
+                num_bytes: num_bytes as usize,
+            }));
+        }
+
         // Read the payload.
         let mut payload = Vec::new();
         payload.resize(num_bytes as usize, 0_u8);
@@ -253,6 +330,17 @@ pub(crate) fn decode(mut stream: impl BufRead) -> io::Result<Option<ServerOp>> {
             ));
         }
 
+        // Skip the headers, payload and "\r\n" if the message is too large.
+        let streamed = is_streamed(sid);
+        if !streamed && matches!(max_message_size, Some(max) if num_bytes as usize > max) {
+            skip(&mut stream, u64::from(num_bytes) + 2)?;
+            return Ok(Some(ServerOp::Oversized {
+                subject,
+                sid,
+                num_bytes: num_bytes as usize,
+            }));
+        }
+
         let num_payload_bytes = num_bytes - num_header_bytes;
 
         // `HMSG <subject> <sid> [reply-to]
@@ -264,7 +352,27 @@ pub(crate) fn decode(mut stream: impl BufRead) -> io::Result<Option<ServerOp>> {
         header_payload.resize(num_header_bytes as usize, 0_u8);
         stream.read_exact(&mut header_payload[..])?;
 
-        let headers = Headers::try_from(&*header_payload)?;
+        // Deliver the message with the headers that could be parsed rather
+        // than dropping it, or the connection, over a malformed header.
+        let (headers, malformed) = headers::parse_lenient(&header_payload);
+        if !malformed.is_empty() {
+            log::warn!(
+                "received a message on {} with malformed headers: {}",
+                subject,
+                malformed.join("; ")
+            );
+        }
+
+        // Leave the payload in the stream if it is streamed.
+        if streamed {
+            return Ok(Some(ServerOp::Streamed {
+                subject,
+                headers: Some(headers),
+                sid,
+                reply_to,
+                num_bytes: num_payload_bytes as usize,
+            }));
+        }
 
         // Read the payload.
         let mut payload = Vec::new();
@@ -294,34 +402,50 @@ pub(crate) fn decode(mut stream: impl BufRead) -> io::Result<Option<ServerOp>> {
 
 /// A protocol operation sent by the client.
 #[derive(Clone, Copy, Debug)]
-pub(crate) enum ClientOp<'a> {
+#[non_exhaustive]
+pub enum ClientOp<'a> {
     /// `CONNECT {["option_name":option_value],...}`
     Connect(&'a ConnectInfo),
 
     /// `PUB <subject> [reply-to] <#bytes>\r\n[payload]\r\n`
     Pub {
+        /// The subject the message is published on.
         subject: &'a str,
+        /// The optional reply subject.
         reply_to: Option<&'a str>,
+        /// The message contents.
         payload: &'a [u8],
     },
 
     /// `HPUB <subject> [reply-to] <#bytes>\r\n[payload]\r\n`
     Hpub {
+        /// The subject the message is published on.
         subject: &'a str,
+        /// The optional reply subject.
         reply_to: Option<&'a str>,
+        /// The message headers.
         headers: &'a Headers,
+        /// The message contents.
         payload: &'a [u8],
     },
 
     /// `SUB <subject> [queue group] <sid>\r\n`
     Sub {
+        /// The subject to subscribe to.
         subject: &'a str,
+        /// The optional queue group.
         queue_group: Option<&'a str>,
+        /// The subscription ID.
         sid: u64,
     },
 
     /// `UNSUB <sid> [max_msgs]`
-    Unsub { sid: u64, max_msgs: Option<u64> },
+    Unsub {
+        /// The subscription ID.
+        sid: u64,
+        /// The number of messages after which the server unsubscribes.
+        max_msgs: Option<u64>,
+    },
 
     /// `PING`
     Ping,
@@ -330,8 +454,31 @@ pub(crate) enum ClientOp<'a> {
     Pong,
 }
 
-/// Encodes a single operation from the client.
-pub(crate) fn encode(mut stream: impl Write, op: ClientOp<'_>) -> io::Result<()> {
+/// Counts the bytes written to the inner writer.
+struct CountingWriter<W> {
+    inner: W,
+    count: usize,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Encodes a single operation from the client, returning the number of
+/// bytes written.
+pub(crate) fn encode(writer: impl Write, op: ClientOp<'_>) -> io::Result<usize> {
+    let mut stream = CountingWriter {
+        inner: writer,
+        count: 0,
+    };
     match &op {
         ClientOp::Connect(connect_info) => {
             let op = format!(
@@ -432,5 +579,168 @@ pub(crate) fn encode(mut stream: impl Write, op: ClientOp<'_>) -> io::Result<()>
         }
     }
 
-    Ok(())
+    Ok(stream.count)
+}
+
+#[cfg(test)]
+mod encode {
+    use super::*;
+
+    #[test]
+    fn returns_written_bytes() {
+        let headers: Headers = vec![("X-Test".to_string(), "a".to_string())]
+            .into_iter()
+            .collect();
+        let ops = [
+            ClientOp::Pub {
+                subject: "foo",
+                reply_to: Some("bar"),
+                payload: b"hello",
+            },
+            ClientOp::Hpub {
+                subject: "foo",
+                reply_to: None,
+                headers: &headers,
+                payload: b"hello",
+            },
+            ClientOp::Sub {
+                subject: "foo",
+                queue_group: None,
+                sid: 1,
+            },
+            ClientOp::Ping,
+        ];
+        for op in &ops {
+            let mut buf = Vec::new();
+            let written = encode(&mut buf, *op).unwrap();
+            assert_eq!(written, buf.len(), "{:?}", op);
+        }
+
+        let mut buf = Vec::new();
+        let op = ClientOp::Pub {
+            subject: "foo",
+            reply_to: None,
+            payload: b"hello",
+        };
+        assert_eq!(
+            encode(&mut buf, op).unwrap(),
+            "PUB foo 5\r\nhello\r\n".len()
+        );
+    }
+}
+
+#[cfg(test)]
+mod decode {
+    use super::*;
+
+    #[test]
+    fn hmsg_with_malformed_headers() {
+        let headers = "NATS/1.0\r\nX-Test: a\r\nbogus\r\n\r\n";
+        let input = format!(
+            "HMSG foo 1 {} {}\r\n{}bar\r\n",
+            headers.len(),
+            headers.len() + 3,
+            headers
+        );
+
+        match decode(input.as_bytes(), None).unwrap() {
+            Some(ServerOp::Hmsg {
+                subject,
+                headers,
+                payload,
+                ..
+            }) => {
+                assert_eq!(subject, "foo");
+                assert!(headers.contains_key("X-Test"));
+                assert_eq!(payload, b"bar");
+            }
+            op => panic!("unexpected op: {:?}", op),
+        }
+    }
+
+    /// Every kind of operation, with payloads containing `\r\n` and a
+    /// message large enough to span many buffer refills.
+    fn ops() -> Vec<u8> {
+        let headers = "NATS/1.0\r\nX-Test: a\r\n\r\n";
+        let large: String = (0..40_000)
+            .map(|i| (b'a' + (i % 26) as u8) as char)
+            .collect();
+        [
+            "INFO {\"server_id\":\"test\",\"host\":\"127.0.0.1\",\"port\":4222,\
+             \"version\":\"2.2.0\",\"go\":\"go1.16\",\"proto\":1,\"max_payload\":1048576}\r\n"
+                .to_string(),
+            "MSG foo 1 0\r\n\r\n".to_string(),
+            "MSG foo.bar 2 reply 7\r\nab\r\ncde\r\n".to_string(),
+            format!("MSG big 3 {}\r\n{}\r\n", large.len(), large),
+            format!(
+                "HMSG foo 4 reply {} {}\r\n{}a\r\nb\r\n",
+                headers.len(),
+                headers.len() + 4,
+                headers
+            ),
+            "PING\r\nPONG\r\n".to_string(),
+            "-ERR 'Authorization Violation'\r\n".to_string(),
+        ]
+        .concat()
+        .into_bytes()
+    }
+
+    /// Decodes operations until the stream ends, formatted for comparison.
+    fn decode_all(mut stream: impl BufRead) -> Vec<String> {
+        let mut decoded = Vec::new();
+        while let Some(op) = decode(&mut stream, None).unwrap() {
+            decoded.push(format!("{:?}", op));
+        }
+        decoded
+    }
+
+    #[test]
+    fn split_reads() {
+        let input = ops();
+        let expected = decode_all(&input[..]);
+        assert_eq!(expected.len(), 8);
+
+        // Every refill boundary lands at a different offset of the control
+        // lines and payloads, including one byte at a time.
+        for capacity in (1..=17).chain(vec![4096, 32 * 1024]) {
+            let stream = io::BufReader::with_capacity(capacity, &input[..]);
+            assert_eq!(decode_all(stream), expected, "capacity {}", capacity);
+        }
+    }
+
+    #[test]
+    fn split_command_exceeding_buffer() {
+        let input = format!("MSG {} 1 0\r\n\r\n", "a".repeat(5000));
+        for capacity in &[1, 1000, 32 * 1024] {
+            let stream = io::BufReader::with_capacity(*capacity, input.as_bytes());
+            let err = decode(stream, None).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn truncated_at_any_byte() {
+        let input = ops();
+        let first = decode_all(&input[..]).remove(0);
+        let info_len = memchr::memchr(b'\n', &input).unwrap() + 1;
+
+        // Cutting off the stream inside an operation is an error, whereas
+        // cutting it off between operations closes it cleanly.
+        for len in 1..info_len {
+            let stream = io::BufReader::with_capacity(1, &input[..len]);
+            let err = decode(stream, None).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::UnexpectedEof, "length {}", len);
+        }
+        let mut stream = io::BufReader::with_capacity(1, &input[..info_len]);
+        let op = decode(&mut stream, None).unwrap().unwrap();
+        assert_eq!(format!("{:?}", op), first);
+        assert!(decode(&mut stream, None).unwrap().is_none());
+
+        let msg = b"MSG foo 1 5\r\nhello\r\n";
+        for len in 1..msg.len() {
+            let stream = io::BufReader::with_capacity(1, &msg[..len]);
+            let err = decode(stream, None).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::UnexpectedEof, "length {}", len);
+        }
+    }
 }
@@ -0,0 +1,60 @@
+// Copyright 2020-2021 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io;
+
+use crate::Connection;
+
+/// A handle to a connection that can only publish messages, for components
+/// that should not be able to subscribe or make requests.
+///
+/// The connection stays open as long as a publisher for it exists.
+///
+/// # Example
+/// ```
+/// # fn main() -> std::io::Result<()> {
+/// # let nc = nats::connect("demo.nats.io")?;
+/// let publisher = nc.publisher();
+/// std::thread::spawn(move || {
+///     publisher.publish("metrics", "cpu=0.5").unwrap();
+/// });
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Publisher {
+    nc: Connection,
+}
+
+impl Publisher {
+    pub(crate) fn new(nc: Connection) -> Publisher {
+        Publisher { nc }
+    }
+
+    /// Publish a message on the given subject, like `Connection::publish`.
+    pub fn publish(&self, subject: &str, msg: impl AsRef<[u8]>) -> io::Result<()> {
+        self.nc.publish(subject, msg)
+    }
+
+    /// Attempts to publish a message without blocking, returning `None` if
+    /// the write buffer does not have enough space for it.
+    pub fn try_publish(&self, subject: &str, msg: impl AsRef<[u8]>) -> Option<io::Result<()>> {
+        self.nc
+            .try_publish_with_reply_or_headers(subject, None, None, msg)
+    }
+
+    /// Flush the connection, like `Connection::flush`.
+    pub fn flush(&self) -> io::Result<()> {
+        self.nc.flush()
+    }
+}
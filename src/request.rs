@@ -0,0 +1,263 @@
+// Copyright 2020-2021 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::{self, Error, ErrorKind};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crossbeam_channel as channel;
+use parking_lot::Mutex;
+
+use crate::headers::STATUS_HEADER;
+use crate::{client, Subscription};
+
+/// A request that has been published but whose reply has not been collected
+/// yet, as returned by `Connection::request_async`.
+///
+/// The reply inbox is unsubscribed once the reply has been received or the
+/// token is dropped.
+#[derive(Debug)]
+pub struct RequestToken {
+    /// Subscription to the reply inbox, taken once the reply is received.
+    sub: Option<Subscription>,
+}
+
+impl RequestToken {
+    pub(crate) fn new(sub: Subscription) -> RequestToken {
+        RequestToken { sub: Some(sub) }
+    }
+
+    /// Waits for the reply, failing with `TimedOut` if none is received
+    /// within the timeout and with `NotFound` if there were no responders.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// # nc.subscribe("foo")?.with_handler(move |m| { m.respond("ans=42")?; Ok(()) });
+    /// let token = nc.request_async("foo", "Help me?")?;
+    /// let resp = token.wait(std::time::Duration::from_secs(2))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn wait(mut self, timeout: Duration) -> io::Result<client::Message> {
+        match self.sub.take() {
+            Some(sub) => check_no_responders(sub.next_timeout(timeout)?),
+            None => Err(already_received()),
+        }
+    }
+
+    /// Returns the reply if it has already been received, without blocking.
+    ///
+    /// Returns `None` if the reply has not arrived yet. Once a reply has been
+    /// returned, subsequent calls fail with `InvalidInput`.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// # nc.subscribe("foo")?.with_handler(move |m| { m.respond("ans=42")?; Ok(()) });
+    /// let mut token = nc.request_async("foo", "Help me?")?;
+    /// let resp = loop {
+    ///     if let Some(resp) = token.try_recv() {
+    ///         break resp?;
+    ///     }
+    ///     // Do other work while waiting for the response.
+    /// };
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn try_recv(&mut self) -> Option<io::Result<client::Message>> {
+        let msg = match self.sub.as_ref() {
+            Some(sub) => sub.try_next()?,
+            None => return Some(Err(already_received())),
+        };
+
+        // Unsubscribe from the reply inbox.
+        self.sub = None;
+
+        Some(check_no_responders(msg))
+    }
+}
+
+/// An iterator over the replies to a request, as returned by
+/// `Connection::request_iter`.
+///
+/// Each call to `next` blocks until the next reply arrives. Iteration ends
+/// once no reply has been received within the timeout, and stops after
+/// yielding an error. The reply inbox is unsubscribed when the iterator is
+/// dropped.
+#[derive(Debug)]
+pub struct RequestIter {
+    /// Subscription to the reply inbox, dropped once iteration ends.
+    sub: Option<Subscription>,
+
+    /// How long to wait for each reply.
+    timeout: Duration,
+}
+
+impl RequestIter {
+    pub(crate) fn new(sub: Subscription, timeout: Duration) -> RequestIter {
+        RequestIter {
+            sub: Some(sub),
+            timeout,
+        }
+    }
+}
+
+impl Iterator for RequestIter {
+    type Item = io::Result<client::Message>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self
+            .sub
+            .as_ref()?
+            .next_timeout(self.timeout)
+            .and_then(check_no_responders);
+        match result {
+            Ok(msg) => Some(Ok(msg)),
+            Err(err) if err.kind() == ErrorKind::TimedOut => {
+                self.sub = None;
+                None
+            }
+            Err(err) => {
+                self.sub = None;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// A live subscription primed with a snapshot of the current state, as
+/// returned by `Connection::subscribe_with_backlog_replay`.
+///
+/// Iterating yields the snapshot first, if there was one, followed by the
+/// updates received on the subscription. Updates published while the
+/// snapshot was being fetched are buffered and replayed after it, so none is
+/// missed. Such updates may already be reflected in the snapshot, so
+/// applying them must be idempotent, as it is for last-value caches.
+#[derive(Debug)]
+pub struct BacklogReplay {
+    /// The snapshot, taken once it has been yielded.
+    snapshot: Option<client::Message>,
+
+    /// The subscription to live updates.
+    sub: Subscription,
+}
+
+impl BacklogReplay {
+    pub(crate) fn new(snapshot: client::Message, sub: Subscription) -> BacklogReplay {
+        // A 404 status means that there is no current state.
+        let status = snapshot
+            .headers
+            .as_ref()
+            .and_then(|headers| headers.get(STATUS_HEADER));
+        let not_found = matches!(status, Some(status) if status.contains("404"));
+        BacklogReplay {
+            snapshot: if not_found { None } else { Some(snapshot) },
+            sub,
+        }
+    }
+
+    /// Returns the snapshot if it has not been yielded yet.
+    pub fn snapshot(&self) -> Option<&client::Message> {
+        self.snapshot.as_ref()
+    }
+
+    /// Returns the subscription to live updates.
+    pub fn subscription(&self) -> &Subscription {
+        &self.sub
+    }
+
+    /// Returns the snapshot if it has not been yielded yet, or waits up to
+    /// `timeout` for the next update, failing with `TimedOut` if none
+    /// arrives in time.
+    pub fn next_timeout(&mut self, timeout: Duration) -> io::Result<client::Message> {
+        match self.snapshot.take() {
+            Some(snapshot) => Ok(snapshot),
+            None => self.sub.next_timeout(timeout),
+        }
+    }
+}
+
+impl Iterator for BacklogReplay {
+    type Item = client::Message;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.snapshot.take().or_else(|| self.sub.next())
+    }
+}
+
+/// Cancels the requests it is passed to, as used by
+/// `Connection::request_with_cancel`.
+///
+/// Clones share their state, so cancelling one clone cancels every request
+/// made with any of them, for example when shutting down.
+#[derive(Debug, Clone)]
+pub struct CancelToken {
+    /// Dropped when cancelled, which disconnects `cancelled`.
+    sender: Arc<Mutex<Option<channel::Sender<()>>>>,
+    cancelled: channel::Receiver<()>,
+}
+
+impl CancelToken {
+    /// Creates a token that has not been cancelled.
+    pub fn new() -> CancelToken {
+        let (sender, cancelled) = channel::bounded(0);
+        CancelToken {
+            sender: Arc::new(Mutex::new(Some(sender))),
+            cancelled,
+        }
+    }
+
+    /// Cancels the requests waiting on this token and any made with it
+    /// later.
+    ///
+    /// # Example
+    /// ```
+    /// let token = nats::CancelToken::new();
+    /// token.clone().cancel();
+    /// assert!(token.is_cancelled());
+    /// ```
+    pub fn cancel(&self) {
+        self.sender.lock().take();
+    }
+
+    /// Returns `true` if the token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.sender.lock().is_none()
+    }
+
+    /// Returns a channel that is disconnected once the token is cancelled.
+    pub(crate) fn receiver(&self) -> &channel::Receiver<()> {
+        &self.cancelled
+    }
+}
+
+impl Default for CancelToken {
+    fn default() -> CancelToken {
+        CancelToken::new()
+    }
+}
+
+fn already_received() -> Error {
+    Error::new(ErrorKind::InvalidInput, "the reply was already received")
+}
+
+fn check_no_responders(msg: client::Message) -> io::Result<client::Message> {
+    if msg.is_no_responders() && msg.client.no_responders_enabled() {
+        Err(Error::new(ErrorKind::NotFound, "no responders"))
+    } else {
+        Ok(msg)
+    }
+}
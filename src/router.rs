@@ -0,0 +1,223 @@
+// Copyright 2020-2021 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Error, ErrorKind};
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+
+use crate::subscription::Handler;
+use crate::{client, Connection};
+
+type RouteFn = dyn Fn(&client::Message) -> io::Result<()> + Send + Sync + 'static;
+
+/// Routes incoming messages to handlers registered for subject patterns,
+/// which may contain the `*` and `>` wildcards.
+///
+/// A message is passed to every handler whose pattern matches its subject.
+/// Messages are fed into the router by subscriptions created with
+/// `Router::subscribe`, or by calling `Router::dispatch` directly. Routes can
+/// be added while the router is already receiving messages.
+///
+/// # Example
+/// ```
+/// # fn main() -> std::io::Result<()> {
+/// # let nc = nats::connect("demo.nats.io")?;
+/// let router = nats::Router::new();
+/// router.route("orders.*.created", |msg| {
+///     println!("created: {}", msg);
+///     Ok(())
+/// })?;
+/// router.route("orders.>", |msg| {
+///     println!("any order event: {}", msg);
+///     Ok(())
+/// })?;
+/// let handler = router.subscribe(&nc, "orders.>")?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct Router {
+    routes: Arc<RwLock<Trie<Arc<RouteFn>>>>,
+}
+
+impl fmt::Debug for Router {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        f.debug_struct("Router")
+            .field("routes", &self.routes.read().len())
+            .finish()
+    }
+}
+
+impl Router {
+    /// Creates a router without any routes.
+    pub fn new() -> Router {
+        Router::default()
+    }
+
+    /// Registers a handler for messages whose subject matches the pattern.
+    ///
+    /// Fails with `InvalidInput` if the pattern is empty, contains whitespace
+    /// or empty tokens, or has a `>` token that is not the last one.
+    pub fn route<F>(&self, pattern: &str, handler: F) -> io::Result<()>
+    where
+        F: Fn(&client::Message) -> io::Result<()> + Send + Sync + 'static,
+    {
+        let tokens: Vec<&str> = pattern.split('.').collect();
+        if pattern.chars().any(char::is_whitespace)
+            || tokens.iter().any(|token| token.is_empty())
+            || tokens[..tokens.len() - 1].contains(&">")
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid subject pattern: {:?}", pattern),
+            ));
+        }
+
+        self.routes.write().insert(pattern, Arc::new(handler));
+        Ok(())
+    }
+
+    /// Passes the message to every handler whose pattern matches its subject
+    /// and returns how many handlers were called.
+    ///
+    /// Stops at and returns the first error returned by a handler.
+    pub fn dispatch(&self, msg: &client::Message) -> io::Result<usize> {
+        // Release the lock before calling the handlers so that they can
+        // register new routes.
+        let handlers = self.routes.read().matches(&msg.subject);
+        for handler in &handlers {
+            handler(msg)?;
+        }
+        Ok(handlers.len())
+    }
+
+    /// Subscribes to the subject and dispatches the received messages to the
+    /// routes on a separate thread, logging errors returned by handlers.
+    ///
+    /// The subject should cover the registered patterns. Messages that match
+    /// no route are dropped. Subscribing the same router to overlapping
+    /// subjects delivers the shared messages to the handlers more than once.
+    pub fn subscribe(&self, nc: &Connection, subject: &str) -> io::Result<Handler> {
+        let router = self.clone();
+        Ok(nc
+            .subscribe(subject)?
+            .with_handler(move |msg| router.dispatch(&msg).map(drop)))
+    }
+}
+
+/// A trie of subject tokens with values stored at the end of each pattern.
+#[derive(Debug)]
+struct Trie<T> {
+    children: HashMap<String, Trie<T>>,
+    values: Vec<T>,
+}
+
+impl<T> Default for Trie<T> {
+    fn default() -> Trie<T> {
+        Trie {
+            children: HashMap::new(),
+            values: Vec::new(),
+        }
+    }
+}
+
+impl<T: Clone> Trie<T> {
+    /// Adds a value for the pattern.
+    fn insert(&mut self, pattern: &str, value: T) {
+        let node = pattern.split('.').fold(self, |node, token| {
+            node.children.entry(token.to_string()).or_default()
+        });
+        node.values.push(value);
+    }
+
+    /// Returns the number of stored values.
+    fn len(&self) -> usize {
+        self.values.len() + self.children.values().map(Trie::len).sum::<usize>()
+    }
+
+    /// Returns the values of all patterns that match the subject.
+    fn matches(&self, subject: &str) -> Vec<T> {
+        let tokens: Vec<&str> = subject.split('.').collect();
+        let mut values = Vec::new();
+        self.collect(&tokens, &mut values);
+        values
+    }
+
+    fn collect(&self, tokens: &[&str], values: &mut Vec<T>) {
+        let (token, rest) = if let Some(split) = tokens.split_first() {
+            split
+        } else {
+            values.extend(self.values.iter().cloned());
+            return;
+        };
+
+        if let Some(node) = self.children.get(*token) {
+            node.collect(rest, values);
+        }
+        if let Some(node) = self.children.get("*") {
+            node.collect(rest, values);
+        }
+        if let Some(node) = self.children.get(">") {
+            values.extend(node.values.iter().cloned());
+        }
+    }
+}
+
+#[cfg(test)]
+mod trie {
+    use super::*;
+
+    fn matches(trie: &Trie<&'static str>, subject: &str) -> Vec<&'static str> {
+        let mut values = trie.matches(subject);
+        values.sort_unstable();
+        values
+    }
+
+    #[test]
+    fn literal() {
+        let mut trie = Trie::default();
+        trie.insert("foo.bar", "foo.bar");
+
+        assert_eq!(matches(&trie, "foo.bar"), vec!["foo.bar"]);
+        assert!(matches(&trie, "foo").is_empty());
+        assert!(matches(&trie, "foo.bar.baz").is_empty());
+        assert!(matches(&trie, "foo.baz").is_empty());
+    }
+
+    #[test]
+    fn wildcards() {
+        let mut trie = Trie::default();
+        trie.insert("foo.*", "foo.*");
+        trie.insert("foo.>", "foo.>");
+        trie.insert("*.bar.*", "*.bar.*");
+        trie.insert(">", ">");
+
+        assert_eq!(matches(&trie, "foo"), vec![">"]);
+        assert_eq!(matches(&trie, "foo.bar"), vec![">", "foo.*", "foo.>"]);
+        assert_eq!(matches(&trie, "foo.bar.baz"), vec!["*.bar.*", ">", "foo.>"]);
+        assert_eq!(matches(&trie, "qux.bar.baz"), vec!["*.bar.*", ">"]);
+        assert_eq!(trie.len(), 4);
+    }
+
+    #[test]
+    fn duplicate_patterns() {
+        let mut trie = Trie::default();
+        trie.insert("foo.*", "first");
+        trie.insert("foo.*", "second");
+
+        assert_eq!(matches(&trie, "foo.bar"), vec!["first", "second"]);
+    }
+}
@@ -0,0 +1,349 @@
+// Copyright 2020-2021 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::prelude::*;
+use std::io::{self, Error, ErrorKind};
+use std::time::Duration;
+
+use crossbeam_channel as channel;
+
+use crate::client::Client;
+use crate::Headers;
+
+/// Number of payload chunks that may be buffered for a reader before the
+/// client thread waits for it to catch up.
+const CHUNK_BACKLOG: usize = 16;
+
+/// How long the client thread waits for a reader to make room for the next
+/// chunk before it discards the rest of the payload.
+pub(crate) const CHUNK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A message whose payload is read from the connection while it is being
+/// consumed, instead of being loaded into memory first.
+///
+/// While the payload is streamed, no other operations are received on the
+/// connection, so it should be read to the end or dropped promptly.
+/// Dropping the message or its reader discards the rest of the payload, as
+/// does falling behind by more than a few seconds.
+#[derive(Debug)]
+pub struct StreamingMessage {
+    /// The subject this message came from.
+    pub subject: String,
+
+    /// Optional reply subject that may be used for sending a response to
+    /// this message.
+    pub reply: Option<String>,
+
+    /// Optional headers associated with this message.
+    pub headers: Option<Headers>,
+
+    /// The length of the payload in bytes.
+    pub len: usize,
+
+    reader: PayloadReader,
+}
+
+impl StreamingMessage {
+    /// Returns a reader over the payload.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// use std::io::Read;
+    ///
+    /// let sub = nc.subscribe_streaming("backups")?;
+    /// if let Some(msg) = sub.next() {
+    ///     let mut file = std::fs::File::create("backup.tar")?;
+    ///     std::io::copy(&mut msg.into_reader(), &mut file)?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn into_reader(self) -> PayloadReader {
+        self.reader
+    }
+}
+
+/// Reads the payload of a `StreamingMessage` as it arrives from the server.
+///
+/// Fails with `UnexpectedEof` if the connection is lost before the whole
+/// payload was received, or if the rest of the payload was discarded because
+/// the reader fell behind.
+#[derive(Debug)]
+pub struct PayloadReader {
+    chunks: channel::Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    pos: usize,
+    remaining: usize,
+}
+
+impl Read for PayloadReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.chunk.len() {
+            if self.remaining == 0 {
+                return Ok(0);
+            }
+            self.chunk = self.chunks.recv().map_err(|_| {
+                Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "payload cut short by a lost connection or a stalled reader",
+                )
+            })?;
+            self.pos = 0;
+            self.remaining -= self.chunk.len();
+        }
+
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Passes the payload of a streamed message from the stream to the
+/// subscription in chunks, followed by consuming the trailing `\r\n`.
+///
+/// The payload is skipped if the subscription is gone, and the rest of it
+/// once the reader has been dropped or has not made room for a chunk within
+/// `timeout`.
+pub(crate) fn forward(
+    mut stream: impl BufRead,
+    messages: Option<channel::Sender<StreamingMessage>>,
+    subject: String,
+    reply: Option<String>,
+    headers: Option<Headers>,
+    len: usize,
+    timeout: Duration,
+) -> io::Result<()> {
+    let (sender, chunks) = channel::bounded(CHUNK_BACKLOG);
+    let msg = StreamingMessage {
+        subject,
+        reply,
+        headers,
+        len,
+        reader: PayloadReader {
+            chunks,
+            chunk: Vec::new(),
+            pos: 0,
+            remaining: len,
+        },
+    };
+    let mut sender = messages
+        .and_then(|messages| messages.try_send(msg).ok())
+        .map(|_| sender);
+
+    let mut remaining = len;
+    while remaining > 0 {
+        let available = stream.fill_buf()?;
+        if available.is_empty() {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "connection closed while streaming a payload",
+            ));
+        }
+        let n = available.len().min(remaining);
+        if let Some(reader) = sender.as_ref() {
+            if reader
+                .send_timeout(available[..n].to_vec(), timeout)
+                .is_err()
+            {
+                log::debug!("discarding the rest of a streamed payload");
+                sender = None;
+            }
+        }
+        stream.consume(n);
+        remaining -= n;
+    }
+
+    // Read "\r\n".
+    stream.read_exact(&mut [0_u8; 2])
+}
+
+/// A subscription whose messages are delivered as `StreamingMessage`s.
+///
+/// Created with `Connection::subscribe_streaming`. Dropping it unsubscribes.
+#[derive(Debug)]
+pub struct StreamingSubscription {
+    sid: u64,
+    messages: channel::Receiver<StreamingMessage>,
+    client: Client,
+}
+
+impl StreamingSubscription {
+    pub(crate) fn new(
+        sid: u64,
+        messages: channel::Receiver<StreamingMessage>,
+        client: Client,
+    ) -> StreamingSubscription {
+        StreamingSubscription {
+            sid,
+            messages,
+            client,
+        }
+    }
+
+    /// Get the next message, or None if the subscription has been
+    /// unsubscribed or the connection closed.
+    pub fn next(&self) -> Option<StreamingMessage> {
+        self.messages.recv().ok()
+    }
+
+    /// Get the next message, or a timeout error if no messages are
+    /// available for timeout.
+    pub fn next_timeout(&self, timeout: Duration) -> io::Result<StreamingMessage> {
+        match self.messages.recv_timeout(timeout) {
+            Ok(msg) => Ok(msg),
+            Err(channel::RecvTimeoutError::Timeout) => {
+                Err(Error::new(ErrorKind::TimedOut, "next_timeout: timed out"))
+            }
+            Err(channel::RecvTimeoutError::Disconnected) => {
+                Err(Error::new(ErrorKind::Other, "next_timeout: unsubscribed"))
+            }
+        }
+    }
+
+    /// Unsubscribes, discarding the messages that have not been received
+    /// yet.
+    pub fn unsubscribe(self) -> io::Result<()> {
+        self.client.unsubscribe(self.sid)
+    }
+}
+
+impl Drop for StreamingSubscription {
+    fn drop(&mut self) {
+        self.client.unsubscribe(self.sid).ok();
+    }
+}
+
+#[cfg(test)]
+mod forward {
+    use super::*;
+
+    #[test]
+    fn streams_payload_in_chunks() {
+        let payload: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+        let mut wire = payload.clone();
+        wire.extend_from_slice(b"\r\nPING\r\n");
+        let mut stream = io::BufReader::with_capacity(1024, &wire[..]);
+
+        let (messages, received) = channel::unbounded();
+        let reader = std::thread::spawn(move || {
+            let msg: StreamingMessage = received.recv().unwrap();
+            assert_eq!(msg.subject, "foo");
+            assert_eq!(msg.len, 100_000);
+            let mut data = Vec::new();
+            msg.into_reader().read_to_end(&mut data).unwrap();
+            data
+        });
+
+        forward(
+            &mut stream,
+            Some(messages),
+            "foo".into(),
+            None,
+            None,
+            100_000,
+            CHUNK_TIMEOUT,
+        )
+        .unwrap();
+        assert_eq!(reader.join().unwrap(), payload);
+
+        let mut rest = String::new();
+        stream.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "PING\r\n");
+    }
+
+    #[test]
+    fn skips_payload_without_receiver() {
+        let wire = [vec![7; 10_000], b"\r\nPONG\r\n".to_vec()].concat();
+        let mut stream = io::BufReader::with_capacity(64, &wire[..]);
+
+        let (messages, received) = channel::unbounded();
+        drop(received);
+        forward(
+            &mut stream,
+            Some(messages),
+            "foo".into(),
+            None,
+            None,
+            10_000,
+            CHUNK_TIMEOUT,
+        )
+        .unwrap();
+
+        let mut rest = String::new();
+        stream.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "PONG\r\n");
+    }
+
+    #[test]
+    fn truncated_payload() {
+        let wire = vec![7; 10];
+        let (messages, received) = channel::unbounded();
+        let err = forward(
+            &wire[..],
+            Some(messages),
+            "foo".into(),
+            None,
+            None,
+            100,
+            CHUNK_TIMEOUT,
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+
+        let mut data = Vec::new();
+        let err = received
+            .recv()
+            .unwrap()
+            .into_reader()
+            .read_to_end(&mut data)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(data, vec![7; 10]);
+    }
+
+    #[test]
+    fn discards_payload_when_reader_stalls() {
+        let wire = [vec![7; 10_000], b"\r\nPONG\r\n".to_vec()].concat();
+        let mut stream = io::BufReader::with_capacity(64, &wire[..]);
+
+        // The message is received, but its payload is never read.
+        let (messages, received) = channel::unbounded();
+        forward(
+            &mut stream,
+            Some(messages),
+            "foo".into(),
+            None,
+            None,
+            10_000,
+            Duration::from_millis(50),
+        )
+        .unwrap();
+
+        let mut rest = String::new();
+        stream.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "PONG\r\n");
+
+        let mut data = Vec::new();
+        let err = received
+            .recv()
+            .unwrap()
+            .into_reader()
+            .read_to_end(&mut data)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(data, vec![7; 64 * CHUNK_BACKLOG]);
+    }
+}
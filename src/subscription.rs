@@ -11,7 +11,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::any::Any;
 use std::io;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
@@ -19,6 +21,7 @@ use std::time::Duration;
 use crossbeam_channel as channel;
 
 use crate::client::{self, Client};
+use crate::Reassembler;
 
 #[derive(Debug)]
 struct Inner {
@@ -217,6 +220,24 @@ impl Subscription {
         }
     }
 
+    /// Turns this subscription into a `Reassembler`, which yields data
+    /// published with `Connection::publish_chunked` as single messages.
+    ///
+    /// # Example
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # let nc = nats::connect("demo.nats.io")?;
+    /// let mut blobs = nc.subscribe("blobs")?.reassemble();
+    /// nc.publish_chunked("blobs", "hello", 2)?;
+    /// let blob = blobs.next_timeout(std::time::Duration::from_secs(5))?;
+    /// assert_eq!(blob.data, b"hello");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn reassemble(self) -> Reassembler {
+        Reassembler::new(self)
+    }
+
     /// Attach a closure to handle messages. This closure will execute in a
     /// separate thread. The result of this call is a `Handler` which can
     /// not be iterated and must be unsubscribed or closed directly to
@@ -255,6 +276,35 @@ impl Subscription {
         Handler { sub: self }
     }
 
+    /// Attach a closure to handle messages like `with_handler`, but pass the
+    /// errors it returns to the error callback of the connection. Panics in
+    /// the closure are caught and reported the same way, and the following
+    /// messages are still handled.
+    pub(crate) fn with_reporting_handler<F>(self, handler: F) -> Handler
+    where
+        F: Fn(client::Message) -> io::Result<()> + Send + 'static,
+    {
+        let client = self.0.client.clone();
+        let subject = self.0.subject.clone();
+        self.with_handler(move |msg| {
+            let res =
+                panic::catch_unwind(AssertUnwindSafe(|| handler(msg))).unwrap_or_else(|payload| {
+                    Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        format!(
+                            "handler for subscription to {:?} panicked: {}",
+                            subject,
+                            panic_message(&payload)
+                        ),
+                    ))
+                });
+            if let Err(err) = res {
+                client.report_error(err);
+            }
+            Ok(())
+        })
+    }
+
     /// Unsubscribe a subscription immediately without draining.
     /// Use `drain` instead if you want any pending messages
     /// to be processed by a handler, if one is configured.
@@ -338,12 +388,26 @@ impl Subscription {
     }
 }
 
-impl IntoIterator for Subscription {
+/// Iterating over a `Subscription` blocks for each message and ends once
+/// the subscription has been unsubscribed or the connection closed. Owned
+/// subscriptions can be used in `for` loops and iterator chains directly,
+/// and unsubscribe when the last clone is dropped.
+///
+/// # Example
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// # let nc = nats::connect("demo.nats.io")?;
+/// for msg in nc.subscribe("foo")?.take(10) {
+///     println!("Received {}", msg);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+impl Iterator for Subscription {
     type Item = client::Message;
-    type IntoIter = IntoIter;
 
-    fn into_iter(self) -> IntoIter {
-        IntoIter { subscription: self }
+    fn next(&mut self) -> Option<client::Message> {
+        self.0.messages.recv().ok()
     }
 }
 
@@ -356,6 +420,17 @@ impl<'a> IntoIterator for &'a Subscription {
     }
 }
 
+/// Returns the message a panic was started with, if it has one.
+fn panic_message(payload: &Box<dyn Any + Send>) -> &str {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg
+    } else {
+        "unknown panic payload"
+    }
+}
+
 /// A `Handler` may be used to unsubscribe a handler thread.
 pub struct Handler {
     sub: Subscription,
@@ -405,18 +480,6 @@ impl<'a> Iterator for Iter<'a> {
     }
 }
 
-/// An iterator over messages from a `Subscription`
-pub struct IntoIter {
-    subscription: Subscription,
-}
-
-impl Iterator for IntoIter {
-    type Item = client::Message;
-    fn next(&mut self) -> Option<Self::Item> {
-        self.subscription.next()
-    }
-}
-
 /// An iterator over messages from a `Subscription`
 /// where `None` will be returned if a new `Message`
 /// has not been received by the end of a timeout.
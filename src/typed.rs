@@ -0,0 +1,106 @@
+// Copyright 2020-2021 The NATS Authors
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt;
+use std::io::{self, Error, ErrorKind};
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+
+use crate::Subscription;
+
+/// A subscription that deserializes the payloads of its messages from JSON.
+///
+/// Messages whose payload is not valid JSON for `T` yield an `InvalidData`
+/// error instead of being dropped.
+///
+/// # Example
+/// ```
+/// # fn main() -> std::io::Result<()> {
+/// # let nc = nats::connect("demo.nats.io")?;
+/// let mut points = nc.subscribe_json::<(i32, i32)>("points")?;
+/// nc.publish_json("points", &(3, 4))?;
+/// assert_eq!(points.next().unwrap()?, (3, 4));
+/// # Ok(())
+/// # }
+/// ```
+pub struct JsonSubscription<T> {
+    sub: Subscription,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> fmt::Debug for JsonSubscription<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        f.debug_struct("JsonSubscription")
+            .field("sub", &self.sub)
+            .finish()
+    }
+}
+
+impl<T: DeserializeOwned> JsonSubscription<T> {
+    pub(crate) fn new(sub: Subscription) -> JsonSubscription<T> {
+        JsonSubscription {
+            sub,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the underlying subscription, for example to unsubscribe.
+    pub fn subscription(&self) -> &Subscription {
+        &self.sub
+    }
+
+    /// Waits up to `timeout` for the next message and deserializes it,
+    /// failing with `TimedOut` if none arrives in time.
+    pub fn next_timeout(&self, timeout: Duration) -> io::Result<T> {
+        decode(&self.sub.next_timeout(timeout)?.data)
+    }
+
+    /// Deserializes the next message if one is immediately available.
+    pub fn try_next(&self) -> Option<io::Result<T>> {
+        self.sub.try_next().map(|msg| decode(&msg.data))
+    }
+}
+
+impl<T: DeserializeOwned> Iterator for JsonSubscription<T> {
+    type Item = io::Result<T>;
+
+    /// Blocks until the next message arrives and deserializes it. Ends when
+    /// the subscription is closed.
+    fn next(&mut self) -> Option<Self::Item> {
+        self.sub.next().map(|msg| decode(&msg.data))
+    }
+}
+
+/// Deserializes a message payload from JSON. Every failure, including a
+/// truncated payload, is reported as `InvalidData`.
+fn decode<T: DeserializeOwned>(data: &[u8]) -> io::Result<T> {
+    serde_json::from_slice(data).map_err(|err| Error::new(ErrorKind::InvalidData, err))
+}
+
+#[cfg(test)]
+mod decode {
+    use super::*;
+
+    #[test]
+    fn valid_and_malformed() {
+        let point: (i32, i32) = decode(b"[3,4]").unwrap();
+        assert_eq!(point, (3, 4));
+
+        let err = decode::<(i32, i32)>(b"[3,").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = decode::<(i32, i32)>(b"\"point\"").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -60,3 +60,46 @@ fn sub_perms() {
     let r = drx.recv_timeout(Duration::from_millis(100));
     assert!(r.is_err(), "we got disconnected on perm violation");
 }
+
+#[test]
+fn oversized_message() {
+    let s = util::run_basic_server();
+
+    let (etx, erx) = bounded(1);
+
+    let nc = nats::Options::new()
+        .max_incoming_message_size(16)
+        .error_callback(move |err| etx.send(err).unwrap())
+        .connect(&s.client_url())
+        .expect("could not connect");
+
+    let sub = nc.subscribe("foo").unwrap();
+    nc.publish("foo", vec![0; 1024]).unwrap();
+    nc.publish("foo", "small").unwrap();
+
+    let r = erx.recv_timeout(Duration::from_secs(1));
+    assert!(r.is_ok(), "expected an error callback, got none");
+    assert_eq!(r.unwrap().kind(), std::io::ErrorKind::InvalidData);
+
+    let msg = sub.next_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(msg.data, b"small");
+}
+
+#[test]
+fn sub_perms_check() {
+    let s = util::run_server("tests/configs/perms.conf");
+
+    let nc = nats::Options::with_user_pass("derek", "s3cr3t!")
+        .connect(&s.client_url())
+        .expect("could not connect");
+
+    let err = nc
+        .subscribe_with_permissions_check("foo", None)
+        .expect_err("expected the subscription to be denied");
+    assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+
+    let err = nc
+        .subscribe_sync("foo", None)
+        .expect_err("expected the subscription to be denied");
+    assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+}
@@ -30,3 +30,19 @@ fn basic_tls() -> io::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn tls_client_cert_in_memory() -> io::Result<()> {
+    let s = util::run_server("tests/configs/tls.conf");
+
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let cert = std::fs::read(path.join("tests/configs/certs/client-cert.pem"))?;
+    let key = std::fs::read(path.join("tests/configs/certs/client-key.pem"))?;
+
+    nats::Options::with_user_pass("derek", "porkchop")
+        .add_root_certificate(path.join("tests/configs/certs/rootCA.pem"))
+        .client_cert_pem(cert, key)
+        .connect(&s.client_url())?;
+
+    Ok(())
+}
@@ -0,0 +1,59 @@
+use std::time::Duration;
+
+mod util;
+
+#[test]
+fn snapshot_then_updates() {
+    let s = util::run_basic_server();
+    let nc = nats::connect(&s.client_url()).unwrap();
+
+    // Publish an update while answering the snapshot request, which must be
+    // replayed after the snapshot.
+    let updater = nc.clone();
+    let _responder = nc.subscribe("state.get").unwrap().with_handler(move |msg| {
+        updater.publish("state.updates", "v2")?;
+        msg.respond("v1")
+    });
+
+    let mut cache = nc
+        .subscribe_with_backlog_replay("state.updates", "state.get", "", Duration::from_secs(2))
+        .unwrap();
+    assert_eq!(cache.snapshot().unwrap().data, b"v1");
+
+    nc.publish("state.updates", "v3").unwrap();
+
+    let timeout = Duration::from_secs(2);
+    assert_eq!(cache.next_timeout(timeout).unwrap().data, b"v1");
+    assert_eq!(cache.next_timeout(timeout).unwrap().data, b"v2");
+    assert_eq!(cache.next_timeout(timeout).unwrap().data, b"v3");
+}
+
+#[test]
+fn missing_snapshot() {
+    let s = util::run_basic_server();
+    let nc = nats::connect(&s.client_url()).unwrap();
+
+    let responder = nc.clone();
+    let _responder = nc.subscribe("state.get").unwrap().with_handler(move |msg| {
+        let headers = vec![("Status".to_string(), "404".to_string())]
+            .into_iter()
+            .collect();
+        responder.publish_with_reply_or_headers(
+            msg.reply.as_ref().unwrap(),
+            None,
+            Some(&headers),
+            "",
+        )
+    });
+
+    let mut cache = nc
+        .subscribe_with_backlog_replay("state.updates", "state.get", "", Duration::from_secs(2))
+        .unwrap();
+    assert!(cache.snapshot().is_none());
+
+    nc.publish("state.updates", "v1").unwrap();
+    assert_eq!(
+        cache.next_timeout(Duration::from_secs(2)).unwrap().data,
+        b"v1"
+    );
+}
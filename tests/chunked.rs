@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+mod util;
+
+#[test]
+fn chunked_roundtrip() {
+    let s = util::run_basic_server();
+    let nc = nats::connect(&s.client_url()).unwrap();
+
+    let mut blobs = nc.subscribe("blobs").unwrap().reassemble();
+
+    // Larger than the default max_payload of 1MB.
+    let blob: Vec<u8> = (0..3 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let chunks = nc.publish_chunked("blobs", &blob, 256 * 1024).unwrap();
+    assert_eq!(chunks, 12);
+    nc.publish("blobs", "plain").unwrap();
+
+    let msg = blobs.next_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(msg.data, blob);
+    assert!(msg.headers.is_none());
+
+    let msg = blobs.next_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(msg.data, b"plain");
+    assert_eq!(blobs.pending(), 0);
+}
+
+#[test]
+fn chunked_empty_and_capped() {
+    let s = util::run_basic_server();
+    let nc = nats::connect(&s.client_url()).unwrap();
+
+    let mut blobs = nc.subscribe("blobs").unwrap().reassemble();
+
+    assert_eq!(nc.publish_chunked("blobs", "", 16).unwrap(), 1);
+    let msg = blobs.next_timeout(Duration::from_secs(5)).unwrap();
+    assert!(msg.data.is_empty());
+
+    // A chunk size above max_payload is capped to fit.
+    let blob = vec![7; nc.max_payload() + 1];
+    assert_eq!(nc.publish_chunked("blobs", &blob, usize::MAX).unwrap(), 2);
+    let msg = blobs.next_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(msg.data, blob);
+
+    let err = nc.publish_chunked("blobs", "data", 0).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn chunked_discards_oldest_pending_transfer() {
+    let s = util::run_basic_server();
+    let nc = nats::connect(&s.client_url()).unwrap();
+
+    let mut blobs = nc.subscribe("blobs").unwrap().reassemble().max_pending(2);
+
+    let chunk = |id: &str, index: usize| {
+        let headers: nats::Headers = vec![
+            ("Nats-Chunk-Id".to_string(), id.to_string()),
+            ("Nats-Chunk-Index".to_string(), index.to_string()),
+            ("Nats-Chunk-Total".to_string(), "2".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        nc.publish_with_reply_or_headers("blobs", None, Some(&headers), id)
+            .unwrap();
+    };
+
+    // The third transfer pushes out the first one, whose second chunk then
+    // starts a new incomplete transfer and pushes out the second one.
+    chunk("a", 0);
+    chunk("b", 0);
+    chunk("c", 0);
+    chunk("a", 1);
+    chunk("c", 1);
+    nc.publish("blobs", "plain").unwrap();
+
+    let msg = blobs.next_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(msg.data, b"cc");
+    let msg = blobs.next_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(msg.data, b"plain");
+    assert_eq!(blobs.pending(), 1);
+}
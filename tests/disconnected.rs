@@ -0,0 +1,68 @@
+use std::{io, thread, time::Duration};
+
+mod util;
+
+/// Publishes until the client notices the server is gone, returning the
+/// first error.
+fn publish_until_err(nc: &nats::Connection) -> io::Error {
+    for _ in 0..50 {
+        if let Err(err) = nc.publish("foo", "bar") {
+            return err;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    panic!("publishing kept succeeding while disconnected");
+}
+
+#[test]
+fn zero_reconnect_buffer_rejects_publishes() {
+    let s = util::run_basic_server();
+    let nc = nats::Options::new()
+        .reconnect_buffer_size(0)
+        .connect(&s.client_url())
+        .unwrap();
+
+    nc.publish("foo", "bar").unwrap();
+    nc.flush().unwrap();
+
+    drop(s);
+
+    let err = publish_until_err(&nc);
+    assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+}
+
+#[test]
+fn fail_when_disconnected_rejects_publishes() {
+    let s = util::run_basic_server();
+    let nc = nats::Options::new()
+        .fail_when_disconnected()
+        .connect(&s.client_url())
+        .unwrap();
+
+    nc.publish("foo", "bar").unwrap();
+    nc.flush().unwrap();
+
+    drop(s);
+
+    let err = publish_until_err(&nc);
+    assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+}
+
+#[test]
+fn publish_if_connected_does_not_buffer() {
+    let s = util::run_basic_server();
+    let nc = nats::connect(&s.client_url()).unwrap();
+
+    assert!(nc.publish_if_connected("foo", None, None, "bar").unwrap());
+    nc.flush().unwrap();
+
+    drop(s);
+
+    for _ in 0..50 {
+        if !nc.publish_if_connected("foo", None, None, "bar").unwrap() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    panic!("publish_if_connected kept publishing while disconnected");
+}
@@ -0,0 +1,58 @@
+//! Counts the allocations made by `Connection::flush`.
+//!
+//! This installs a counting global allocator, so it needs a test binary of
+//! its own.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::io;
+
+use nats_test_server::NatsTestServer;
+
+thread_local! {
+    /// Allocations made by the current thread.
+    static ALLOCATIONS: Cell<usize> = Cell::new(0);
+}
+
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // The counter is gone while the thread is being torn down.
+        ALLOCATIONS.try_with(|n| n.set(n.get() + 1)).ok();
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(Cell::get)
+}
+
+#[test]
+fn flush_does_not_allocate_per_call() -> io::Result<()> {
+    let server = NatsTestServer::build().spawn();
+    let nc = nats::connect(&server.address().to_string())?;
+
+    // The first flush allocates the PONG slot that later flushes reuse.
+    nc.flush()?;
+
+    let before = allocations();
+    for _ in 0..1000 {
+        nc.flush()?;
+    }
+    let allocated = allocations() - before;
+    assert!(
+        allocated < 10,
+        "1000 flushes made {} allocations",
+        allocated
+    );
+
+    Ok(())
+}
@@ -406,3 +406,57 @@ fn jetstream_libdoc_test() {
     let flipped: std::io::Result<Vec<usize>> = results.into_iter().collect();
     let _sizes: Vec<usize> = flipped.unwrap();
 }
+
+#[test]
+fn jetstream_ack_sync() {
+    let (_s, nc, js) = run_basic_jetstream();
+
+    js.add_stream("ack_sync").unwrap();
+    nc.publish("ack_sync", "1").unwrap();
+
+    let mut consumer = js.create_or_bind("ack_sync", "ack_sync_consumer").unwrap();
+    let msg = consumer.pull().unwrap();
+    msg.ack_sync(Duration::from_secs(5)).unwrap();
+
+    let info = js.consumer_info("ack_sync", "ack_sync_consumer").unwrap();
+    assert_eq!(info.ack_floor.consumer_seq, 1);
+}
+
+#[test]
+fn double_ack_gives_up_after_max_attempts() {
+    let s = run_basic_server();
+    let nc = nats::connect(&s.client_url()).unwrap();
+
+    // Absorb the acks without ever confirming them.
+    let _acks = nc.subscribe("ack.target").unwrap();
+    let sub = nc.subscribe("work").unwrap();
+    nc.publish_request("work", "ack.target", "1").unwrap();
+    let msg = sub.next_timeout(Duration::from_secs(5)).unwrap();
+
+    let options = AckOptions {
+        attempt_timeout: Duration::from_millis(50),
+        max_attempts: Some(2),
+        ..Default::default()
+    };
+    let err = msg
+        .double_ack_with_options(AckKind::Ack, options)
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+}
+
+#[test]
+fn jetstream_ack_with_wrong_token() {
+    let (_s, nc, js) = run_basic_jetstream();
+
+    js.add_stream("ack_token").unwrap();
+    nc.publish("ack_token", "1").unwrap();
+
+    let mut consumer = js
+        .create_or_bind("ack_token", "ack_token_consumer")
+        .unwrap();
+    let msg = consumer.pull().unwrap();
+    assert!(msg.is_jetstream());
+
+    let err = msg.ack_kind_with_token(AckKind::Ack, "bogus").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
@@ -0,0 +1,35 @@
+#![cfg(feature = "json")]
+
+use std::io;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+mod util;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Point {
+    x: i32,
+    y: i32,
+}
+
+#[test]
+fn json_roundtrip() {
+    let s = util::run_basic_server();
+    let nc = nats::connect(&s.client_url()).unwrap();
+
+    let points = nc.subscribe_json::<Point>("points").unwrap();
+    nc.publish_json("points", &Point { x: 3, y: 4 }).unwrap();
+    nc.publish("points", "not json").unwrap();
+    nc.publish_json("points", &Point { x: 5, y: 6 }).unwrap();
+
+    let point = points.next_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(point, Point { x: 3, y: 4 });
+
+    // Malformed messages are reported instead of being dropped.
+    let err = points.next_timeout(Duration::from_secs(5)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    let point = points.next_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(point, Point { x: 5, y: 6 });
+}
@@ -8,3 +8,32 @@ fn no_responders() {
     let nc = nats::connect(&s.client_url()).expect("could not connect");
     nc.request("nobody-home", "hello").unwrap();
 }
+
+#[test]
+fn no_responders_disabled() {
+    let s = util::run_basic_server();
+    let nc = nats::Options::new()
+        .no_responders(false)
+        .connect(&s.client_url())
+        .expect("could not connect");
+    let err = nc
+        .request_timeout(
+            "nobody-home",
+            "hello",
+            std::time::Duration::from_millis(200),
+        )
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+}
+
+#[test]
+fn request_iter_no_responders() {
+    let s = util::run_basic_server();
+    let nc = nats::connect(&s.client_url()).expect("could not connect");
+    let mut replies = nc
+        .request_iter("nobody-home", "hello", std::time::Duration::from_secs(1))
+        .unwrap();
+    let err = replies.next().unwrap().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    assert!(replies.next().is_none());
+}
@@ -0,0 +1,28 @@
+use std::time::Duration;
+
+mod util;
+
+#[test]
+fn publisher_outlives_connection() {
+    let s = util::run_basic_server();
+    let nc = nats::connect(&s.client_url()).unwrap();
+    let sub = nc.subscribe("metrics").unwrap();
+
+    let publisher = nc.publisher();
+    publisher.publish("metrics", "cpu=0.5").unwrap();
+    publisher
+        .try_publish("metrics", "cpu=0.6")
+        .unwrap()
+        .unwrap();
+    publisher.flush().unwrap();
+
+    // The publisher keeps the connection open.
+    drop(nc);
+    publisher.publish("metrics", "cpu=0.7").unwrap();
+    publisher.flush().unwrap();
+
+    for expected in &["cpu=0.5", "cpu=0.6", "cpu=0.7"] {
+        let msg = sub.next_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(msg.data, expected.as_bytes());
+    }
+}